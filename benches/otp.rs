@@ -0,0 +1,172 @@
+//! Code generation with the precomputed HMAC key state, against the allocating HMAC it replaced
+//!
+//! `cargo bench --bench otp`
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use hmac::{Hmac, Mac};
+use otpc::otp::{Algorithm, Otp, OtpType};
+use sha2::Sha256;
+
+// RFC 6238 Appendix B seeds, Base32 and raw
+const SHA1_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+const SHA256_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA";
+const SHA1_KEY: &[u8] = b"12345678901234567890";
+const SHA256_KEY: &[u8] = b"12345678901234567890123456789012";
+
+fn otp(algorithm: Algorithm) -> Otp {
+    let secret = match algorithm {
+        Algorithm::Sha1 => SHA1_SECRET,
+        _ => SHA256_SECRET,
+    };
+    Otp::new(secret, 6, 30, OtpType::Hotp).unwrap().with_algorithm(algorithm)
+}
+
+fn key(algorithm: Algorithm) -> &'static [u8] {
+    match algorithm {
+        Algorithm::Sha1 => SHA1_KEY,
+        _ => SHA256_KEY,
+    }
+}
+
+/// The code generation `Otp` used before the key state was precomputed: SHA1 pads a copy of
+/// every message, HMAC-SHA1 pads the key and allocates its inner and outer messages, and the
+/// SHA-2 HMACs hash the key blocks again for each code
+mod reference {
+    use super::*;
+
+    const BLOCK_SIZE: usize = 64;
+    const SHA1_SIZE: usize = 20;
+
+    pub fn generate_hotp(key: &[u8], algorithm: Algorithm, counter: u64, digits: u32) -> String {
+        let hmac = match algorithm {
+            Algorithm::Sha1 => hmac_sha1(key, &counter.to_be_bytes()).to_vec(),
+            _ => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+                mac.update(&counter.to_be_bytes());
+                mac.finalize().into_bytes().to_vec()
+            }
+        };
+
+        let offset = (hmac[hmac.len() - 1] & 0xf) as usize;
+        let binary = ((hmac[offset] & 0x7f) as u32) << 24
+            | (hmac[offset + 1] as u32) << 16
+            | (hmac[offset + 2] as u32) << 8
+            | (hmac[offset + 3] as u32);
+        format!("{:0width$}", binary % 10u32.pow(digits), width = digits as usize)
+    }
+
+    fn hmac_sha1(secret: &[u8], data: &[u8]) -> [u8; SHA1_SIZE] {
+        let mut key = secret.to_vec();
+        if key.len() > BLOCK_SIZE {
+            key = sha1(&key).to_vec();
+        }
+        key.resize(BLOCK_SIZE, 0);
+
+        let mut ipad = [0x36u8; BLOCK_SIZE];
+        let mut opad = [0x5cu8; BLOCK_SIZE];
+        for i in 0..BLOCK_SIZE {
+            ipad[i] ^= key[i];
+            opad[i] ^= key[i];
+        }
+
+        let mut inner_data = Vec::with_capacity(BLOCK_SIZE + data.len());
+        inner_data.extend_from_slice(&ipad);
+        inner_data.extend_from_slice(data);
+        let inner_hash = sha1(&inner_data);
+
+        let mut outer_data = Vec::with_capacity(BLOCK_SIZE + SHA1_SIZE);
+        outer_data.extend_from_slice(&opad);
+        outer_data.extend_from_slice(&inner_hash);
+        sha1(&outer_data)
+    }
+
+    fn sha1(data: &[u8]) -> [u8; SHA1_SIZE] {
+        let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+        let mut padded_data = data.to_vec();
+        padded_data.push(0x80);
+        while padded_data.len() % 64 != 56 {
+            padded_data.push(0);
+        }
+        padded_data.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+        for chunk in padded_data.chunks(64) {
+            let mut w = [0u32; 80];
+            for i in 0..16 {
+                w[i] = u32::from_be_bytes([chunk[i * 4], chunk[i * 4 + 1], chunk[i * 4 + 2], chunk[i * 4 + 3]]);
+            }
+            for i in 16..80 {
+                w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+            }
+
+            let [mut a, mut b, mut c, mut d, mut e] = h;
+            for (i, word) in w.iter().enumerate() {
+                let (f, k) = match i {
+                    0..=19 => ((b & c) | ((!b) & d), 0x5A827999),
+                    20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                    40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                    _ => (b ^ c ^ d, 0xCA62C1D6),
+                };
+                let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+                e = d;
+                d = c;
+                c = b.rotate_left(30);
+                b = a;
+                a = temp;
+            }
+            for (state, value) in h.iter_mut().zip([a, b, c, d, e]) {
+                *state = state.wrapping_add(value);
+            }
+        }
+
+        let mut result = [0u8; SHA1_SIZE];
+        for (bytes, state) in result.chunks_mut(4).zip(h) {
+            bytes.copy_from_slice(&state.to_be_bytes());
+        }
+        result
+    }
+}
+
+fn single_code(c: &mut Criterion) {
+    let mut group = c.benchmark_group("single_code");
+    for algorithm in [Algorithm::Sha1, Algorithm::Sha256] {
+        let reused = otp(algorithm);
+        assert_eq!(reused.generate_hotp(1), reference::generate_hotp(key(algorithm), algorithm, 1, 6));
+
+        group.bench_function(BenchmarkId::new("precomputed", algorithm.as_str()), |b| {
+            b.iter(|| reused.generate_hotp(black_box(1)))
+        });
+        group.bench_function(BenchmarkId::new("allocating", algorithm.as_str()), |b| {
+            b.iter(|| reference::generate_hotp(key(algorithm), algorithm, black_box(1), 6))
+        });
+    }
+    group.finish();
+}
+
+fn verification_window(c: &mut Criterion) {
+    let mut group = c.benchmark_group("window");
+    let reused = otp(Algorithm::Sha1);
+    for n in [3, 21, 201] {
+        let expected: Vec<String> = (1_000..1_000 + n as u64)
+            .map(|counter| reference::generate_hotp(SHA1_KEY, Algorithm::Sha1, counter, 6))
+            .collect();
+        assert_eq!(reused.generate_range(1_000, n), expected);
+
+        group.bench_with_input(BenchmarkId::new("generate_range", n), &n, |b, &n| {
+            b.iter(|| reused.generate_range(black_box(1_000), n))
+        });
+        group.bench_with_input(BenchmarkId::new("allocating", n), &n, |b, &n| {
+            b.iter(|| {
+                (1_000..1_000 + n as u64)
+                    .map(|counter| reference::generate_hotp(SHA1_KEY, Algorithm::Sha1, counter, 6))
+                    .collect::<Vec<_>>()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, single_code, verification_window);
+criterion_main!(benches);
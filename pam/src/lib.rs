@@ -0,0 +1,244 @@
+//! PAM module asking for a one-time code and checking it against the user's otpc store
+//!
+//! `auth required pam_otpc.so [store=<path>] [account=<name>] [window=<periods>]`
+//!
+//! The return codes below are Linux-PAM's; OpenPAM numbers them differently.
+#![cfg(target_os = "linux")]
+
+use std::ffi::{CStr, OsStr};
+use std::os::raw::{c_char, c_int, c_void};
+use std::os::unix::ffi::OsStrExt as _;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::ptr;
+
+use otpc::pam::{self, Options, Outcome};
+use zeroize::{Zeroize as _, Zeroizing};
+
+const PAM_SUCCESS: c_int = 0;
+const PAM_SERVICE_ERR: c_int = 3;
+const PAM_SYSTEM_ERR: c_int = 4;
+const PAM_AUTH_ERR: c_int = 7;
+const PAM_AUTHINFO_UNAVAIL: c_int = 9;
+const PAM_USER_UNKNOWN: c_int = 10;
+const PAM_CONV_ERR: c_int = 19;
+
+/// `pam_get_item` type of the application's conversation function
+const PAM_CONV: c_int = 5;
+const PAM_PROMPT_ECHO_OFF: c_int = 1;
+
+const PROMPT: &CStr = c"Verification code: ";
+
+// A panic must come back as an error code: aborting would take sudo or login down with it
+#[cfg(panic = "abort")]
+compile_error!("pam_otpc must unwind on panic, build it with `cargo build --profile pam -p pam_otpc`");
+
+/// Opaque `pam_handle_t`
+#[repr(C)]
+pub struct PamHandle {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct PamMessage {
+    msg_style: c_int,
+    msg: *const c_char,
+}
+
+#[repr(C)]
+struct PamResponse {
+    resp: *mut c_char,
+    resp_retcode: c_int,
+}
+
+type ConvFn = unsafe extern "C" fn(c_int, *mut *const PamMessage, *mut *mut PamResponse, *mut c_void) -> c_int;
+
+#[repr(C)]
+struct PamConv {
+    conv: Option<ConvFn>,
+    appdata_ptr: *mut c_void,
+}
+
+// Linked by soname, so building needs no libpam development symlink
+#[link(name = "libpam.so.0", kind = "dylib", modifiers = "+verbatim")]
+extern "C" {
+    fn pam_get_user(pamh: *mut PamHandle, user: *mut *const c_char, prompt: *const c_char) -> c_int;
+    fn pam_get_item(pamh: *const PamHandle, item_type: c_int, item: *mut *const c_void) -> c_int;
+}
+
+/// Ask for a code and accept it when it matches the user's store
+///
+/// # Safety
+///
+/// Called by libpam with a valid handle and `argc` NUL-terminated arguments in `argv`.
+#[no_mangle]
+pub unsafe extern "C" fn pam_sm_authenticate(
+    pamh: *mut PamHandle,
+    _flags: c_int,
+    argc: c_int,
+    argv: *const *const c_char,
+) -> c_int {
+    // SAFETY: the caller's guarantees are passed on unchanged
+    guard(|| unsafe { authenticate(pamh, argc, argv) })
+}
+
+/// Nothing to set: a code grants no credentials
+///
+/// # Safety
+///
+/// Never dereferences its arguments.
+#[no_mangle]
+pub unsafe extern "C" fn pam_sm_setcred(
+    _pamh: *mut PamHandle,
+    _flags: c_int,
+    _argc: c_int,
+    _argv: *const *const c_char,
+) -> c_int {
+    guard(|| PAM_SUCCESS)
+}
+
+/// Run an entry point, turning a panic into `PAM_SYSTEM_ERR` instead of letting it unwind into libpam
+fn guard(entry: impl FnOnce() -> c_int) -> c_int {
+    panic::catch_unwind(AssertUnwindSafe(entry)).unwrap_or_else(|payload| {
+        let reason = payload.downcast_ref::<&str>().copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown cause");
+        log(libc::LOG_ERR, &format!("Internal error: {}", reason));
+        PAM_SYSTEM_ERR
+    })
+}
+
+unsafe fn authenticate(pamh: *mut PamHandle, argc: c_int, argv: *const *const c_char) -> c_int {
+    // SAFETY: libpam hands over `argc` valid C strings
+    let args = unsafe { arguments(argc, argv) };
+    let options = match Options::parse(args.iter().map(String::as_str)) {
+        Ok(options) => options,
+        Err(e) => {
+            log(libc::LOG_ERR, &e.to_string());
+            return PAM_SERVICE_ERR;
+        }
+    };
+
+    // SAFETY: `pamh` is the handle libpam called us with
+    let Some(user) = (unsafe { user_name(pamh) }) else {
+        return PAM_USER_UNKNOWN;
+    };
+    let Some((uid, gid, home)) = lookup(&user) else {
+        return PAM_USER_UNKNOWN;
+    };
+    let path = match options.store_path(&user, &home) {
+        Ok(path) => path,
+        Err(e) => {
+            log(libc::LOG_ERR, &e.to_string());
+            return PAM_SERVICE_ERR;
+        }
+    };
+    // Everything under the user's home is touched as the user, so a symlink there cannot reach root's files
+    if let Err(e) = pam::drop_privileges(uid, gid).and_then(|_privileges| pam::check_permissions(&path, uid)) {
+        log(libc::LOG_ERR, &format!("Cannot use the store of {}: {}", user, e));
+        return PAM_AUTHINFO_UNAVAIL;
+    }
+
+    // SAFETY: as above; the application's conversation runs with its own privileges
+    let Some(code) = (unsafe { ask(pamh, PROMPT) }) else {
+        return PAM_CONV_ERR;
+    };
+    let result = pam::drop_privileges(uid, gid).and_then(|_privileges| pam::authenticate(&options, &path, &code, pam::unix_time()));
+    if let Ok((_, Some(upgrade))) = &result {
+        log(libc::LOG_NOTICE, &upgrade.to_string());
+    }
+    match result.map(|(outcome, _)| outcome) {
+        Ok(Outcome::Accepted) => PAM_SUCCESS,
+        Ok(outcome) => {
+            log(libc::LOG_NOTICE, &format!("Refused the code of {}: {}", user, outcome));
+            PAM_AUTH_ERR
+        }
+        Err(e) => {
+            log(libc::LOG_ERR, &format!("Cannot verify the code of {}: {}", user, e));
+            PAM_AUTHINFO_UNAVAIL
+        }
+    }
+}
+
+unsafe fn arguments(argc: c_int, argv: *const *const c_char) -> Vec<String> {
+    if argv.is_null() {
+        return Vec::new();
+    }
+    (0..argc.max(0) as usize)
+        // SAFETY: the caller guarantees `argc` entries, each a C string
+        .map(|i| unsafe { CStr::from_ptr(*argv.add(i)) }.to_string_lossy().into_owned())
+        .collect()
+}
+
+unsafe fn user_name(pamh: *mut PamHandle) -> Option<String> {
+    let mut user: *const c_char = ptr::null();
+    // SAFETY: libpam keeps the returned string alive with the handle
+    if unsafe { pam_get_user(pamh, &mut user, ptr::null()) } != PAM_SUCCESS || user.is_null() {
+        return None;
+    }
+    // SAFETY: checked for NULL above
+    Some(unsafe { CStr::from_ptr(user) }.to_string_lossy().into_owned())
+}
+
+/// Uid, primary gid and home directory from the password database
+fn lookup(user: &str) -> Option<(u32, u32, PathBuf)> {
+    let name = std::ffi::CString::new(user).ok()?;
+    let mut buffer = vec![0 as c_char; 16 * 1024];
+    // SAFETY: `passwd` is plain data, filled in by getpwnam_r
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = ptr::null_mut();
+    // SAFETY: every pointer is valid for the call, and the strings in `entry` point into `buffer`
+    let status = unsafe { libc::getpwnam_r(name.as_ptr(), &mut entry, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+    if status != 0 || result.is_null() || entry.pw_dir.is_null() {
+        return None;
+    }
+    // SAFETY: `pw_dir` is a C string inside `buffer`, which is still alive
+    let home = unsafe { CStr::from_ptr(entry.pw_dir) };
+    Some((entry.pw_uid, entry.pw_gid, PathBuf::from(OsStr::from_bytes(home.to_bytes()))))
+}
+
+/// Ask the application for a hidden answer through its conversation function
+unsafe fn ask(pamh: *mut PamHandle, prompt: &CStr) -> Option<Zeroizing<String>> {
+    let mut item: *const c_void = ptr::null();
+    // SAFETY: PAM_CONV items are `struct pam_conv`, owned by the application for the whole transaction
+    if unsafe { pam_get_item(pamh, PAM_CONV, &mut item) } != PAM_SUCCESS || item.is_null() {
+        return None;
+    }
+    // SAFETY: checked for NULL above
+    let conv = unsafe { &*(item as *const PamConv) };
+    let function = conv.conv?;
+
+    let message = PamMessage { msg_style: PAM_PROMPT_ECHO_OFF, msg: prompt.as_ptr() };
+    let mut messages = [&message as *const PamMessage];
+    let mut responses: *mut PamResponse = ptr::null_mut();
+    // SAFETY: one message in, and the application allocates one response for us to free
+    let status = unsafe { function(1, messages.as_mut_ptr(), &mut responses, conv.appdata_ptr) };
+    if status != PAM_SUCCESS || responses.is_null() {
+        return None;
+    }
+
+    // SAFETY: the response and its string were allocated with malloc and now belong to us
+    unsafe {
+        let answer = (*responses).resp;
+        let code = match answer.is_null() {
+            true => None,
+            false => {
+                let bytes = std::slice::from_raw_parts_mut(answer as *mut u8, libc::strlen(answer));
+                let code = Zeroizing::new(String::from_utf8_lossy(bytes).into_owned());
+                bytes.zeroize();
+                libc::free(answer as *mut c_void);
+                Some(code)
+            }
+        };
+        libc::free(responses as *mut c_void);
+        code
+    }
+}
+
+fn log(priority: c_int, message: &str) {
+    let Ok(message) = std::ffi::CString::new(format!("pam_otpc: {}", message)) else {
+        return;
+    };
+    // SAFETY: both strings are NUL-terminated and the format consumes exactly one argument
+    unsafe { libc::syslog(libc::LOG_AUTHPRIV | priority, c"%s".as_ptr(), message.as_ptr()) };
+}
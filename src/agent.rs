@@ -0,0 +1,455 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::crypto::VaultKey;
+use crate::secret::SecretString;
+
+/// Environment variable holding the agent socket path
+pub(crate) const SOCKET_ENV: &str = "OTPC_AGENT_SOCK";
+
+/// How long a client waits for the agent before giving up
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum AgentRequest {
+    /// Fetch the key cached for the store with this salt
+    Get { salt: String },
+    /// Cache a key for the store with this salt
+    Add { salt: String, key: SecretString },
+    /// Forget every cached key
+    Lock,
+    /// Report the agent state
+    Status,
+    /// Shut the agent down
+    Stop,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct AgentResponse {
+    ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<SecretString>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    keys: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    locks_in: Option<u64>,
+}
+
+impl AgentResponse {
+    fn ok() -> Self {
+        Self { ok: true, ..Default::default() }
+    }
+
+    fn error(message: &str) -> Self {
+        Self { ok: false, error: Some(message.to_string()), ..Default::default() }
+    }
+}
+
+/// Keys held by a running agent
+struct AgentState {
+    keys: HashMap<String, VaultKey>,
+    idle_timeout: Duration,
+    last_used: Instant,
+}
+
+impl AgentState {
+    fn new(idle_timeout: Duration) -> Self {
+        Self { keys: HashMap::new(), idle_timeout, last_used: Instant::now() }
+    }
+
+    /// Handle a request, returning the response and whether the agent should stop
+    fn handle(&mut self, request: AgentRequest) -> (AgentResponse, bool) {
+        self.expire();
+
+        match request {
+            AgentRequest::Get { salt } => match self.keys.get(&salt) {
+                Some(key) => {
+                    self.last_used = Instant::now();
+                    let response = AgentResponse { key: Some(BASE64.encode(key.as_bytes()).into()), ..AgentResponse::ok() };
+                    (response, false)
+                }
+                None => (AgentResponse::error("Agent is locked"), false),
+            },
+            AgentRequest::Add { salt, key } => {
+                let decoded = BASE64.decode(key.expose()).map(Zeroizing::new);
+                match decoded.map_err(anyhow::Error::from).and_then(|bytes| VaultKey::from_bytes(&bytes)) {
+                    Ok(key) => {
+                        self.keys.insert(salt, key);
+                        self.last_used = Instant::now();
+                        (AgentResponse::ok(), false)
+                    }
+                    Err(_) => (AgentResponse::error("Invalid key"), false),
+                }
+            }
+            AgentRequest::Lock => {
+                self.keys.clear();
+                (AgentResponse::ok(), false)
+            }
+            AgentRequest::Status => {
+                let locks_in = (!self.keys.is_empty())
+                    .then(|| self.idle_timeout.saturating_sub(self.last_used.elapsed()).as_secs());
+                let response = AgentResponse { keys: Some(self.keys.len()), locks_in, ..AgentResponse::ok() };
+                (response, false)
+            }
+            AgentRequest::Stop => {
+                self.keys.clear();
+                (AgentResponse::ok(), true)
+            }
+        }
+    }
+
+    /// Drop every key once the agent has been idle for too long
+    fn expire(&mut self) {
+        if !self.keys.is_empty() && self.last_used.elapsed() >= self.idle_timeout {
+            self.keys.clear();
+        }
+    }
+}
+
+/// Path of the agent socket: `OTPC_AGENT_SOCK`, or a per-user default
+pub(crate) fn socket_path() -> Result<PathBuf> {
+    if let Some(path) = env::var_os(SOCKET_ENV).filter(|path| !path.is_empty()) {
+        return Ok(PathBuf::from(path));
+    }
+    default_socket_path()
+}
+
+#[cfg(unix)]
+fn default_socket_path() -> Result<PathBuf> {
+    // SAFETY: getuid has no preconditions and cannot fail
+    let uid = unsafe { libc::getuid() };
+    let dir = match env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir).join("otpc"),
+        None => env::temp_dir().join(format!("otpc-{}", uid)),
+    };
+    Ok(dir.join("agent.sock"))
+}
+
+#[cfg(not(unix))]
+fn default_socket_path() -> Result<PathBuf> {
+    Err(anyhow::anyhow!("The otpc agent is only supported on Unix"))
+}
+
+/// Ask a running agent for the key of the store with this salt
+pub(crate) fn request_key(salt: &str) -> Option<VaultKey> {
+    let response = send(&AgentRequest::Get { salt: salt.to_string() }).ok()?;
+    let bytes = Zeroizing::new(BASE64.decode(response.key?.expose()).ok()?);
+    VaultKey::from_bytes(&bytes).ok()
+}
+
+/// Hand a freshly unlocked key to a running agent, if there is one
+pub(crate) fn offer_key(salt: &str, key: &VaultKey) {
+    let request = AgentRequest::Add { salt: salt.to_string(), key: BASE64.encode(key.as_bytes()).into() };
+    // No agent running is the common case and not an error
+    let _ = send(&request);
+}
+
+#[cfg(unix)]
+fn send(request: &AgentRequest) -> Result<AgentResponse> {
+    use std::io::{BufRead as _, BufReader, Write as _};
+    use std::os::unix::net::UnixStream;
+
+    let mut stream = UnixStream::connect(socket_path()?)?;
+    // Keys only ever go to, or come from, an agent run by the same user
+    check_peer(&stream)?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let mut line = Zeroizing::new(serde_json::to_string(request)?);
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut reply = Zeroizing::new(String::new());
+    BufReader::new(stream).read_line(&mut reply)?;
+    let response: AgentResponse = serde_json::from_str(&reply)?;
+    match (&response.ok, &response.error) {
+        (false, Some(error)) => Err(anyhow::anyhow!("{}", error)),
+        _ => Ok(response),
+    }
+}
+
+/// Refuse a socket whose other end belongs to another user
+#[cfg(unix)]
+fn check_peer(stream: &std::os::unix::net::UnixStream) -> Result<()> {
+    // SAFETY: getuid has no preconditions and cannot fail
+    let uid = unsafe { libc::getuid() };
+    let peer = peer_uid(stream)?;
+    if peer != uid {
+        return Err(anyhow::anyhow!("Agent socket peer is uid {}, not {}", peer, uid));
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &std::os::unix::net::UnixStream) -> Result<libc::uid_t> {
+    use std::os::unix::io::AsRawFd as _;
+
+    // SAFETY: `ucred` is plain data, filled in by getsockopt
+    let mut credentials: libc::ucred = unsafe { std::mem::zeroed() };
+    let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: the buffer and its length match SO_PEERCRED's `struct ucred`
+    let status = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut credentials as *mut libc::ucred as *mut libc::c_void,
+            &mut length,
+        )
+    };
+    if status != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(credentials.uid)
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+fn peer_uid(stream: &std::os::unix::net::UnixStream) -> Result<libc::uid_t> {
+    use std::os::unix::io::AsRawFd as _;
+
+    let (mut uid, mut gid) = (0, 0);
+    // SAFETY: both out-pointers are valid for the call
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(uid)
+}
+
+/// Refuse a socket directory that is a symlink, belongs to another user or is open to anyone else
+#[cfg(unix)]
+fn check_socket_dir(dir: &std::path::Path) -> Result<()> {
+    use std::os::unix::fs::MetadataExt as _;
+
+    let meta = std::fs::symlink_metadata(dir)?;
+    // SAFETY: getuid has no preconditions and cannot fail
+    let uid = unsafe { libc::getuid() };
+    if !meta.is_dir() || meta.uid() != uid || meta.mode() & 0o777 != 0o700 {
+        return Err(anyhow::anyhow!(
+            "{} must be a directory owned by uid {} with mode 700 to hold the agent socket",
+            dir.display(),
+            uid
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send(_request: &AgentRequest) -> Result<AgentResponse> {
+    Err(anyhow::anyhow!("The otpc agent is only supported on Unix"))
+}
+
+/// Start the agent in the background and print shell commands exporting its socket
+#[cfg(unix)]
+pub(crate) fn start(idle_timeout: u64) -> Result<()> {
+    use std::os::unix::process::CommandExt as _;
+    use std::process::{Command, Stdio};
+
+    let path = socket_path()?;
+    if send(&AgentRequest::Status).is_err() {
+        let child = Command::new(env::current_exe()?)
+            .args(["agent", "run", "--timeout", &idle_timeout.to_string()])
+            .env(SOCKET_ENV, &path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            // Detach from the terminal so closing the shell does not kill the agent
+            .process_group(0)
+            .spawn()?;
+
+        let deadline = Instant::now() + CLIENT_TIMEOUT;
+        while send(&AgentRequest::Status).is_err() {
+            if Instant::now() > deadline {
+                return Err(anyhow::anyhow!("Agent did not start"));
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        println!("{}={}; export {};", SOCKET_ENV, path.display(), SOCKET_ENV);
+        println!("echo Agent pid {};", child.id());
+    } else {
+        println!("{}={}; export {};", SOCKET_ENV, path.display(), SOCKET_ENV);
+    }
+    Ok(())
+}
+
+/// Run the agent in the foreground until it is stopped
+#[cfg(unix)]
+pub(crate) fn run(idle_timeout: u64) -> Result<()> {
+    use std::fs;
+    use std::io::{BufRead as _, BufReader, ErrorKind, Write as _};
+    use std::os::unix::fs::{DirBuilderExt as _, FileTypeExt as _, PermissionsExt as _};
+    use std::os::unix::net::UnixListener;
+
+    let path = socket_path()?;
+    if let Some(dir) = path.parent() {
+        // An existing directory is accepted by `create`, so it might have been planted by another user
+        fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+        check_socket_dir(dir)?;
+    }
+    if let Ok(meta) = fs::symlink_metadata(&path) {
+        if !meta.file_type().is_socket() {
+            return Err(anyhow::anyhow!("{} exists and is not a socket", path.display()));
+        }
+        if send(&AgentRequest::Status).is_ok() {
+            return Err(anyhow::anyhow!("An agent is already listening on {}", path.display()));
+        }
+        fs::remove_file(&path)?;
+    }
+
+    let listener = UnixListener::bind(&path)?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    listener.set_nonblocking(true)?;
+
+    let mut state = AgentState::new(Duration::from_secs(idle_timeout));
+    loop {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                state.expire();
+                std::thread::sleep(Duration::from_millis(200));
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        if check_peer(&stream).is_err() {
+            continue;
+        }
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        let mut line = Zeroizing::new(String::new());
+        if BufReader::new(&stream).read_line(&mut line).is_err() {
+            continue;
+        }
+
+        let (response, stop) = match serde_json::from_str(&line) {
+            Ok(request) => state.handle(request),
+            Err(_) => (AgentResponse::error("Malformed request"), false),
+        };
+        let mut reply = Zeroizing::new(serde_json::to_string(&response)?);
+        reply.push('\n');
+        let _ = (&stream).write_all(reply.as_bytes());
+
+        if stop {
+            break;
+        }
+    }
+
+    fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn start(_idle_timeout: u64) -> Result<()> {
+    Err(anyhow::anyhow!("The otpc agent is only supported on Unix"))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn run(_idle_timeout: u64) -> Result<()> {
+    Err(anyhow::anyhow!("The otpc agent is only supported on Unix"))
+}
+
+/// Forget every key cached by the agent
+pub(crate) fn lock() -> Result<()> {
+    send(&AgentRequest::Lock)?;
+    Ok(())
+}
+
+/// Stop the running agent
+pub(crate) fn stop() -> Result<()> {
+    send(&AgentRequest::Stop)?;
+    Ok(())
+}
+
+/// Print whether the agent is running and holds any keys
+pub(crate) fn status() -> Result<()> {
+    let response = send(&AgentRequest::Status)
+        .map_err(|_| anyhow::anyhow!("No agent running at {}", socket_path().unwrap_or_default().display()))?;
+
+    match (response.keys.unwrap_or(0), response.locks_in) {
+        (0, _) => println!("Agent is running and locked"),
+        (keys, Some(secs)) => println!("Agent is unlocked ({} store(s)), locks in {}s", keys, secs),
+        (keys, None) => println!("Agent is unlocked ({} store(s))", keys),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(state: &mut AgentState, salt: &str, key: &[u8; 32]) -> AgentResponse {
+        let request = AgentRequest::Add { salt: salt.to_string(), key: BASE64.encode(key).into() };
+        state.handle(request).0
+    }
+
+    #[test]
+    fn test_add_get_lock() {
+        let mut state = AgentState::new(Duration::from_secs(60));
+        assert!(add(&mut state, "salt", &[7u8; 32]).ok);
+
+        let (response, _) = state.handle(AgentRequest::Get { salt: "salt".to_string() });
+        assert!(!format!("{:?}", response).contains(&BASE64.encode([7u8; 32])));
+        assert_eq!(BASE64.decode(response.key.unwrap().expose()).unwrap(), vec![7u8; 32]);
+
+        let (response, _) = state.handle(AgentRequest::Get { salt: "other".to_string() });
+        assert!(!response.ok);
+
+        state.handle(AgentRequest::Lock);
+        let (response, _) = state.handle(AgentRequest::Get { salt: "salt".to_string() });
+        assert!(response.key.is_none());
+    }
+
+    #[test]
+    fn test_idle_timeout_expires_keys() {
+        let mut state = AgentState::new(Duration::ZERO);
+        add(&mut state, "salt", &[1u8; 32]);
+
+        let (response, _) = state.handle(AgentRequest::Get { salt: "salt".to_string() });
+        assert!(!response.ok);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_checks_peer_and_socket_dir() {
+        use std::os::unix::fs::PermissionsExt as _;
+        use std::os::unix::net::UnixStream;
+
+        let (client, _server) = UnixStream::pair().unwrap();
+        check_peer(&client).unwrap();
+
+        let dir = env::temp_dir().join(format!("otpc-agent-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700)).unwrap();
+        check_socket_dir(&dir).unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(check_socket_dir(&dir).is_err());
+
+        let link = dir.with_extension("link");
+        let _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink(&dir, &link).unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700)).unwrap();
+        assert!(check_socket_dir(&link).is_err());
+        std::fs::remove_file(&link).unwrap();
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_bad_key_and_stops() {
+        let mut state = AgentState::new(Duration::from_secs(60));
+        let request = AgentRequest::Add { salt: "salt".to_string(), key: BASE64.encode([1u8; 5]).into() };
+        assert!(!state.handle(request).0.ok);
+
+        let (response, stop) = state.handle(AgentRequest::Stop);
+        assert!(response.ok && stop);
+    }
+}
@@ -0,0 +1,336 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read as _, Seek as _, SeekFrom, Write as _};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::{Account, Settings};
+use crate::formats::hex_encode;
+use crate::otp;
+use crate::reconcile::{Change, Outcome};
+
+const LOG_FILE: &str = "audit.log";
+
+/// How far back from the end of the log `last_hash` looks for the last line
+const MAX_TAIL: u64 = 64 * 1024;
+
+/// Years `parse_time` accepts, keeping the calendar arithmetic far from overflow
+const YEARS: RangeInclusive<u64> = 1970..=9999;
+
+/// One line of the audit log; never holds secrets or codes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Entry {
+    /// UTC time, e.g. `2024-05-01T12:30:00Z`
+    pub time: String,
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    pub result: String,
+    /// Hash of the previous chained entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev: Option<String>,
+    /// SHA-256 of this entry with `hash` left out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+impl Entry {
+    fn digest(&self) -> Result<String> {
+        let unhashed = Entry { hash: None, ..self.clone() };
+        Ok(hex_encode(&Sha256::digest(serde_json::to_vec(&unhashed)?)))
+    }
+}
+
+/// Append-only JSON lines log of store changes and code generations
+///
+/// Entries are appended after the change they describe was saved. A failed append fails the command,
+/// but the saved change stands; the log is only ever read from its last line, so a damaged line does
+/// not block later commands and is left for `otpc log verify` to report.
+pub(crate) struct AuditLog {
+    path: PathBuf,
+    hash_chain: bool,
+    code_generation: bool,
+}
+
+impl AuditLog {
+    /// The log kept next to the given config file
+    pub(crate) fn beside(config_path: &Path, settings: &Settings) -> Self {
+        Self {
+            path: config_path.with_file_name(LOG_FILE),
+            hash_chain: settings.audit_hash_chain,
+            code_generation: settings.audit_code_generation,
+        }
+    }
+
+    pub(crate) fn record(&self, command: &str, account: Option<&Account>, result: &str) -> Result<()> {
+        let mut entry = Entry {
+            time: format_time(otp::unix_time()),
+            command: command.to_string(),
+            account_id: account.map(|account| account.id.clone()).filter(|id| !id.is_empty()),
+            account: account.map(|account| account.name.clone()),
+            result: result.to_string(),
+            prev: None,
+            hash: None,
+        };
+
+        let mut options = OpenOptions::new();
+        options.create(true).read(true).append(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt as _;
+            options.mode(0o600).custom_flags(libc::O_NOFOLLOW);
+        }
+        let mut file = options.open(&self.path)?;
+        // Held until the file is closed, so concurrent commands cannot both chain onto the same entry
+        lock(&file)?;
+
+        if self.hash_chain {
+            entry.prev = last_hash(&mut file)?;
+            entry.hash = Some(entry.digest()?);
+        }
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Record a code generation or verification, unless turned off in the settings
+    pub(crate) fn record_code(&self, command: &str, account: &Account, result: &str) -> Result<()> {
+        if !self.code_generation {
+            return Ok(());
+        }
+        self.record(command, Some(account), result)
+    }
+
+    /// Record every change that touched the store
+    pub(crate) fn record_changes(&self, command: &str, accounts: &[Account], changes: &[Change]) -> Result<()> {
+        for change in changes {
+            let (name, result) = match &change.outcome {
+                Outcome::Added => (&change.name, "added"),
+                Outcome::Replaced => (&change.name, "replaced, previous trashed"),
+                Outcome::Renamed(name) => (name, "renamed"),
+                _ => continue,
+            };
+            self.record(command, accounts.iter().find(|account| &account.name == name), result)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn entries(&self) -> Result<Vec<Entry>> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        text.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| serde_json::from_str(line)
+                .map_err(|e| anyhow::anyhow!("Line {} of the audit log is invalid: {}", i + 1, e)))
+            .collect()
+    }
+
+    /// Check the hash chain; returns the number of chained entries
+    ///
+    /// Entries written before the chain was turned on are accepted, anything
+    /// after the first chained entry must be chained too.
+    pub(crate) fn verify(&self) -> Result<usize> {
+        let mut last: Option<String> = None;
+        let mut chained = 0;
+
+        for (i, entry) in self.entries()?.iter().enumerate() {
+            let Some(hash) = &entry.hash else {
+                if last.is_some() {
+                    return Err(anyhow::anyhow!("Entry {} has no hash, the chain is broken", i + 1));
+                }
+                continue;
+            };
+            if entry.prev != last {
+                return Err(anyhow::anyhow!("Entry {} does not follow the previous one, entries were removed or reordered", i + 1));
+            }
+            if entry.digest()? != *hash {
+                return Err(anyhow::anyhow!("Entry {} was modified", i + 1));
+            }
+            last = Some(hash.clone());
+            chained += 1;
+        }
+        Ok(chained)
+    }
+}
+
+/// Hash of the last entry, read backwards from the end of the log
+///
+/// An unchained or unreadable last line starts a new chain; `AuditLog::verify` reports the break.
+fn last_hash(file: &mut File) -> Result<Option<String>> {
+    let end = file.seek(SeekFrom::End(0))?;
+    let start = end.saturating_sub(MAX_TAIL);
+    file.seek(SeekFrom::Start(start))?;
+    let mut tail = Vec::new();
+    file.take(end - start).read_to_end(&mut tail)?;
+
+    let Some(line) = tail.split(|&b| b == b'\n').rev().find(|line| !line.trim_ascii().is_empty()) else {
+        return Ok(None);
+    };
+    Ok(serde_json::from_slice::<Entry>(line).ok().and_then(|entry| entry.hash))
+}
+
+#[cfg(unix)]
+fn lock(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd as _;
+
+    // SAFETY: the descriptor stays open for as long as `file` lives
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn lock(_file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+/// Format a Unix time as `YYYY-MM-DDTHH:MM:SSZ`
+pub(crate) fn format_time(timestamp: u64) -> String {
+    let (days, seconds) = (timestamp / 86400, timestamp % 86400);
+    let (year, month, day) = civil_from_days(days as i64);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, seconds / 3600, seconds % 3600 / 60, seconds % 60,
+    )
+}
+
+/// Parse `YYYY-MM-DD`, `YYYY-MM-DDTHH:MM:SSZ`, or an age such as `30m`, `12h` or `7d`
+pub(crate) fn parse_time(text: &str, now: u64) -> Result<u64> {
+    let text = text.trim();
+    let invalid = || anyhow::anyhow!("Invalid time '{}', use YYYY-MM-DD, YYYY-MM-DDTHH:MM:SSZ or an age like 7d", text);
+
+    if let Some(unit) = text.chars().last().filter(|c| matches!(c, 'm' | 'h' | 'd')) {
+        if let Ok(amount) = text[..text.len() - 1].parse::<u64>() {
+            let seconds = match unit {
+                'm' => 60,
+                'h' => 3600,
+                _ => 86400,
+            };
+            return Ok(now.saturating_sub(amount.saturating_mul(seconds)));
+        }
+    }
+
+    let (date, time) = match text.split_once('T') {
+        Some((date, time)) => (date, Some(time.strip_suffix('Z').ok_or_else(invalid)?)),
+        None => (text, None),
+    };
+    let numbers = |part: &str, separator: char| -> Option<Vec<u64>> {
+        part.split(separator).map(|n| n.parse().ok()).collect()
+    };
+
+    let date = numbers(date, '-').filter(|date| date.len() == 3).ok_or_else(invalid)?;
+    let time = match time {
+        Some(time) => numbers(time, ':').filter(|time| time.len() == 3).ok_or_else(invalid)?,
+        None => vec![0, 0, 0],
+    };
+    if !YEARS.contains(&date[0]) || !(1..=12).contains(&date[1]) || !(1..=31).contains(&date[2]) || time[0] > 23 || time[1] > 59 || time[2] > 59 {
+        return Err(invalid());
+    }
+
+    let days = days_from_civil(date[0] as i64, date[1] as u32, date[2] as u32);
+    let days = u64::try_from(days).map_err(|_| invalid())?;
+    Ok(days * 86400 + time[0] * 3600 + time[1] * 60 + time[2])
+}
+
+// Days since 1970-01-01 to and from the Gregorian calendar, after Howard Hinnant's date algorithms
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = month as i64;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_log(name: &str, hash_chain: bool) -> AuditLog {
+        let dir = std::env::temp_dir().join(format!("otpc-audit-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let _ = fs::remove_file(dir.join(LOG_FILE));
+
+        let settings = Settings { audit_hash_chain: hash_chain, audit_code_generation: false, ..Default::default() };
+        AuditLog::beside(&dir.join("config.toml"), &settings)
+    }
+
+    #[test]
+    fn test_hash_chain() {
+        let log = test_log("chain", true);
+        let mut account = Account::new("alice".to_string(), "JBSWY3DPEHPK3PXP".into(), "Example".to_string());
+        account.id = "1234".to_string();
+
+        log.record("load", Some(&account), "added").unwrap();
+        log.record_code("code", &account, "generated").unwrap();
+        log.record("encrypt", None, "store encrypted").unwrap();
+        assert_eq!(log.verify().unwrap(), 2);
+
+        let text = fs::read_to_string(&log.path).unwrap();
+        assert!(!text.contains("JBSWY3DPEHPK3PXP"));
+        let entries = log.entries().unwrap();
+        assert_eq!((entries[0].account_id.as_deref(), entries[1].account.as_deref()), (Some("1234"), None));
+
+        fs::write(&log.path, text.replace("\"added\"", "\"replaced\"")).unwrap();
+        assert!(log.verify().unwrap_err().to_string().contains("Entry 1 was modified"));
+
+        let lines: Vec<&str> = text.lines().collect();
+        fs::write(&log.path, format!("{}\n", lines[1])).unwrap();
+        assert!(log.verify().is_err());
+
+        // A damaged line is reported by verify but does not stop later entries
+        fs::write(&log.path, format!("{}\n{{broken\n", lines[0])).unwrap();
+        log.record("encrypt", None, "store encrypted").unwrap();
+        assert!(log.verify().is_err());
+        assert_eq!(last_hash(&mut File::open(&log.path).unwrap()).unwrap().map(|hash| hash.len()), Some(64));
+    }
+
+    #[test]
+    fn test_unchained_entries() {
+        let log = test_log("plain", false);
+        log.record("encrypt", None, "store encrypted").unwrap();
+        assert!(log.entries().unwrap()[0].hash.is_none());
+        assert_eq!(log.verify().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_time() {
+        assert_eq!(format_time(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_time(1_709_251_199), "2024-02-29T23:59:59Z");
+        assert_eq!(parse_time("2024-02-29T23:59:59Z", 0).unwrap(), 1_709_251_199);
+        assert_eq!(parse_time("2024-03-01", 0).unwrap(), 1_709_251_200);
+        assert_eq!(parse_time("7d", 10 * 86400).unwrap(), 3 * 86400);
+        assert!(parse_time("2024-13-01", 0).is_err());
+        assert!(parse_time("yesterday", 0).is_err());
+        assert_eq!(parse_time("9999-12-31", 0).unwrap(), 253_402_214_400);
+        assert!(parse_time("1969-12-31", 0).is_err());
+        assert!(parse_time("10000-01-01", 0).is_err());
+        assert!(parse_time("18446744073709551615-01-01", 0).is_err());
+    }
+}
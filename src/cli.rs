@@ -0,0 +1,768 @@
+use std::io::{self, Write as _};
+use std::process;
+use std::sync::MutexGuard;
+use std::thread;
+use std::time::Duration;
+
+use clap::{Parser as _, ValueEnum as _};
+use crate::commands::{AccountFilter, AgentAction, Cli, Command, LogAction, ProfileAction, RecoveryAction, TagAction, TrashAction};
+use anyhow::Result;
+use zeroize::Zeroizing;
+
+use crate::audit::AuditLog;
+
+use crate::config::{Account, Config};
+use crate::formats::table::{Field, FieldMap};
+use crate::formats::{ExportFormat, ImportFormat};
+use crate::reconcile::Resolution;
+use crate::otp::{Otp, OtpType};
+use crate::verify::{Outcome, Policy};
+use crate::{agent, audit, completions, config, docs, doctor, edit, exec, formats, menu, otp, profile, pty, reconcile, recovery, serve, tags, trash, verify, watch};
+
+fn lock_config() -> Result<MutexGuard<'static, Config>> {
+    config::get_config()?
+        .lock()
+        .map_err(|e| anyhow::anyhow!(e.to_string()))
+}
+
+/// The audit log next to the store, following the store's settings
+fn audit_log(config: &Config) -> Result<AuditLog> {
+    Ok(AuditLog::beside(&Config::get_path()?, &config.settings))
+}
+
+/// Move trashed accounts past their retention period out for good
+fn expire_trash(config: &mut Config, now: u64) -> Result<bool> {
+    let expired = trash::expire(config, now);
+    let log = audit_log(config)?;
+    for account in &expired {
+        log.record("trash expire", Some(account), "purged")?;
+    }
+    Ok(!expired.is_empty())
+}
+
+/// One-line summary of an account for `list` and `find`
+fn describe(account: &Account) -> String {
+    let mut line = format!("name: {}, issuer: {}", account.name, account.issuer);
+    if let Some(group) = &account.group {
+        line.push_str(&format!(", group: {}", group));
+    }
+    if !account.tags.is_empty() {
+        line.push_str(&format!(", tags: {}", account.tags.join(", ")));
+    }
+    if account.favorite {
+        line.push_str(", favorite");
+    }
+    line
+}
+
+/// Print the accounts passing the filter and query, from every profile or the active one
+fn print_accounts(filter: &AccountFilter, query: Option<&str>, all_profiles: bool) -> Result<()> {
+    let stores = if all_profiles {
+        profile::names()?
+            .into_iter()
+            .map(|name| Ok((name.clone(), profile::accounts(&name)?)))
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        vec![(profile::active()?, lock_config()?.accounts.clone())]
+    };
+
+    let mut found = 0;
+    let mut listed = Vec::new();
+    for (name, accounts) in &stores {
+        let matches = filter.select(accounts)
+            .into_iter()
+            .filter(|account| query.is_none_or(|query| tags::matches_query(account, query)));
+        for account in matches {
+            found += 1;
+            if all_profiles {
+                println!("{}: {}, profile: {}", found, describe(account), name);
+            } else {
+                println!("{}: {}", found, describe(account));
+            }
+            listed.push(account);
+        }
+    }
+    if found == 0 {
+        println!("No accounts found");
+    }
+    for warning in recovery::low_codes_warnings(listed) {
+        eprintln!("{}", warning);
+    }
+    Ok(())
+}
+
+/// Ask for a new passphrase twice and make sure both entries match
+fn prompt_new_passphrase() -> Result<Zeroizing<String>> {
+    let passphrase = Zeroizing::new(rpassword::prompt_password("New passphrase: ")?);
+    let confirm = Zeroizing::new(rpassword::prompt_password("Confirm passphrase: ")?);
+    if passphrase.is_empty() || passphrase != confirm {
+        return Err(anyhow::anyhow!("Passphrases are empty or do not match"));
+    }
+    Ok(passphrase)
+}
+
+/// Print a question on stderr and read one answer line from stdin
+fn prompt_line(question: &str) -> Result<String> {
+    eprint!("{} ", question);
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().to_lowercase())
+}
+
+/// Code of the account at `index`; generating a HOTP code from the stored counter moves it forward
+fn generate_code(config: &mut Config, index: usize, otp_type: Option<OtpType>, counter: Option<u64>) -> Result<String> {
+    let account = &config.accounts[index];
+    let otp_type = otp_type.unwrap_or(account.otp_type);
+    let otp = Otp::new(account.secret.expose(), account.digits, account.period, otp_type)
+        .map_err(|e| anyhow::anyhow!("{}: {}", account.name, e))?
+        .with_algorithm(account.algorithm);
+
+    let code = match (otp_type, counter) {
+        (_, Some(counter)) => otp.generate_hotp(counter),
+        (OtpType::Hotp, None) => {
+            // Use the stored counter and move it forward, as the server will; the counter is read again under
+            // the store lock so concurrent `code` runs and `serve` never hand out the same one
+            let path = Config::get_path()?;
+            let _lock = config::lock_store(&path)?;
+            let mut fresh = config.reload_from_file(&path)?;
+            let stored = fresh.accounts.iter_mut()
+                .find(|stored| stored.name == account.name && stored.has_same_secret(account))
+                .ok_or(anyhow::anyhow!("'{}' was changed or removed meanwhile", account.name))?;
+            let code = otp.generate_hotp(stored.counter);
+            stored.counter += 1;
+            let counter = stored.counter;
+            fresh.save_to_file(&path)?;
+            config.accounts[index].counter = counter;
+            code
+        }
+        (OtpType::Totp, None) => otp.generate_code(),
+    };
+    Ok(code)
+}
+
+/// Ask a yes/no question on the terminal; anything but "y" or "yes" means no
+fn confirm(question: &str) -> Result<bool> {
+    let answer = prompt_line(&format!("{} [y/N]", question))?;
+    Ok(matches!(answer.as_str(), "y" | "yes"))
+}
+
+/// Settle an import conflict interactively (`--on-conflict ask`)
+fn ask_resolution(existing: &Account, incoming: &Account) -> Result<Option<Resolution>> {
+    eprintln!(
+        "'{}' ({}) conflicts with the stored '{}' ({}), which has a different secret",
+        incoming.name, incoming.issuer, existing.name, existing.issuer,
+    );
+    loop {
+        match prompt_line("[s]kip, [r]eplace or re[n]ame?")?.as_str() {
+            "s" | "skip" | "" => return Ok(Some(Resolution::Skip)),
+            "r" | "replace" => return Ok(Some(Resolution::Replace)),
+            "n" | "rename" => return Ok(Some(Resolution::Rename)),
+            _ => continue,
+        }
+    }
+}
+
+/// Parse the command line and run the command
+pub fn run() -> Result<()> {
+    let cli = Cli::parse();
+    if let Some(path) = cli.config {
+        config::set_path(path);
+    }
+    if let Some(name) = cli.profile {
+        profile::select(name);
+    }
+
+    match cli.command {
+        Command::List { filter, all_profiles } => {
+            print_accounts(&filter, None, all_profiles)?;
+        }
+        Command::Find { query, filter, all_profiles } => {
+            print_accounts(&filter, Some(&query), all_profiles)?;
+        }
+        Command::Move { account, to } => {
+            let from = profile::active()?;
+            if from == to {
+                return Err(anyhow::anyhow!("'{}' is already in profile '{}'", account, to));
+            }
+            let target_path = profile::store_path(&to)?;
+            if !target_path.is_file() {
+                return Err(anyhow::anyhow!("Profile '{}' does not exist", to));
+            }
+
+            let mut config = lock_config()?;
+            let index = config.accounts.iter().position(|acc| acc.name == account)
+                .ok_or(anyhow::anyhow!("Account not found"))?;
+            let moved = config.accounts[index].clone();
+
+            let mut target = Config::load_from_file(&target_path)?;
+            match reconcile::classify(&target.accounts, &moved) {
+                reconcile::Match::New => {}
+                reconcile::Match::Duplicate(_) => {
+                    return Err(anyhow::anyhow!("Profile '{}' already holds an account with the same secret", to));
+                }
+                _ => return Err(anyhow::anyhow!("Profile '{}' already has an account named '{}'", to, account)),
+            }
+
+            // Save the copy first, so a failure never loses the account
+            target.accounts.push(moved.clone());
+            target.save_to_file(&target_path)?;
+            config.accounts.remove(index);
+            let path = Config::get_path()?;
+            config.save_to_file(&path)?;
+
+            AuditLog::beside(&target_path, &target.settings).record("move", Some(&moved), &format!("moved from {}", from))?;
+            audit_log(&config)?.record("move", Some(&moved), &format!("moved to {}", to))?;
+            println!("Account moved to profile '{}'", to);
+        }
+        Command::Profile { action } => match action {
+            ProfileAction::List => {
+                let (active, default) = (profile::active()?, profile::default_profile()?);
+                for name in profile::names()? {
+                    let path = profile::store_path(&name)?;
+                    let store = if path.is_file() { Some(Config::load_metadata(&path)?) } else { None };
+
+                    let mut line = format!("{} {}", if name == active { "*" } else { " " }, name);
+                    line.push_str(&format!(": {} account(s)", store.as_ref().map_or(0, |store| store.accounts.len())));
+                    if store.as_ref().is_some_and(Config::is_encrypted) {
+                        line.push_str(", encrypted");
+                    }
+                    if name == default {
+                        line.push_str(", default");
+                    }
+                    println!("{}", line);
+                }
+            }
+            ProfileAction::Create { name, encrypt } => {
+                let passphrase = if encrypt { Some(prompt_new_passphrase()?) } else { None };
+                let path = profile::create(&name, passphrase.as_deref().map(String::as_str))?;
+                println!("Profile '{}' created at {}", name, path.display());
+            }
+            ProfileAction::Remove { name, yes } => {
+                profile::check_removable(&name)?;
+                let count = profile::accounts(&name)?.len();
+                if !yes && !confirm(&format!("Delete profile '{}' and its {} account(s)?", name, count))? {
+                    return Err(anyhow::anyhow!("Remove cancelled"));
+                }
+                profile::remove(&name)?;
+                println!("Profile '{}' removed", name);
+            }
+            ProfileAction::Default { name } => {
+                profile::set_default(&name)?;
+                println!("Default profile set to '{}'", name);
+            }
+        },
+        Command::Watch { filter } => {
+            let config = lock_config()?;
+            let accounts: Vec<Account> = filter.select(&config.accounts).into_iter().cloned().collect();
+            let log = audit_log(&config)?;
+            for account in accounts.iter().filter(|account| account.otp_type == OtpType::Totp) {
+                log.record_code("watch", account, "generated")?;
+            }
+            // Don't hold the config lock while the screen refreshes
+            drop(config);
+            watch::run(&accounts.iter().collect::<Vec<_>>())?;
+        }
+        Command::Tag { action } => {
+            let mut config = lock_config()?;
+            let (name, tags, add) = match &action {
+                TagAction::Add { account, tags } => (account, tags, true),
+                TagAction::Remove { account, tags } => (account, tags, false),
+            };
+            let account = config.accounts.iter_mut().find(|acc| &acc.name == name)
+                .ok_or(anyhow::anyhow!("Account not found"))?;
+
+            let changed = if add { tags::add_tags(account, tags) } else { tags::remove_tags(account, tags) };
+            let current = if account.tags.is_empty() { "none".to_string() } else { account.tags.join(", ") };
+            let account = account.clone();
+            if changed > 0 {
+                let path = Config::get_path()?;
+                config.save_to_file(&path)?;
+                audit_log(&config)?.record("tag", Some(&account), "updated")?;
+            }
+            println!("Tags: {}", current);
+        }
+        Command::Recovery { action } => {
+            let mut config = lock_config()?;
+            let name = match &action {
+                RecoveryAction::Add { account, .. } | RecoveryAction::List { account } | RecoveryAction::Use { account, .. } => account,
+            };
+            let index = config.accounts.iter().position(|acc| &acc.name == name)
+                .ok_or(anyhow::anyhow!("Account not found"))?;
+            let path = Config::get_path()?;
+
+            match action {
+                RecoveryAction::Add { codes, .. } => {
+                    let codes: Vec<Zeroizing<String>> = if codes.is_empty() {
+                        io::stdin().lines().map(|line| line.map(Zeroizing::new)).collect::<io::Result<_>>()?
+                    } else {
+                        codes.into_iter().map(Zeroizing::new).collect()
+                    };
+                    let added = recovery::add_codes(&mut config.accounts[index], &codes);
+                    if added > 0 {
+                        config.save_to_file(&path)?;
+                        audit_log(&config)?.record("recovery add", Some(&config.accounts[index]), &format!("added {}", added))?;
+                    }
+                    let account = &config.accounts[index];
+                    println!("Added {} recovery code(s), {} unused", added, recovery::unused(account));
+                }
+                RecoveryAction::List { .. } => {
+                    let account = &config.accounts[index];
+                    if account.recovery_codes.is_empty() {
+                        println!("No recovery codes stored for '{}'", account.name);
+                    }
+                    for code in &account.recovery_codes {
+                        println!("{}  {}", code.code.expose(), if code.used { "used" } else { "unused" });
+                    }
+                    if let Some(warning) = recovery::low_codes_warning(account) {
+                        eprintln!("{}", warning);
+                    }
+                }
+                RecoveryAction::Use { code, .. } => {
+                    let used = recovery::use_code(&mut config.accounts[index], code.as_deref())?;
+                    config.save_to_file(&path)?;
+                    audit_log(&config)?.record("recovery use", Some(&config.accounts[index]), "used")?;
+                    println!("{}", used.expose());
+                    if let Some(warning) = recovery::low_codes_warning(&config.accounts[index]) {
+                        eprintln!("{}", warning);
+                    }
+                }
+            }
+        }
+        Command::Code { account, otp_type, counter } => {
+            let mut config = lock_config()?;
+            let index = config.accounts.iter().position(|acc| acc.name == account)
+                .ok_or(anyhow::anyhow!("Account not found"))?;
+
+            let code = generate_code(&mut config, index, otp_type, counter)?;
+            audit_log(&config)?.record_code("code", &config.accounts[index], "generated")?;
+            println!("{}", code);
+        }
+        Command::Verify { account, code, window } => {
+            let mut config = lock_config()?;
+            // Load, verify and save under the store lock, so a code is never accepted twice
+            let path = Config::get_path()?;
+            let _lock = config::lock_store(&path)?;
+            *config = config.reload_from_file(&path)?;
+            let index = config.accounts.iter().position(|acc| acc.name == account)
+                .ok_or(anyhow::anyhow!("Account not found"))?;
+
+            let policy = Policy::from_settings(&config.settings);
+            let outcome = verify::verify(&mut config.accounts[index], &code, otp::unix_time(), window, &policy);
+            if !matches!(outcome, Outcome::Locked { .. }) {
+                config.save_to_file(&path)?;
+            }
+            audit_log(&config)?.record_code("verify", &config.accounts[index], &outcome.to_string())?;
+
+            match outcome {
+                Outcome::Accepted => println!("Valid"),
+                Outcome::Invalid => return Err(anyhow::anyhow!("Invalid code")),
+                Outcome::Replayed => return Err(anyhow::anyhow!("The code was already used")),
+                Outcome::Locked { until } => return Err(anyhow::anyhow!(
+                    "Too many failed attempts, '{}' is locked until {}; lift it with `otpc edit -a {} --reset-failures`",
+                    account, audit::format_time(until), account,
+                )),
+            }
+        }
+        Command::Exec { account, env, stdin, min_remaining, command } => {
+            let delivery = exec::Delivery { env: env.as_deref(), stdin };
+            delivery.check(&command)?;
+
+            let mut config = lock_config()?;
+            let index = config.accounts.iter().position(|acc| acc.name == account)
+                .ok_or(anyhow::anyhow!("Account not found"))?;
+
+            let account = &config.accounts[index];
+            if account.otp_type == OtpType::Totp {
+                let remaining = Otp::from_account(account)?.remaining_seconds(otp::unix_time());
+                let wait = exec::wait_seconds(remaining, min_remaining);
+                if wait > 0 {
+                    eprintln!("The current code expires in {}s, waiting for the next one", wait);
+                    thread::sleep(Duration::from_secs(wait));
+                }
+            }
+
+            let code = Zeroizing::new(generate_code(&mut config, index, None, None)?);
+            audit_log(&config)?.record_code("exec", &config.accounts[index], "generated")?;
+
+            let status = exec::run(&command, &code, &delivery)?;
+            drop(code);
+            drop(config);
+            process::exit(exec::exit_code(status));
+        }
+        Command::Pty { account, prompt_regex, password_file, password_command, password_regex, command } => {
+            let password = pty::read_password(password_file.as_deref(), password_command.as_deref())?;
+            let mut script = pty::Script::new(&prompt_regex, password.map(|password| (password_regex.as_str(), password)))?;
+
+            // Unlock the store before the terminal is handed to the command
+            let mut config = lock_config()?;
+            let index = config.accounts.iter().position(|acc| acc.name == account)
+                .ok_or(anyhow::anyhow!("Account not found"))?;
+
+            let status = pty::run(&command, &mut script, || {
+                let code = Zeroizing::new(generate_code(&mut config, index, None, None)?);
+                audit_log(&config)?.record_code("pty", &config.accounts[index], "typed")?;
+                Ok(code)
+            })?;
+            drop(config);
+            process::exit(exec::exit_code(status));
+        }
+        Command::Menu { launcher, command, copy, type_code, typer, filter } => {
+            let mut config = lock_config()?;
+            // Map the pick back by position; accounts of a hand-edited store may all lack an ID
+            let indices = filter.select_indices(&config.accounts);
+            if indices.is_empty() {
+                return Err(anyhow::anyhow!("No accounts found"));
+            }
+            let accounts: Vec<&Account> = indices.iter().map(|&index| &config.accounts[index]).collect();
+            let lines = menu::entries(&accounts);
+
+            let Some(picked) = menu::pick(launcher, command.as_deref(), &lines)? else {
+                return Err(anyhow::anyhow!("No account selected"));
+            };
+            let index = indices[picked];
+            let code = Zeroizing::new(generate_code(&mut config, index, None, None)?);
+
+            let result = if copy {
+                menu::copy(&code)?;
+                "copied"
+            } else if type_code {
+                menu::type_code(&code, typer)?;
+                "typed"
+            } else {
+                println!("{}", code.as_str());
+                "generated"
+            };
+            audit_log(&config)?.record_code("menu", &config.accounts[index], result)?;
+        }
+        Command::Edit { account, fields, show_secret } => {
+            let mut config = lock_config()?;
+            let index = config.accounts.iter().position(|acc| acc.name == account)
+                .ok_or(anyhow::anyhow!("Account not found"))?;
+
+            let updated = if fields.is_empty() {
+                match edit::edit_in_editor(&config.accounts[index], show_secret, &edit::editor_command())? {
+                    Some(updated) => updated,
+                    None => {
+                        println!("No changes");
+                        return Ok(());
+                    }
+                }
+            } else {
+                let mut updated = config.accounts[index].clone();
+                fields.apply(&mut updated);
+                updated.validate()?;
+                updated
+            };
+            edit::check_name(&config.accounts, index, &updated.name)?;
+
+            config.accounts[index] = updated;
+            let path = Config::get_path()?;
+            config.save_to_file(&path)?;
+            audit_log(&config)?.record("edit", Some(&config.accounts[index]), "updated")?;
+
+            println!("Account updated")
+        }
+        Command::Delete { account, yes, all } => {
+            let mut config = lock_config()?;
+            let matches = config.accounts.iter().filter(|acc| acc.name == account).count();
+            match matches {
+                0 => return Err(anyhow::anyhow!("Account not found")),
+                1 => {}
+                _ if !all => {
+                    return Err(anyhow::anyhow!("{} accounts are named '{}', pass --all to delete all of them", matches, account));
+                }
+                _ => {}
+            }
+
+            let question = if matches == 1 {
+                format!("Move '{}' to the trash?", account)
+            } else {
+                format!("Move all {} accounts named '{}' to the trash?", matches, account)
+            };
+            if !yes && !confirm(&question)? {
+                return Err(anyhow::anyhow!("Delete cancelled"));
+            }
+
+            let now = otp::unix_time();
+            let deleted_accounts: Vec<Account> = config.accounts.iter().filter(|acc| acc.name == account).cloned().collect();
+            let deleted = trash::delete(&mut config, &account, now);
+            expire_trash(&mut config, now)?;
+            let path = Config::get_path()?;
+            config.save_to_file(&path)?;
+
+            let log = audit_log(&config)?;
+            for account in &deleted_accounts {
+                log.record("delete", Some(account), "trashed")?;
+            }
+
+            println!("{} account(s) moved to the trash, restore with `otpc trash restore -a {}`", deleted, account)
+        }
+        Command::Log { action: Some(LogAction::Verify), .. } => {
+            let config = lock_config()?;
+            let chained = audit_log(&config)?.verify()?;
+            println!("Audit log intact, {} chained entr{}", chained, if chained == 1 { "y" } else { "ies" });
+        }
+        Command::Log { action: None, account, since } => {
+            let config = lock_config()?;
+            let since = since.map(|since| audit::parse_time(&since, otp::unix_time())).transpose()?;
+
+            for entry in audit_log(&config)?.entries()? {
+                let matches_account = account.as_ref().is_none_or(|account| {
+                    entry.account.as_ref() == Some(account) || entry.account_id.as_ref() == Some(account)
+                });
+                let recent = since.is_none_or(|since| audit::parse_time(&entry.time, 0).is_ok_and(|time| time >= since));
+                if !matches_account || !recent {
+                    continue;
+                }
+
+                let subject = match (&entry.account, &entry.account_id) {
+                    (Some(name), Some(id)) => format!("{} [{}]", name, id),
+                    (Some(name), None) => name.clone(),
+                    _ => "-".to_string(),
+                };
+                println!("{}  {:<16} {}  {}", entry.time, entry.command, subject, entry.result);
+            }
+        }
+        Command::Completions { shell } => {
+            completions::generate(shell, &mut io::stdout())?;
+        }
+        Command::GenMan { dir } => {
+            docs::generate_man(&dir)?;
+            println!("Man pages written to {}", dir.display());
+        }
+        Command::GenDocs => {
+            print!("{}", docs::markdown());
+        }
+        Command::CompleteAccounts => {
+            // Only names and issuers are needed, so the store is never unlocked or created here;
+            // a missing or unreadable store simply completes nothing
+            let path = Config::get_path()?;
+            if let Ok(config) = Config::load_metadata(&path) {
+                print!("{}", completions::account_lines(&config.accounts));
+            }
+        }
+        Command::Doctor => {
+            let config = lock_config()?;
+            let mut findings = doctor::check(&config);
+            findings.extend(doctor::check_permissions(&Config::get_path()?)?);
+
+            for finding in &findings {
+                println!("{}", finding);
+            }
+            let errors = findings.iter().filter(|finding| finding.severity == doctor::Severity::Error).count();
+            let warnings = findings.len() - errors;
+            println!(
+                "Checked {} account(s) in store version {}: {} error(s), {} warning(s)",
+                config.accounts.len(), config.version, errors, warnings,
+            );
+            if errors > 0 {
+                return Err(anyhow::anyhow!("Fix the errors above with `otpc edit` or `otpc delete`"));
+            }
+        }
+        Command::Trash { action } => {
+            let mut config = lock_config()?;
+            let now = otp::unix_time();
+            let mut changed = expire_trash(&mut config, now)?;
+            let log = audit_log(&config)?;
+
+            match action {
+                TrashAction::List => {
+                    if config.trash.is_empty() {
+                        println!("The trash is empty");
+                    }
+                    for (i, trashed) in config.trash.iter().enumerate() {
+                        println!(
+                            "{}: name: {}, issuer: {}, deleted {}",
+                            (i+1), trashed.account.name, trashed.account.issuer, trash::age(trashed.deleted_at, now),
+                        );
+                    }
+                }
+                TrashAction::Restore { account } => {
+                    let restored = trash::restore(&mut config, &account)?;
+                    log.record("trash restore", Some(&restored), "restored")?;
+                    changed = true;
+                    println!("Account restored");
+                }
+                TrashAction::Purge { account, yes } => {
+                    let question = match &account {
+                        Some(account) => format!("Permanently delete '{}' from the trash?", account),
+                        None => "Permanently delete every account in the trash?".to_string(),
+                    };
+                    if !yes && !confirm(&question)? {
+                        return Err(anyhow::anyhow!("Purge cancelled"));
+                    }
+                    let purged = trash::purge(&mut config, account.as_deref());
+                    for account in &purged {
+                        log.record("trash purge", Some(account), "purged")?;
+                    }
+                    changed |= !purged.is_empty();
+                    println!("{} account(s) purged", purged.len());
+                }
+            }
+
+            if changed {
+                let path = Config::get_path()?;
+                config.save_to_file(&path)?;
+            }
+        }
+        Command::Load { secret, account, issuer, on_conflict } => {
+            let mut config = lock_config()?;
+            let account = Account::new(account, secret, issuer.unwrap_or("host".to_string()));
+            account.validate()?;
+
+            let store = &mut *config;
+            let changes = reconcile::reconcile(
+                &mut store.accounts, &mut store.trash, vec![account], on_conflict, otp::unix_time(), ask_resolution,
+            )?;
+            if reconcile::has_changes(&changes) {
+                let path = Config::get_path()?;
+                config.save_to_file(&path)?;
+                audit_log(&config)?.record_changes("load", &config.accounts, &changes)?;
+            }
+
+            for change in &changes {
+                println!("{}", change);
+            }
+        }
+        Command::Import { file, format, apply, map, on_conflict } => {
+            let mapped = matches!(format, ImportFormat::Auto | ImportFormat::Csv | ImportFormat::Json | ImportFormat::Toml);
+            if !map.is_empty() && !mapped {
+                return Err(anyhow::anyhow!("--map only applies to csv, json and toml imports"));
+            }
+            let map = FieldMap::parse(&map)?;
+
+            let mut config = lock_config()?;
+            let imported = formats::import_file(format, &file, &map, || {
+                Ok(Zeroizing::new(rpassword::prompt_password("Backup password: ")?))
+            })?;
+
+            for reason in &imported.skipped {
+                eprintln!("Skipped {}", reason);
+            }
+
+            // Dry runs work on a copy and leave `ask` conflicts unresolved
+            let (mut accounts, mut trash) = (config.accounts.clone(), config.trash.clone());
+            let now = otp::unix_time();
+            let changes = reconcile::reconcile(&mut accounts, &mut trash, imported.accounts, on_conflict, now, |existing, incoming| {
+                if apply { ask_resolution(existing, incoming) } else { Ok(None) }
+            })?;
+            for change in &changes {
+                println!("{}", change);
+            }
+
+            if apply {
+                if reconcile::has_changes(&changes) {
+                    (config.accounts, config.trash) = (accounts, trash);
+                    let path = Config::get_path()?;
+                    config.save_to_file(&path)?;
+                    audit_log(&config)?.record_changes("import", &config.accounts, &changes)?;
+                }
+                println!("Summary: {}", reconcile::summary(&changes))
+            } else {
+                println!("Dry run: {}. Re-run with --apply to import.", reconcile::summary(&changes))
+            }
+        }
+        Command::Export { format, output, encrypt, include_secrets, fields, filter } => {
+            if encrypt && format != ExportFormat::Aegis {
+                return Err(anyhow::anyhow!("Only Aegis exports can be encrypted"));
+            }
+            let tabular = matches!(format, ExportFormat::Csv | ExportFormat::Json | ExportFormat::Toml);
+            if (include_secrets || !fields.is_empty()) && !tabular {
+                return Err(anyhow::anyhow!("--include-secrets and --fields only apply to csv, json and toml exports"));
+            }
+            let fields = formats::table::select_fields(&fields, include_secrets)?;
+            if include_secrets && !confirm("The export will contain every secret in plain text. Continue?")? {
+                return Err(anyhow::anyhow!("Export cancelled"));
+            }
+
+            let config = lock_config()?;
+            let accounts: Vec<Account> = filter.select(&config.accounts).into_iter().cloned().collect();
+            if format == ExportFormat::Pass {
+                let prefix = output.as_deref().map(|path| path.to_string_lossy().into_owned());
+                let (created, existing) = formats::pass::export_store(&accounts, prefix.as_deref().unwrap_or("otp"))?;
+                for entry in &existing {
+                    eprintln!("Skipped {}: entry already exists", entry);
+                }
+                let log = audit_log(&config)?;
+                let prefix = prefix.as_deref().unwrap_or("otp");
+                for account in accounts.iter().filter(|account| created.contains(&formats::pass::entry_name(prefix, account))) {
+                    log.record("export pass", Some(account), "exported")?;
+                }
+                println!("{} account(s) exported to the password store", created.len());
+                return Ok(());
+            }
+
+            let password = if encrypt { Some(prompt_new_passphrase()?) } else { None };
+            let data = formats::export(format, &accounts, password.as_deref().map(String::as_str), &fields)?;
+            let log = audit_log(&config)?;
+            let command = format!("export {}", format.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default());
+            // Table exports may leave the secret out, every other format carries it
+            let result = if tabular && !fields.contains(&Field::Secret) { "exported without secret" } else { "exported" };
+            for account in &accounts {
+                log.record(&command, Some(account), result)?;
+            }
+
+            match output {
+                Some(output) => {
+                    config::write_private_file(&output, data.as_bytes())?;
+                    println!("{} account(s) exported to {}", accounts.len(), output.display());
+                }
+                None => println!("{}", data.as_str()),
+            }
+        }
+        Command::Serve { socket, listen, token, allow_write } => {
+            serve::run(serve::ServeOptions { socket, listen, token, allow_write })?;
+        }
+        Command::Agent { action, timeout } => match action {
+            None => agent::start(timeout)?,
+            Some(AgentAction::Run { timeout }) => agent::run(timeout)?,
+            Some(AgentAction::Unlock) => {
+                // Loading the store asks the agent first and caches the key after prompting
+                let config = lock_config()?;
+                if config.is_encrypted() {
+                    println!("Store unlocked");
+                } else {
+                    println!("Store is not encrypted");
+                }
+            }
+            Some(AgentAction::Lock) => {
+                agent::lock()?;
+                println!("Agent locked");
+            }
+            Some(AgentAction::Status) => agent::status()?,
+            Some(AgentAction::Stop) => {
+                agent::stop()?;
+                println!("Agent stopped");
+            }
+        },
+        Command::Encrypt => {
+            let mut config = lock_config()?;
+            let passphrase = prompt_new_passphrase()?;
+
+            config.set_passphrase(Some(&passphrase))?;
+            let path = Config::get_path()?;
+            config.save_to_file(&path)?;
+            audit_log(&config)?.record("encrypt", None, "store encrypted")?;
+
+            println!("Store encrypted")
+        }
+        Command::Decrypt => {
+            let mut config = lock_config()?;
+            if !config.is_encrypted() {
+                println!("Store is not encrypted");
+                return Ok(());
+            }
+
+            config.set_passphrase(None)?;
+            let path = Config::get_path()?;
+            config.save_to_file(&path)?;
+            audit_log(&config)?.record("decrypt", None, "store decrypted")?;
+
+            println!("Store decrypted")
+        }
+    }
+
+    Ok(())
+}
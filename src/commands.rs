@@ -1,12 +1,76 @@
-use clap::Parser;
+use std::path::PathBuf;
 
-use crate::otp::OtpType;
+use clap::{Args, Parser, Subcommand};
+
+use crate::formats::table::Field;
+use crate::formats::{ExportFormat, ImportFormat};
+use crate::menu::{Launcher, Typer};
+use crate::otp::{Algorithm, OtpType};
+use crate::reconcile::ConflictPolicy;
+use crate::secret::SecretString;
 
 #[derive(Parser, Debug, Clone)]
+#[clap(name = "otpc", version, about = "OTP command line tool")]
+pub struct Cli {
+    /// Path of the account store [default: $XDG_CONFIG_HOME/otpc/config.toml]
+    #[clap(long, global = true, env = "OTPC_CONFIG", value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Profile to use [default: the profile set with `otpc profile default`]
+    #[clap(long, global = true, env = "OTPC_PROFILE", value_name = "NAME")]
+    pub profile: Option<String>,
+
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug, Clone)]
 pub enum Command {
     /// Show all saved OTP keys
     #[clap(name = "list", about = "Show all saved OTP keys")]
-    List,
+    List {
+        #[clap(flatten)]
+        filter: AccountFilter,
+
+        /// List the accounts of every profile
+        #[clap(long)]
+        all_profiles: bool,
+    },
+
+    /// Search accounts by name, issuer, group or tag
+    #[clap(name = "find", about = "Search accounts by name, issuer, group or tag")]
+    Find {
+        /// Text to look for, ignoring case
+        query: String,
+
+        #[clap(flatten)]
+        filter: AccountFilter,
+
+        /// Search every profile
+        #[clap(long)]
+        all_profiles: bool,
+    },
+
+    /// Show live TOTP codes, refreshed every second
+    #[clap(name = "watch", about = "Show live TOTP codes, refreshed every second")]
+    Watch {
+        #[clap(flatten)]
+        filter: AccountFilter,
+    },
+
+    /// Add or remove tags on an account
+    #[clap(name = "tag", about = "Add or remove tags on an account")]
+    Tag {
+        #[clap(subcommand)]
+        action: TagAction,
+    },
+
+    /// Keep the recovery codes a service handed out with an account
+    #[clap(name = "recovery", about = "Keep the recovery codes a service handed out with an account")]
+    Recovery {
+        #[clap(subcommand)]
+        action: RecoveryAction,
+    },
     
     /// Generate the current OTP code for a specific account
     #[clap(name = "code", about = "Generate the current OTP code for a specific account")]
@@ -15,29 +79,230 @@ pub enum Command {
         #[clap(long, short)]
         account: String,
 
-        /// The type of OTP code to generate
-        #[clap(long, short, default_value = "totp")]
-        otp_type: OtpType,
+        /// The type of OTP code to generate (defaults to the account's type)
+        #[clap(long, short)]
+        otp_type: Option<OtpType>,
 
-        /// The counter value for HOTP
+        /// The counter value for HOTP (defaults to the stored counter)
         #[clap(long, short)]
         counter: Option<u64>,
     },
     
-    /// Delete an account
-    #[clap(name = "delete", about = "Delete an account")]
+    /// Check a code as a server would, refusing replayed codes and locking after repeated failures
+    #[clap(name = "verify", about = "Check a code as a server would, refusing replayed codes and locking after repeated failures")]
+    Verify {
+        /// The name of the account
+        #[clap(long, short)]
+        account: String,
+
+        /// The code to check
+        code: String,
+
+        /// TOTP periods of clock drift accepted on either side (HOTP checks 2n + 1 codes from its counter), at most 10
+        #[clap(long, default_value = "1", value_parser = clap::value_parser!(u64).range(..=crate::verify::MAX_WINDOW))]
+        window: u64,
+    },
+
+    /// Run a command with the current code in its environment, standard input or arguments
+    #[clap(name = "exec", about = "Run a command with the current code in its environment, standard input or arguments")]
+    Exec {
+        /// The name of the account
+        #[clap(long, short)]
+        account: String,
+
+        /// Set this environment variable to the code
+        #[clap(long, value_name = "NAME")]
+        env: Option<String>,
+
+        /// Write the code and a newline to the command's standard input
+        #[clap(long)]
+        stdin: bool,
+
+        /// Wait for the next code when the current one expires within this many seconds (TOTP only)
+        #[clap(long, value_name = "SECONDS", default_value = "0")]
+        min_remaining: u64,
+
+        /// The command to run; `{code}` in its arguments is replaced by the code
+        #[clap(last = true, required = true)]
+        command: Vec<String>,
+    },
+
+    /// Run an interactive command, typing the code when it asks for one
+    #[clap(name = "pty", about = "Run an interactive command, typing the code when it asks for one")]
+    Pty {
+        /// The name of the account
+        #[clap(long, short)]
+        account: String,
+
+        /// Regular expression matching the prompt for the code
+        #[clap(long, default_value = "(?i)verification code")]
+        prompt_regex: String,
+
+        /// File whose first line is a password to type before the code
+        #[clap(long, value_name = "PATH", conflicts_with = "password_command")]
+        password_file: Option<PathBuf>,
+
+        /// Command printing a password to type before the code, e.g. `pass show host`
+        #[clap(long, value_name = "COMMAND")]
+        password_command: Option<String>,
+
+        /// Regular expression matching the password prompt
+        #[clap(long, default_value = "(?i)password:")]
+        password_regex: String,
+
+        /// The command to run
+        #[clap(last = true, required = true)]
+        command: Vec<String>,
+    },
+
+    /// Pick an account from dmenu, rofi, fzf or another launcher and output its code
+    #[clap(name = "menu", about = "Pick an account from dmenu, rofi, fzf or another launcher and output its code")]
+    Menu {
+        /// The launcher to pick the account with
+        #[clap(long, value_enum, default_value = "dmenu")]
+        launcher: Launcher,
+
+        /// The command to run for --launcher custom; it reads one account per line and prints the chosen one
+        #[clap(long, required_if_eq("launcher", "custom"))]
+        command: Option<String>,
+
+        /// Copy the code to the clipboard instead of printing it
+        #[clap(long, conflicts_with = "type_code")]
+        copy: bool,
+
+        /// Type the code into the focused window instead of printing it
+        #[clap(long = "type")]
+        type_code: bool,
+
+        /// The tool to type with (wtype on Wayland, xdotool on X11, ydotool otherwise)
+        #[clap(long, value_enum, requires = "type_code")]
+        typer: Option<Typer>,
+
+        #[clap(flatten)]
+        filter: AccountFilter,
+    },
+
+    /// Change the name, issuer or OTP parameters of an account
+    #[clap(name = "edit", about = "Change the name, issuer or OTP parameters of an account")]
+    Edit {
+        /// The name of the account to edit
+        #[clap(long, short)]
+        account: String,
+
+        #[clap(flatten)]
+        fields: EditFields,
+
+        /// Show the secret in the editor instead of masking it
+        #[clap(long)]
+        show_secret: bool,
+    },
+
+    /// Move an account to the trash
+    #[clap(name = "delete", about = "Move an account to the trash")]
     Delete {
         /// The name or ID of the account to delete
         #[clap(long, short)]
         account: String,
+
+        /// Don't ask for confirmation
+        #[clap(long, short)]
+        yes: bool,
+
+        /// Delete every account with this name
+        #[clap(long)]
+        all: bool,
+    },
+
+    /// Move an account to another profile
+    #[clap(name = "move", about = "Move an account to another profile")]
+    Move {
+        /// The name of the account
+        #[clap(long, short)]
+        account: String,
+
+        /// The profile to move it to
+        #[clap(long)]
+        to: String,
+    },
+
+    /// List, create, remove or choose the default profile
+    #[clap(name = "profile", about = "List, create, remove or choose the default profile")]
+    Profile {
+        #[clap(subcommand)]
+        action: ProfileAction,
+    },
+
+    /// Show or verify the audit log
+    #[clap(name = "log", about = "Show or verify the audit log", args_conflicts_with_subcommands = true)]
+    Log {
+        #[clap(subcommand)]
+        action: Option<LogAction>,
+
+        /// Only entries for this account name or ID
+        #[clap(long, short)]
+        account: Option<String>,
+
+        /// Only entries at or after this time (YYYY-MM-DD, YYYY-MM-DDTHH:MM:SSZ, or an age like 7d)
+        #[clap(long)]
+        since: Option<String>,
+    },
+
+    /// List, restore or purge deleted accounts
+    #[clap(name = "trash", about = "List, restore or purge deleted accounts")]
+    Trash {
+        #[clap(subcommand)]
+        action: TrashAction,
     },
     
-    /// Import an OTP key from a QR code image
-    #[clap(name = "import", about = "Import an OTP key from a QR code image")]
+    /// Import OTP keys from a QR code image or a backup file
+    #[clap(name = "import", about = "Import OTP keys from a QR code image or a backup file")]
     Import {
-        /// The path to the QR code image file
+        /// The path to the QR code image or backup file (or password store directory for pass)
         #[clap(long, short)]
         file: std::path::PathBuf,
+
+        /// The format of the file (detected from its contents by default)
+        #[clap(long, value_enum, default_value = "auto")]
+        format: ImportFormat,
+
+        /// Save the new accounts instead of only showing what would be imported
+        #[clap(long)]
+        apply: bool,
+
+        /// Column holding each field for csv, json and toml, e.g. name=Login,secret=Seed
+        #[clap(long, value_delimiter = ',')]
+        map: Vec<String>,
+
+        /// What to do with accounts that clash with a stored one
+        #[clap(long, value_enum, default_value = "skip")]
+        on_conflict: ConflictPolicy,
+    },
+
+    /// Export all accounts to a backup file
+    #[clap(name = "export", about = "Export all accounts to a backup file")]
+    Export {
+        /// The backup format
+        #[clap(long, value_enum)]
+        format: ExportFormat,
+
+        /// The file to write (defaults to standard output), or the folder in the password store for pass
+        #[clap(long, short)]
+        output: Option<std::path::PathBuf>,
+
+        /// Encrypt the backup with a password (Aegis only)
+        #[clap(long)]
+        encrypt: bool,
+
+        /// Include the secrets and recovery codes in csv, json and toml exports
+        #[clap(long)]
+        include_secrets: bool,
+
+        /// Fields to write for csv, json and toml, e.g. name,issuer,group (all but the secret and recovery codes by default)
+        #[clap(long, value_enum, value_delimiter = ',')]
+        fields: Vec<Field>,
+
+        #[clap(flatten)]
+        filter: AccountFilter,
     },
 
     /// Load an OTP key
@@ -45,7 +310,7 @@ pub enum Command {
     Load {
         /// The OTP secret key
         #[clap(long, short)]
-        secret: String,
+        secret: SecretString,
 
         /// The account name
         #[clap(long, short)]
@@ -54,6 +319,271 @@ pub enum Command {
         /// The issuer name
         #[clap(long, short)]
         issuer: Option<String>,
+
+        /// What to do if the account clashes with a stored one
+        #[clap(long, value_enum, default_value = "skip")]
+        on_conflict: ConflictPolicy,
     },
-    
-}
\ No newline at end of file
+
+    /// Serve a local JSON API for listing accounts and generating codes
+    #[clap(name = "serve", about = "Serve a local JSON API for listing accounts and generating codes")]
+    Serve {
+        /// The path of the Unix socket to listen on
+        #[clap(long)]
+        socket: Option<std::path::PathBuf>,
+
+        /// The TCP address to listen on (e.g. 127.0.0.1:7878)
+        #[clap(long)]
+        listen: Option<std::net::SocketAddr>,
+
+        /// The bearer token TCP clients must send
+        #[clap(long, env = "OTPC_SERVE_TOKEN", hide_env_values = true)]
+        token: Option<String>,
+
+        /// Allow clients to add and delete accounts
+        #[clap(long)]
+        allow_write: bool,
+    },
+
+    /// Start the agent that caches the store key for a session
+    #[clap(name = "agent", about = "Start the agent that caches the store key for a session")]
+    Agent {
+        #[clap(subcommand)]
+        action: Option<AgentAction>,
+
+        /// Seconds of inactivity before the agent forgets its keys
+        #[clap(long, short, default_value = "900")]
+        timeout: u64,
+    },
+
+    /// Check every account for invalid or weak settings
+    #[clap(name = "doctor", about = "Check every account for invalid or weak settings")]
+    Doctor,
+
+    /// Print a shell completion script
+    #[clap(name = "completions", about = "Print a shell completion script")]
+    Completions {
+        /// The shell to complete for
+        #[clap(value_enum)]
+        shell: clap_complete::Shell,
+    },
+
+    /// Write a man page for every command
+    #[clap(name = "gen-man", about = "Write a man page for every command")]
+    GenMan {
+        /// The directory to write the pages to
+        dir: PathBuf,
+    },
+
+    /// Print a markdown reference of every command
+    #[clap(name = "gen-docs", about = "Print a markdown reference of every command")]
+    GenDocs,
+
+    /// Print account names and issuers for shell completion
+    #[clap(name = "__complete-accounts", hide = true)]
+    CompleteAccounts,
+
+    /// Encrypt the stored secrets with a passphrase (or change it)
+    #[clap(name = "encrypt", about = "Encrypt the stored secrets with a passphrase (or change it)")]
+    Encrypt,
+
+    /// Remove the passphrase and store the secrets in plain text
+    #[clap(name = "decrypt", about = "Remove the passphrase and store the secrets in plain text")]
+    Decrypt,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum TagAction {
+    /// Add tags to an account
+    Add {
+        /// The name of the account
+        #[clap(long, short)]
+        account: String,
+
+        /// The tags to add
+        #[clap(required = true)]
+        tags: Vec<String>,
+    },
+    /// Remove tags from an account
+    Remove {
+        /// The name of the account
+        #[clap(long, short)]
+        account: String,
+
+        /// The tags to remove
+        #[clap(required = true)]
+        tags: Vec<String>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum RecoveryAction {
+    /// Store recovery codes for an account
+    Add {
+        /// The name of the account
+        #[clap(long, short)]
+        account: String,
+
+        /// The codes to add (read one per line from standard input when none are given)
+        codes: Vec<String>,
+    },
+    /// Show the recovery codes of an account and which are used
+    List {
+        /// The name of the account
+        #[clap(long, short)]
+        account: String,
+    },
+    /// Mark a recovery code used and print it
+    Use {
+        /// The name of the account
+        #[clap(long, short)]
+        account: String,
+
+        /// The code to mark (the first unused one by default)
+        code: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ProfileAction {
+    /// Show every profile
+    List,
+    /// Create an empty profile
+    Create {
+        /// The name of the profile
+        name: String,
+
+        /// Protect the profile with its own passphrase
+        #[clap(long)]
+        encrypt: bool,
+    },
+    /// Delete a profile with all of its accounts
+    Remove {
+        /// The name of the profile
+        name: String,
+
+        /// Don't ask for confirmation
+        #[clap(long, short)]
+        yes: bool,
+    },
+    /// Use a profile when --profile is not given
+    Default {
+        /// The name of the profile
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum LogAction {
+    /// Check the hash chain for removed, reordered or modified entries
+    Verify,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum TrashAction {
+    /// Show deleted accounts
+    List,
+    /// Move a deleted account back into the store
+    Restore {
+        /// The name of the account
+        #[clap(long, short)]
+        account: String,
+    },
+    /// Remove deleted accounts for good
+    Purge {
+        /// Only purge accounts with this name (all of them by default)
+        #[clap(long, short)]
+        account: Option<String>,
+
+        /// Don't ask for confirmation
+        #[clap(long, short)]
+        yes: bool,
+    },
+}
+
+/// Account selection shared by `list`, `watch` and `export`
+#[derive(Args, Debug, Clone, Default)]
+pub struct AccountFilter {
+    /// Only accounts with this tag (repeat to require several)
+    #[clap(long = "tag")]
+    pub tags: Vec<String>,
+
+    /// Only accounts in this group
+    #[clap(long)]
+    pub group: Option<String>,
+
+    /// Only favorite accounts
+    #[clap(long)]
+    pub favorites: bool,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum AgentAction {
+    /// Run the agent in the foreground
+    #[clap(name = "run", about = "Run the agent in the foreground")]
+    Run {
+        /// Seconds of inactivity before the agent forgets its keys
+        #[clap(long, short, default_value = "900")]
+        timeout: u64,
+    },
+
+    /// Unlock the store and cache its key in the agent
+    #[clap(name = "unlock", about = "Unlock the store and cache its key in the agent")]
+    Unlock,
+
+    /// Make the agent forget every cached key
+    #[clap(name = "lock", about = "Make the agent forget every cached key")]
+    Lock,
+
+    /// Show whether the agent is running and unlocked
+    #[clap(name = "status", about = "Show whether the agent is running and unlocked")]
+    Status,
+
+    /// Stop the running agent
+    #[clap(name = "stop", about = "Stop the running agent")]
+    Stop,
+}
+
+/// Account fields changed by `otpc edit`; without any of them the account opens in $EDITOR
+#[derive(Args, Debug, Clone, Default)]
+pub struct EditFields {
+    /// New account name
+    #[clap(long)]
+    pub name: Option<String>,
+
+    /// New issuer name
+    #[clap(long, short)]
+    pub issuer: Option<String>,
+
+    /// Number of digits in a code
+    #[clap(long)]
+    pub digits: Option<u32>,
+
+    /// TOTP time step in seconds
+    #[clap(long)]
+    pub period: Option<u64>,
+
+    /// HMAC algorithm
+    #[clap(long, value_enum)]
+    pub algorithm: Option<Algorithm>,
+
+    /// Next HOTP counter value
+    #[clap(long)]
+    pub counter: Option<u64>,
+
+    /// Group name (an empty value removes the group)
+    #[clap(long)]
+    pub group: Option<String>,
+
+    /// Free-form note (an empty value removes the note)
+    #[clap(long)]
+    pub note: Option<String>,
+
+    /// Mark or unmark the account as a favorite
+    #[clap(long)]
+    pub favorite: Option<bool>,
+
+    /// Clear the failed verifications and lift a lockout
+    #[clap(long)]
+    pub reset_failures: bool,
+}
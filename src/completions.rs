@@ -0,0 +1,148 @@
+use std::io::Write;
+
+use anyhow::Result;
+use clap::CommandFactory as _;
+use clap_complete::Shell;
+
+use crate::commands::Cli;
+use crate::config::Account;
+
+/// Hidden subcommand the scripts call to list account names
+const COMPLETE_ACCOUNTS: &str = "__complete-accounts";
+
+const BASH_ACCOUNTS: &str = r#"
+_otpc_accounts() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD-1]}"
+    if [[ ${prev} == -a || ${prev} == --account ]]; then
+        local IFS=$'\n'
+        COMPREPLY=($(compgen -W "$(otpc __complete-accounts 2>/dev/null | cut -f1)" -- "${cur}"))
+        return 0
+    fi
+    _otpc "$@"
+}
+
+complete -F _otpc_accounts -o bashdefault -o default otpc
+"#;
+
+const ZSH_ACCOUNTS: &str = r#"
+_otpc_accounts() {
+    local line
+    local -a accounts
+    for line in ${(f)"$(otpc __complete-accounts 2>/dev/null)"}; do
+        accounts+=("${${line%%$'\t'*}//:/\\:}:${line#*$'\t'}")
+    done
+    _describe -t accounts 'account' accounts
+}
+"#;
+
+const POWERSHELL_ACCOUNTS: &str = r#"
+    $previous = $commandAst.CommandElements | Where-Object { $_.Extent.EndOffset -lt $cursorPosition } | Select-Object -Last 1
+    if ($previous -and ($previous.ToString() -eq '-a' -or $previous.ToString() -eq '--account')) {
+        otpc __complete-accounts 2>$null | ForEach-Object {
+            $name, $issuer = $_ -split "`t", 2
+            if (-not $issuer) { $issuer = $name }
+            if ($name -like "$wordToComplete*") {
+                [CompletionResult]::new($name, $name, [CompletionResultType]::ParameterValue, $issuer)
+            }
+        }
+        return
+    }
+"#;
+
+const ELVISH_ACCOUNTS: &str = r#"
+var otpc-complete~ = $edit:completion:arg-completer[otpc]
+set edit:completion:arg-completer[otpc] = {|@words|
+    if (and (> (count $words) 2) (has-value [-a --account] $words[-2])) {
+        otpc __complete-accounts 2>/dev/null | each {|line|
+            var name issuer = (str:split "\t" $line)
+            edit:complex-candidate $name &display=$name' '$issuer
+        }
+    } else {
+        $otpc-complete~ $@words
+    }
+}
+"#;
+
+/// Write the completion script for `shell`, completing `--account` values from the store
+pub(crate) fn generate(shell: Shell, out: &mut impl Write) -> Result<()> {
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut visible_command(), "otpc", &mut script);
+    let script = String::from_utf8(script)?;
+
+    let script = match shell {
+        Shell::Bash => format!("{}{}", script, BASH_ACCOUNTS),
+        // The generated specs complete every account option with `_default`
+        Shell::Zsh => script.replacen("#compdef otpc\n", &format!("#compdef otpc\n{}", ZSH_ACCOUNTS), 1)
+            .replace(":ACCOUNT:_default'", ":ACCOUNT:_otpc_accounts'"),
+        Shell::Fish => script.lines()
+            .map(|line| match line.contains(" -l account ") {
+                true => format!("{} -f -a \"(otpc {} 2>/dev/null)\"\n", line, COMPLETE_ACCOUNTS),
+                false => format!("{}\n", line),
+            })
+            .collect(),
+        // Both only complete option names, so account values are handled before the generated completer runs
+        Shell::PowerShell => script.replacen(
+            "param($wordToComplete, $commandAst, $cursorPosition)\n",
+            &format!("param($wordToComplete, $commandAst, $cursorPosition)\n{}", POWERSHELL_ACCOUNTS),
+            1,
+        ),
+        Shell::Elvish => format!("{}{}", script, ELVISH_ACCOUNTS),
+        _ => script,
+    };
+    out.write_all(script.as_bytes())?;
+    Ok(())
+}
+
+/// The CLI as users see it: clap's script generators list hidden subcommands too
+fn visible_command() -> clap::Command {
+    let cli = Cli::command();
+    let subcommands: Vec<clap::Command> = cli.get_subcommands()
+        .filter(|command| !command.is_hide_set())
+        .cloned()
+        .collect();
+    clap::Command::new("otpc")
+        .args(cli.get_arguments().cloned())
+        .subcommands(subcommands)
+}
+
+/// One `name<TAB>issuer` line per account, the format fish reads directly
+pub(crate) fn account_lines(accounts: &[Account]) -> String {
+    let clean = |text: &str| text.replace(['\t', '\n', '\r'], " ");
+    accounts.iter()
+        .map(|account| format!("{}\t{}\n", clean(&account.name), clean(&account.issuer)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(shell: Shell) -> String {
+        let mut out = Vec::new();
+        generate(shell, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_scripts_complete_accounts() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::PowerShell, Shell::Elvish] {
+            let script = script(shell);
+            assert!(script.contains("otpc __complete-accounts"), "{} does not complete accounts", shell);
+            // Only the dynamic hook may mention the hidden subcommand
+            assert_eq!(script.matches(COMPLETE_ACCOUNTS).count(), match shell {
+                Shell::Fish => script.matches(" -l account ").count(),
+                _ => 1,
+            }, "{} offers the hidden subcommand", shell);
+        }
+        assert!(!script(Shell::Zsh).contains(":ACCOUNT:_default"));
+    }
+
+    #[test]
+    fn test_account_lines() {
+        let accounts = vec![
+            Account::new("alice".to_string(), "JBSWY3DPEHPK3PXP".into(), "Example".to_string()),
+            Account::new("bob\tsmith".to_string(), "GEZDGNBVGY3TQOJQ".into(), String::new()),
+        ];
+        assert_eq!(account_lines(&accounts), "alice\tExample\nbob smith\t\n");
+    }
+}
@@ -1,91 +1,868 @@
-use std::fs::{self, File, OpenOptions};
-use std::io::{self, Read as _, Seek as _, SeekFrom, Write as _};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write as _};
 
-#[cfg(unix)]
-use std::os::unix::fs::PermissionsExt as _;
-
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::env;
-use std::sync::{LazyLock, Mutex};
+use std::sync::{Mutex, OnceLock};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
 
 use anyhow::Result;
 
-static CONFIG: LazyLock<Mutex<Config>> = LazyLock::new(|| match load_config() {
-    Ok(config) => Mutex::new(config),
-    Err(e) => panic!("Failed to load config: {}", e),
-});
+use crate::agent;
+use crate::crypto::{self, VaultKey};
+use crate::formats::random_uuid;
+use crate::migrate::{self, Upgrade};
+use crate::profile;
+use crate::otp::{self, constant_time_eq, Algorithm, OtpType};
+use crate::secret::SecretString;
 
+static CONFIG: OnceLock<Mutex<Config>> = OnceLock::new();
+/// Store path given with `--config` or `OTPC_CONFIG`
+static PATH_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
 
-#[derive(Serialize, Deserialize)]
+/// Prefix marking a secret encrypted with the store key
+const ENCRYPTED_PREFIX: &str = "enc:";
+/// Known plaintext sealed into `Encryption::check` to verify passphrases
+const CHECK_PLAINTEXT: &[u8] = b"otpc";
+/// scrypt cost used for new passphrases (N = 2^15)
+const DEFAULT_LOG_N: u8 = 15;
+/// Days deleted accounts stay in the trash unless configured otherwise
+const DEFAULT_TRASH_RETENTION_DAYS: u64 = 30;
+/// Wrong codes in a row before verification locks an account
+const DEFAULT_VERIFY_MAX_FAILURES: u32 = 5;
+/// Seconds of the first verification lockout
+const DEFAULT_VERIFY_LOCKOUT_SECONDS: u64 = 30;
+/// Largest verification window a `serve` client may ask for
+const DEFAULT_SERVE_MAX_WINDOW: u64 = 1;
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    /// Layout version of the store, see `migrate`
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<Encryption>,
+    #[serde(default, skip_serializing_if = "Settings::is_default")]
+    pub settings: Settings,
     pub accounts: Vec<Account>,
+    /// Deleted accounts, kept until restored or purged
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trash: Vec<TrashedAccount>,
+    /// Key the secrets were decrypted with, kept to re-encrypt them on save
+    #[serde(skip)]
+    key: Option<VaultKey>,
+}
+
+/// Passphrase-derived encryption of the account secrets
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Encryption {
+    pub kdf: String,
+    pub salt: String,
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+    pub check: String,
+}
+
+/// User preferences stored in the `[settings]` table
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// Days before trashed accounts are purged for good, 0 to keep them forever
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u64,
+    /// Link audit log entries with a SHA-256 hash chain
+    #[serde(default = "default_true")]
+    pub audit_hash_chain: bool,
+    /// Record code generations and verifications in the audit log
+    #[serde(default = "default_true")]
+    pub audit_code_generation: bool,
+    /// Consecutive wrong codes before verification is locked, 0 to never lock
+    #[serde(default = "default_verify_max_failures")]
+    pub verify_max_failures: u32,
+    /// Seconds of the first lockout, doubled by each further wrong code
+    #[serde(default = "default_verify_lockout_seconds")]
+    pub verify_lockout_seconds: u64,
+    /// Largest window a `serve` client may ask for; larger requests are clamped to it
+    #[serde(default = "default_serve_max_window")]
+    pub serve_max_window: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            trash_retention_days: DEFAULT_TRASH_RETENTION_DAYS,
+            audit_hash_chain: true,
+            audit_code_generation: true,
+            verify_max_failures: DEFAULT_VERIFY_MAX_FAILURES,
+            verify_lockout_seconds: DEFAULT_VERIFY_LOCKOUT_SECONDS,
+            serve_max_window: DEFAULT_SERVE_MAX_WINDOW,
+        }
+    }
+}
+
+impl Settings {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+fn default_trash_retention_days() -> u64 {
+    DEFAULT_TRASH_RETENTION_DAYS
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_verify_max_failures() -> u32 {
+    DEFAULT_VERIFY_MAX_FAILURES
+}
+
+fn default_verify_lockout_seconds() -> u64 {
+    DEFAULT_VERIFY_LOCKOUT_SECONDS
+}
+
+fn default_serve_max_window() -> u64 {
+    DEFAULT_SERVE_MAX_WINDOW
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+/// An account moved to the trash, with the Unix time it was deleted at
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TrashedAccount {
+    pub deleted_at: u64,
+    pub account: Account,
+}
+
+impl Encryption {
+    pub(crate) fn derive_key(&self, passphrase: &str) -> Result<VaultKey> {
+        if self.kdf != "scrypt" {
+            return Err(anyhow::anyhow!("Unsupported key derivation function: {}", self.kdf));
+        }
+
+        let salt = BASE64.decode(&self.salt)?;
+        let key = crypto::derive_key(passphrase, &salt, self.log_n, self.r, self.p)?;
+        if !self.verify(&key) {
+            return Err(anyhow::anyhow!("Incorrect passphrase"));
+        }
+        Ok(key)
+    }
+
+    pub(crate) fn verify(&self, key: &VaultKey) -> bool {
+        BASE64.decode(&self.check)
+            .ok()
+            .and_then(|check| crypto::decrypt(key, &check).ok())
+            .is_some_and(|plain| plain.as_slice() == CHECK_PLAINTEXT)
+    }
 }
 
 impl Config {   
+    pub(crate) fn new(accounts: Vec<Account>) -> Self {
+        Self { version: migrate::CURRENT_VERSION, accounts, ..Default::default() }
+    }
+
+    /// Path of the active profile's store
+    ///
+    /// Only resolves the path; nothing is created until the store is loaded.
     pub(crate) fn get_path() -> Result<PathBuf> {
-        let home_dir = match env::var("HOME").or_else(|_| env::var("USERPROFILE")) {
-            Ok(path) => PathBuf::from(path),
-            Err(_) => return Err(anyhow::anyhow!("Cannot found user directory")),
+        profile::store_path(&profile::active()?)
+    }
+
+    /// Read a store without unlocking it; encrypted secrets stay sealed
+    pub(crate) fn load_metadata(path: &Path) -> Result<Config> {
+        let config_str = Zeroizing::new(fs::read_to_string(path)?);
+        Ok(Config::parse(&config_str)?.0)
+    }
+
+    pub(crate) fn load_from_file(path: &Path) -> Result<Config> {
+        Config::open(path, None)
+    }
+
+    /// Load the store again, reusing the key this one was unlocked with
+    pub(crate) fn reload_from_file(&self, path: &Path) -> Result<Config> {
+        Config::open(path, self.key.as_ref())
+    }
+
+    /// Load a store that has no passphrase, for callers that cannot prompt for one
+    ///
+    /// Any upgrade is returned rather than printed, since such callers may have no terminal to print to.
+    pub(crate) fn load_unencrypted(path: &Path) -> Result<(Config, Option<Upgrade>)> {
+        Config::open_with(path, |config_str| {
+            let (config, upgraded_from) = Config::parse(config_str)?;
+            if config.is_encrypted() {
+                return Err(anyhow::anyhow!("{} is encrypted, store it in plain text with `otpc decrypt`", path.display()));
+            }
+            Ok((config, upgraded_from))
+        })
+    }
+
+    /// Load and unlock a store, upgrading older layouts after backing them up
+    fn open(path: &Path, key: Option<&VaultKey>) -> Result<Config> {
+        let (config, upgrade) = Config::open_with(path, |config_str| Config::from_toml(config_str, key))?;
+        if let Some(upgrade) = upgrade {
+            eprintln!("{}", upgrade);
+        }
+        Ok(config)
+    }
+
+    fn open_with(
+        path: &Path,
+        load: impl FnOnce(&str) -> Result<(Config, Option<u32>)>,
+    ) -> Result<(Config, Option<Upgrade>)> {
+        let config_str = Zeroizing::new(fs::read_to_string(path)?);
+        let (mut config, upgraded_from) = load(&config_str)?;
+
+        let upgrade = match upgraded_from {
+            Some(version) => {
+                let backup = migrate::write_backup(path, version, &config_str)?;
+                config.save_to_file(path)?;
+                Some(Upgrade { from: version, backup })
+            }
+            None => None,
+        };
+        Ok((config, upgrade))
+    }
+
+    /// Parse a store, bringing it up to the current version in memory
+    fn parse(config_str: &str) -> Result<(Config, Option<u32>)> {
+        let mut table: toml::Table = toml::from_str(config_str)
+            .map_err(|e| anyhow::anyhow!(e))?;
+        let upgraded_from = migrate::upgrade(&mut table)?;
+        // Unknown keys are refused rather than silently dropped by the next save
+        let config = Config::deserialize(table).map_err(|e| anyhow::anyhow!("Invalid store: {}", e))?;
+        Ok((config, upgraded_from))
+    }
+
+    fn from_toml(config_str: &str, key: Option<&VaultKey>) -> Result<(Config, Option<u32>)> {
+        let (mut config, upgraded_from) = Config::parse(config_str)?;
+
+        if let Some(encryption) = &config.encryption {
+            let key = match key.filter(|key| encryption.verify(key)) {
+                Some(key) => key.clone(),
+                None => unlock(encryption)?,
+            };
+            config.decrypt_secrets(key)?;
+        }
+        Ok((config, upgraded_from))
+    }
+
+    pub(crate) fn is_encrypted(&self) -> bool {
+        self.encryption.is_some()
+    }
+
+    /// Encrypt the secrets with a new passphrase, or store them in plain text when `None`
+    pub(crate) fn set_passphrase(&mut self, passphrase: Option<&str>) -> Result<()> {
+        self.set_passphrase_with_cost(passphrase, DEFAULT_LOG_N)
+    }
+
+    fn set_passphrase_with_cost(&mut self, passphrase: Option<&str>, log_n: u8) -> Result<()> {
+        let Some(passphrase) = passphrase else {
+            self.encryption = None;
+            self.key = None;
+            return Ok(());
         };
-        Ok(home_dir.join(".otpc").join("config.toml"))
+
+        let salt = crypto::random_bytes::<16>()?;
+        let (r, p) = (8, 1);
+        let key = crypto::derive_key(passphrase, &salt, log_n, r, p)?;
+        let check = crypto::encrypt(&key, CHECK_PLAINTEXT)?;
+
+        self.encryption = Some(Encryption {
+            kdf: "scrypt".to_string(),
+            salt: BASE64.encode(salt),
+            log_n,
+            r,
+            p,
+            check: BASE64.encode(check),
+        });
+        self.key = Some(key);
+        Ok(())
+    }
+
+    /// Give every account without one a stable random ID
+    pub(crate) fn assign_ids(&mut self) -> Result<()> {
+        for account in self.all_accounts_mut().filter(|account| account.id.is_empty()) {
+            account.id = random_uuid()?;
+        }
+        Ok(())
     }
 
-    pub(crate) fn save_to_file(&self, path: &PathBuf) -> Result<()> {
-        let mut file = File::create(path)?;
-        let config_str = toml::to_string(self)?;
-        file.write_all(config_str.as_bytes())?;
+    /// Stored and trashed accounts alike, for work on every secret
+    fn all_accounts_mut(&mut self) -> impl Iterator<Item = &mut Account> {
+        self.accounts.iter_mut().chain(self.trash.iter_mut().map(|trashed| &mut trashed.account))
+    }
+
+    fn decrypt_secrets(&mut self, key: VaultKey) -> Result<()> {
+        for account in self.all_accounts_mut() {
+            unseal(&key, &mut account.secret)?;
+            for recovery in &mut account.recovery_codes {
+                unseal(&key, &mut recovery.code)?;
+            }
+        }
+        self.key = Some(key);
         Ok(())
     }
+
+    fn to_toml(&self) -> Result<String> {
+        let Some(key) = &self.key else {
+            return Ok(toml::to_string(self)?);
+        };
+
+        let mut sealed = Config {
+            version: self.version,
+            encryption: self.encryption.clone(),
+            settings: self.settings.clone(),
+            accounts: self.accounts.clone(),
+            trash: self.trash.clone(),
+            key: None,
+        };
+        for account in sealed.all_accounts_mut() {
+            seal(key, &mut account.secret)?;
+            for recovery in &mut account.recovery_codes {
+                seal(key, &mut recovery.code)?;
+            }
+        }
+        Ok(toml::to_string(&sealed)?)
+    }
+
+    pub(crate) fn save_to_file(&mut self, path: &Path) -> Result<()> {
+        self.version = migrate::CURRENT_VERSION;
+        self.assign_ids()?;
+        let config_str = Zeroizing::new(self.to_toml()?);
+        write_atomically(path, config_str.as_bytes())
+    }
+}
+
+/// Encrypt a secret in place with the store key
+fn seal(key: &VaultKey, secret: &mut SecretString) -> Result<()> {
+    let ciphertext = crypto::encrypt(key, secret.expose().as_bytes())?;
+    *secret = SecretString::new(format!("{}{}", ENCRYPTED_PREFIX, BASE64.encode(ciphertext)));
+    Ok(())
+}
+
+/// Decrypt a secret sealed by `seal`, leaving plain ones as they are
+fn unseal(key: &VaultKey, secret: &mut SecretString) -> Result<()> {
+    if let Some(sealed) = secret.expose().strip_prefix(ENCRYPTED_PREFIX) {
+        let plain = crypto::decrypt(key, &BASE64.decode(sealed)?)?;
+        *secret = SecretString::new(String::from_utf8(plain.to_vec())?);
+    }
+    Ok(())
+}
+
+/// Obtain the store key from the agent, or prompt for the passphrase
+fn unlock(encryption: &Encryption) -> Result<VaultKey> {
+    if let Some(key) = agent::request_key(&encryption.salt) {
+        if encryption.verify(&key) {
+            return Ok(key);
+        }
+    }
+
+    let passphrase = Zeroizing::new(rpassword::prompt_password("Passphrase: ")?);
+    let key = encryption.derive_key(&passphrase)?;
+
+    // Cache the key for later commands when an agent is running
+    agent::offer_key(&encryption.salt, &key);
+    Ok(key)
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Account {
+    /// Stable identifier used by the audit log, assigned when the account is first saved
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
     pub name: String,
-    pub secret: String,
+    pub secret: SecretString,
     pub issuer: String,
+    #[serde(default, rename = "type")]
+    pub otp_type: OtpType,
+    #[serde(default)]
+    pub algorithm: Algorithm,
+    #[serde(default = "default_digits")]
+    pub digits: u32,
+    #[serde(default = "default_period")]
+    pub period: u64,
+    /// Next HOTP counter value
+    #[serde(default)]
+    pub counter: u64,
+    /// Last TOTP time step a verifier accepted; codes from it or earlier steps are refused as replays
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_step: Option<u64>,
+    /// Wrong or replayed codes given to a verifier since the last accepted one
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub failures: u32,
+    /// Unix time until which a verifier refuses every code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked_until: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub favorite: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Codes the service handed out for when the authenticator is lost, sealed like the secret
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recovery_codes: Vec<RecoveryCode>,
 }
 
-fn load_config() -> Result<Config> {
-    let config_path = Config::get_path()?;
-    let path = config_path.parent().ok_or(anyhow::anyhow!("Cannot found user directory"))?;
-    fs::create_dir_all(path)?;
-
-    let mut config_file = match File::open(&config_path) {
-        Ok(file) => file,
-        Err(e) => {
-            if e.kind() == io::ErrorKind::NotFound {
-                let mut new_config_file = OpenOptions::new()
-                    .create(true)
-                    .write(true)
-                    .read(true)
-                    .truncate(true)
-                    .open(&config_path)?;
-
-                #[cfg(unix)]
-                new_config_file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
-
-                let new_config = Config { accounts: vec![] };
-                let new_config_str = toml::to_string(&new_config).map_err(|e| anyhow::anyhow!(e))?;
-                new_config_file.write_all(new_config_str.as_bytes())?;
-
-                new_config_file.seek(SeekFrom::Start(0))?;
-                new_config_file
-            } else {
-                return Err(anyhow::anyhow!(e));
-            }
+/// A single-use recovery code, kept after use so it is not mistaken for a fresh one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RecoveryCode {
+    pub code: SecretString,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub used: bool,
+}
+
+impl Account {
+    /// Create a TOTP account with the default parameters (SHA1, 6 digits, 30 seconds)
+    pub fn new(name: String, secret: SecretString, issuer: String) -> Self {
+        Self {
+            id: String::new(),
+            name,
+            secret,
+            issuer,
+            otp_type: OtpType::Totp,
+            algorithm: Algorithm::Sha1,
+            digits: default_digits(),
+            period: default_period(),
+            counter: 0,
+            last_step: None,
+            failures: 0,
+            locked_until: None,
+            group: None,
+            tags: Vec::new(),
+            favorite: false,
+            note: None,
+            recovery_codes: Vec::new(),
+        }
+    }
+
+    /// Whether both accounts hold the same seed, ignoring case, spaces and padding
+    pub(crate) fn has_same_secret(&self, other: &Account) -> bool {
+        let normalize = |secret: &SecretString| -> Zeroizing<String> {
+            Zeroizing::new(secret.expose()
+                .chars()
+                .filter(|c| *c != ' ' && *c != '=')
+                .map(|c| c.to_ascii_uppercase())
+                .collect())
+        };
+        constant_time_eq(normalize(&self.secret).as_bytes(), normalize(&other.secret).as_bytes())
+    }
+
+    /// Check that the secret and OTP parameters can produce codes
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            return Err(anyhow::anyhow!("Account name cannot be empty"));
+        }
+        let secret = self.secret.expose().trim_end_matches('=');
+        let is_base32 = secret.chars()
+            .filter(|c| *c != ' ')
+            .all(|c| matches!(c.to_ascii_uppercase(), 'A'..='Z' | '2'..='7'));
+        if secret.trim().is_empty() || !is_base32 {
+            return Err(anyhow::anyhow!("Secret of '{}' is not valid Base32", self.name));
+        }
+        if !otp::DIGITS.contains(&self.digits) {
+            return Err(anyhow::anyhow!(
+                "Digits of '{}' must be between {} and {}", self.name, otp::DIGITS.start(), otp::DIGITS.end()
+            ));
+        }
+        if self.period == 0 {
+            return Err(anyhow::anyhow!("Period of '{}' must be greater than zero", self.name));
         }
+        Ok(())
+    }
+}
+
+fn default_digits() -> u32 {
+    6
+}
+
+fn default_period() -> u64 {
+    30
+}
+
+/// Write a file readable only by the current user, refusing to follow a symlink at `path`
+pub(crate) fn write_private_file(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.create(true).write(true).truncate(true);
+    let mut file = open_private_file(&mut options, path)?;
+    file.write_all(contents)?;
+    Ok(())
+}
+
+/// Create a new file readable only by the current user, refusing to reuse an existing file or follow a symlink
+fn create_private_file(path: &Path) -> io::Result<fs::File> {
+    open_private_file(OpenOptions::new().create_new(true).write(true), path)
+}
+
+fn open_private_file(options: &mut OpenOptions, path: &Path) -> io::Result<fs::File> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt as _;
+        options.mode(0o600).custom_flags(libc::O_NOFOLLOW);
+    }
+
+    let file = options.open(path)?;
+    // The mode above is filtered through the umask and ignored for existing files; make it exact on the descriptor
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    Ok(file)
+}
+
+/// Replace a private file so readers never see it half-written: write a sibling file, then rename it over the old one
+///
+/// The sibling gets a random name and is created exclusively, so a symlink planted in the directory is
+/// never written through, and `rename` replaces a symlink at `path` instead of following it.
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let file_name = path.file_name().ok_or(anyhow::anyhow!("Invalid file path {}", path.display()))?;
+    let suffix = crate::formats::hex_encode(&crypto::random_bytes::<8>()?);
+    let temp = path.with_file_name(format!(".{}.{}.tmp", file_name.to_string_lossy(), suffix));
+
+    let mut file = create_private_file(&temp)?;
+    keep_owner(&file, path);
+    let written = file.write_all(contents)
+        .and_then(|_| file.sync_all())
+        .and_then(|_| fs::rename(&temp, path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// Give the new file the owner of the one it replaces, e.g. when root updates a user's store; best effort
+#[cfg(unix)]
+fn keep_owner(file: &fs::File, path: &Path) {
+    use std::os::unix::fs::MetadataExt as _;
+    if let Some(metadata) = fs::symlink_metadata(path).ok().filter(|metadata| metadata.is_file()) {
+        let _ = std::os::unix::fs::fchown(file, Some(metadata.uid()), Some(metadata.gid()));
+    }
+}
+
+#[cfg(not(unix))]
+fn keep_owner(_file: &fs::File, _path: &Path) {}
+
+/// Exclusive lock on `<store>.lock`, released when dropped
+///
+/// Held from loading a store to saving it wherever a code is verified or a HOTP counter moves, so
+/// `verify`, `code`, `serve` and the PAM module never accept the same code twice or lose each other's state.
+pub(crate) struct StoreLock {
+    #[cfg_attr(not(unix), allow(dead_code))]
+    file: fs::File,
+}
+
+pub(crate) fn lock_store(store: &Path) -> Result<StoreLock> {
+    let mut name = store.as_os_str().to_owned();
+    name.push(".lock");
+    let mut options = OpenOptions::new();
+    options.create(true).truncate(false).write(true);
+    let file = open_private_file(&mut options, Path::new(&name))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::AsRawFd as _;
+        // SAFETY: the descriptor belongs to `file`, which outlives the call
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+    Ok(StoreLock { file })
+}
+
+#[cfg(unix)]
+impl Drop for StoreLock {
+    fn drop(&mut self) {
+        use std::os::unix::io::AsRawFd as _;
+        // SAFETY: the descriptor is still open; closing it would release the lock as well
+        unsafe { libc::flock(self.file.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
+
+/// Use `path` for the store instead of the default location
+pub(crate) fn set_path(path: PathBuf) {
+    let _ = PATH_OVERRIDE.set(path);
+}
+
+/// Path of the default profile's store: `--config`/`OTPC_CONFIG`, or `otpc/config.toml` in the XDG config directory
+pub(crate) fn base_path() -> Result<PathBuf> {
+    if let Some(path) = PATH_OVERRIDE.get() {
+        return Ok(path.clone());
+    }
+    default_path(env::var_os("XDG_CONFIG_HOME").map(PathBuf::from), home_dir())
+}
+
+fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+/// `$XDG_CONFIG_HOME/otpc/config.toml`, falling back to `~/.config` (or `%APPDATA%` on Windows)
+fn default_path(xdg_config_home: Option<PathBuf>, home: Option<PathBuf>) -> Result<PathBuf> {
+    // The XDG spec says relative paths are invalid and must be ignored
+    let config_home = match xdg_config_home.filter(|dir| dir.is_absolute()) {
+        Some(dir) => dir,
+        None => match env::var_os("APPDATA").filter(|_| cfg!(windows)) {
+            Some(appdata) => PathBuf::from(appdata),
+            None => home.ok_or(anyhow::anyhow!("Cannot found user directory"))?.join(".config"),
+        },
     };
+    Ok(config_home.join("otpc").join("config.toml"))
+}
+
+/// Move a store from `legacy` (the old `~/.otpc`) to `dir` unless `dir` already exists
+fn migrate_legacy_store(legacy: &Path, dir: &Path) -> Result<bool> {
+    if dir.exists() || !legacy.join("config.toml").is_file() {
+        return Ok(false);
+    }
+    if let Some(parent) = dir.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if fs::rename(legacy, dir).is_err() {
+        // Different file systems: copy the files and leave the old directory in place
+        fs::create_dir_all(dir)?;
+        for entry in fs::read_dir(legacy)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                let contents = Zeroizing::new(fs::read(entry.path())?);
+                write_private_file(&dir.join(entry.file_name()), &contents)?;
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Resolve the store path, moving an old `~/.otpc` store to the default location first
+pub(crate) fn prepare_path() -> Result<PathBuf> {
+    if PATH_OVERRIDE.get().is_none() {
+        let base = base_path()?;
+        let dir = base.parent().ok_or(anyhow::anyhow!("Cannot found user directory"))?;
+        if let Some(legacy) = home_dir().map(|home| home.join(".otpc")) {
+            if migrate_legacy_store(&legacy, dir)? {
+                eprintln!("Moved the account store from {} to {}", legacy.display(), dir.display());
+            }
+        }
+    }
+
+    let config_path = Config::get_path()?;
+    let active = profile::active()?;
+    if active != profile::DEFAULT_PROFILE && !config_path.is_file() {
+        return Err(anyhow::anyhow!("Profile '{}' does not exist, create it with `otpc profile create {}`", active, active));
+    }
+    let dir = config_path.parent().ok_or(anyhow::anyhow!("Cannot found user directory"))?;
+    fs::create_dir_all(dir)?;
+    Ok(config_path)
+}
 
-    let mut config_str = String::new();
-    config_file.read_to_string(&mut config_str)?;
+fn load_config() -> Result<Config> {
+    let config_path = prepare_path()?;
 
-    let config: Config = toml::from_str(&config_str)
-        .map_err(|e| anyhow::anyhow!(e))?;
-    
-    Ok(config)
+    if let Err(e) = fs::metadata(&config_path) {
+        if e.kind() != io::ErrorKind::NotFound {
+            return Err(anyhow::anyhow!(e));
+        }
+        let new_config = Config::new(vec![]);
+        let new_config_str = toml::to_string(&new_config).map_err(|e| anyhow::anyhow!(e))?;
+        write_private_file(&config_path, new_config_str.as_bytes())?;
+    }
+
+    Config::open(&config_path, None)
 }
 
 pub(crate) fn get_config() -> Result<&'static Mutex<Config>> {
-    Ok(&CONFIG)
-}
\ No newline at end of file
+    if let Some(config) = CONFIG.get() {
+        return Ok(config);
+    }
+
+    let config = load_config()?;
+    Ok(CONFIG.get_or_init(|| Mutex::new(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_config() -> Config {
+        Config::new(vec![Account::new(
+            "alice".to_string(),
+            SecretString::from("JBSWY3DPEHPK3PXP"),
+            "Example".to_string(),
+        )])
+    }
+
+    #[test]
+    fn test_encrypted_roundtrip() {
+        let mut config = sample_config();
+        config.set_passphrase_with_cost(Some("hunter2"), 4).unwrap();
+        config.accounts[0].recovery_codes.push(RecoveryCode { code: SecretString::from("k7m2p-q9x4t"), used: true });
+
+        config.trash.push(TrashedAccount {
+            deleted_at: 1,
+            account: Account::new("bob".to_string(), SecretString::from("GEZDGNBVGY3TQOJQ"), "host".to_string()),
+        });
+
+        let sealed = config.to_toml().unwrap();
+        assert!(!sealed.contains("JBSWY3DPEHPK3PXP"));
+        assert!(!sealed.contains("GEZDGNBVGY3TQOJQ"));
+        assert!(!sealed.contains("k7m2p-q9x4t"));
+        assert!(sealed.contains("name = \"alice\""));
+
+        let mut loaded: Config = toml::from_str(&sealed).unwrap();
+        let encryption = loaded.encryption.clone().unwrap();
+        assert!(encryption.derive_key("wrong").is_err());
+
+        let key = encryption.derive_key("hunter2").unwrap();
+        loaded.decrypt_secrets(key).unwrap();
+        assert_eq!(loaded.accounts[0].secret.expose(), "JBSWY3DPEHPK3PXP");
+        assert_eq!(loaded.trash[0].account.secret.expose(), "GEZDGNBVGY3TQOJQ");
+        assert_eq!(loaded.accounts[0].recovery_codes, config.accounts[0].recovery_codes);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_default_path() {
+        let home = Some(PathBuf::from("/home/alice"));
+        let xdg = default_path(Some(PathBuf::from("/xdg")), home.clone()).unwrap();
+        assert_eq!(xdg, Path::new("/xdg/otpc/config.toml"));
+
+        let relative = default_path(Some(PathBuf::from("relative")), home).unwrap();
+        assert_eq!(relative, Path::new("/home/alice/.config/otpc/config.toml"));
+    }
+
+    #[test]
+    fn test_migrate_legacy_store() {
+        let root = env::temp_dir().join(format!("otpc-migrate-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let (legacy, dir) = (root.join(".otpc"), root.join(".config").join("otpc"));
+        fs::create_dir_all(&legacy).unwrap();
+        fs::write(legacy.join("config.toml"), "accounts = []\n").unwrap();
+
+        assert!(migrate_legacy_store(&legacy, &dir).unwrap());
+        assert!(dir.join("config.toml").is_file());
+        assert!(!legacy.exists());
+        assert!(!migrate_legacy_store(&legacy, &dir).unwrap());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_keeps_backup() {
+        let dir = env::temp_dir().join(format!("otpc-upgrade-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        let original = "[[accounts]]\nname = \"alice\"\nsecret = \"JBSWY3DPEHPK3PXP\"\nissuer = \"Example\"\n";
+        fs::write(&path, original).unwrap();
+
+        let config = Config::load_from_file(&path).unwrap();
+        assert_eq!(config.version, migrate::CURRENT_VERSION);
+        assert!(!config.accounts[0].id.is_empty());
+        assert_eq!(fs::read_to_string(dir.join("config.toml.v0.bak")).unwrap(), original);
+
+        // The upgraded file loads as is, keeping the IDs it was given
+        let reloaded = Config::load_from_file(&path).unwrap();
+        assert_eq!(reloaded.accounts[0].id, config.accounts[0].id);
+
+        fs::write(&path, format!("version = {}\naccounts = []\n", migrate::CURRENT_VERSION + 1)).unwrap();
+        assert!(Config::load_from_file(&path).is_err());
+        assert!(Config::load_metadata(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_every_older_version() {
+        let dir = env::temp_dir().join(format!("otpc-upgrade-versions-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+
+        for version in 1..migrate::CURRENT_VERSION {
+            let original = format!(
+                "version = {}\n\n[[accounts]]\nid = \"1234\"\nname = \"alice\"\nsecret = \"JBSWY3DPEHPK3PXP\"\nissuer = \"Example\"\n",
+                version,
+            );
+            fs::write(&path, &original).unwrap();
+
+            let config = Config::load_from_file(&path).unwrap();
+            assert_eq!((config.version, config.accounts[0].id.as_str()), (migrate::CURRENT_VERSION, "1234"));
+            let backup = dir.join(format!("config.toml.v{}.bak", version));
+            assert_eq!(fs::read_to_string(backup).unwrap(), original);
+            assert_eq!(Config::load_metadata(&path).unwrap().version, migrate::CURRENT_VERSION);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        let dir = env::temp_dir().join(format!("otpc-unknown-keys-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+
+        let account = "[[accounts]]\nname = \"alice\"\nsecret = \"JBSWY3DPEHPK3PXP\"\nissuer = \"Example\"\n";
+        // A top-level key, a setting and an account field, each one unknown
+        let documents = [
+            format!("colour = \"blue\"\n{}", account),
+            format!("[settings]\nlock_after = 5\n\n{}", account),
+            format!("{}issuer_url = \"https://example.com\"\n", account),
+        ];
+        for document in documents {
+            let original = format!("version = {}\n{}", migrate::CURRENT_VERSION, document);
+            fs::write(&path, &original).unwrap();
+
+            let err = Config::load_metadata(&path).err().unwrap().to_string();
+            assert!(err.contains("unknown field"), "{}", err);
+            assert!(Config::load_from_file(&path).is_err());
+            // The file is left exactly as it was
+            assert_eq!(fs::read_to_string(&path).unwrap(), original);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_remove_passphrase() {
+        let mut config = sample_config();
+        config.set_passphrase_with_cost(Some("hunter2"), 4).unwrap();
+        config.set_passphrase(None).unwrap();
+
+        let plain = config.to_toml().unwrap();
+        assert!(!plain.contains("encryption"));
+        assert!(plain.contains("JBSWY3DPEHPK3PXP"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_store_lock_is_exclusive() {
+        use std::sync::mpsc;
+        use std::time::Duration;
+
+        let dir = env::temp_dir().join(format!("otpc-lock-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        let first = lock_store(&path).unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let waiting = std::thread::spawn({
+            let path = path.clone();
+            move || {
+                let _second = lock_store(&path).unwrap();
+                sender.send(()).unwrap();
+            }
+        });
+        assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+        drop(first);
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        waiting.join().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
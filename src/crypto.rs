@@ -0,0 +1,283 @@
+#[cfg(unix)]
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(unix)]
+use std::sync::Mutex;
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::Result;
+use hmac::Hmac;
+use sha1::Sha1;
+use sha2::Sha256;
+use zeroize::{Zeroize, Zeroizing};
+
+/// Length of an AES-256 key in bytes
+pub(crate) const KEY_LEN: usize = 32;
+/// Length of an AES-GCM nonce in bytes
+pub(crate) const NONCE_LEN: usize = 12;
+
+/// Symmetric key protecting the account store
+///
+/// The key lives in its own heap allocation which is locked from swap where
+/// the platform allows it and wiped on drop.
+pub(crate) struct VaultKey(Box<[u8; KEY_LEN]>);
+
+impl VaultKey {
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != KEY_LEN {
+            return Err(anyhow::anyhow!("Invalid key length"));
+        }
+
+        let mut key = Box::new([0u8; KEY_LEN]);
+        lock_memory(key.as_ptr(), KEY_LEN);
+        key.copy_from_slice(bytes);
+        Ok(Self(key))
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8; KEY_LEN] {
+        &self.0
+    }
+}
+
+impl Clone for VaultKey {
+    fn clone(&self) -> Self {
+        Self::from_bytes(self.as_bytes()).expect("key length is fixed")
+    }
+}
+
+impl Drop for VaultKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+        unlock_memory(self.0.as_ptr(), KEY_LEN);
+    }
+}
+
+impl fmt::Debug for VaultKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "VaultKey(<redacted>)")
+    }
+}
+
+/// Pages currently held by `lock_memory`, keyed by page address, with the number of regions on each
+///
+/// `mlock` does not nest: a single `munlock` releases the page for every
+/// allocation sharing it. Small secrets routinely share a page, so the lock
+/// is only released once the last region on a page is gone.
+#[cfg(unix)]
+static LOCKED_PAGES: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+#[cfg(unix)]
+fn page_size() -> usize {
+    // SAFETY: sysconf has no preconditions
+    let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    usize::try_from(size).ok().filter(|&size| size > 0).unwrap_or(4096)
+}
+
+/// Addresses of the pages overlapping `len` bytes at `addr`
+#[cfg(unix)]
+fn pages(addr: usize, len: usize, page: usize) -> impl Iterator<Item = usize> {
+    let first = addr - addr % page;
+    let last = addr.saturating_add(len.max(1) - 1);
+    (first..=last).step_by(page)
+}
+
+/// Count a region against its pages and return the pages that became locked
+#[cfg(unix)]
+fn retain_pages(locked: &mut BTreeMap<usize, usize>, addr: usize, len: usize, page: usize) -> Vec<usize> {
+    pages(addr, len, page)
+        .filter(|&start| {
+            let count = locked.entry(start).or_insert(0);
+            *count += 1;
+            *count == 1
+        })
+        .collect()
+}
+
+/// Drop a region from its pages and return the pages no longer used by any region
+#[cfg(unix)]
+fn release_pages(locked: &mut BTreeMap<usize, usize>, addr: usize, len: usize, page: usize) -> Vec<usize> {
+    pages(addr, len, page)
+        .filter(|&start| match locked.get_mut(&start) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                false
+            }
+            Some(_) => {
+                locked.remove(&start);
+                true
+            }
+            None => false,
+        })
+        .collect()
+}
+
+/// Lock a memory region so it is never written to swap (best effort)
+#[cfg(unix)]
+pub(crate) fn lock_memory(ptr: *const u8, len: usize) {
+    if len == 0 {
+        return;
+    }
+    let page = page_size();
+    let mut locked = LOCKED_PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    for start in retain_pages(&mut locked, ptr as usize, len, page) {
+        // SAFETY: the page belongs to a region the caller owns; mlock does not touch its contents
+        unsafe { libc::mlock(start as *const libc::c_void, page) };
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn lock_memory(_ptr: *const u8, _len: usize) {}
+
+/// Release a region previously passed to `lock_memory`
+///
+/// Pages still shared with another locked region stay locked.
+#[cfg(unix)]
+pub(crate) fn unlock_memory(ptr: *const u8, len: usize) {
+    if len == 0 {
+        return;
+    }
+    let page = page_size();
+    let mut locked = LOCKED_PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    for start in release_pages(&mut locked, ptr as usize, len, page) {
+        // SAFETY: see `lock_memory`; no other locked region uses this page any more
+        unsafe { libc::munlock(start as *const libc::c_void, page) };
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn unlock_memory(_ptr: *const u8, _len: usize) {}
+
+/// Fill a buffer from the operating system RNG
+pub(crate) fn random_bytes<const N: usize>() -> Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    getrandom::getrandom(&mut buf).map_err(|e| anyhow::anyhow!("Failed to gather randomness: {}", e))?;
+    Ok(buf)
+}
+
+/// Derive a key from a passphrase with scrypt (N = 2^log_n)
+pub(crate) fn derive_key(passphrase: &str, salt: &[u8], log_n: u8, r: u32, p: u32) -> Result<VaultKey> {
+    let params = scrypt::Params::new(log_n, r, p, KEY_LEN)
+        .map_err(|e| anyhow::anyhow!("Invalid scrypt parameters: {}", e))?;
+
+    let mut key = Zeroizing::new([0u8; KEY_LEN]);
+    scrypt::scrypt(passphrase.as_bytes(), salt, &params, key.as_mut())
+        .map_err(|e| anyhow::anyhow!("Key derivation failed: {}", e))?;
+    VaultKey::from_bytes(key.as_ref())
+}
+
+/// Hash function used as the PBKDF2 pseudo-random function
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Pbkdf2Hash {
+    Sha1,
+    Sha256,
+}
+
+/// Derive a key from a password with PBKDF2-HMAC
+pub(crate) fn derive_key_pbkdf2(password: &str, salt: &[u8], rounds: u32, hash: Pbkdf2Hash) -> Result<VaultKey> {
+    let mut key = Zeroizing::new([0u8; KEY_LEN]);
+    let result = match hash {
+        Pbkdf2Hash::Sha1 => pbkdf2::pbkdf2::<Hmac<Sha1>>(password.as_bytes(), salt, rounds, key.as_mut()),
+        Pbkdf2Hash::Sha256 => pbkdf2::pbkdf2::<Hmac<Sha256>>(password.as_bytes(), salt, rounds, key.as_mut()),
+    };
+    result.map_err(|e| anyhow::anyhow!("Key derivation failed: {}", e))?;
+    VaultKey::from_bytes(key.as_ref())
+}
+
+/// Encrypt with AES-256-GCM, returning `nonce || ciphertext || tag`
+pub(crate) fn encrypt(key: &VaultKey, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let nonce = random_bytes::<NONCE_LEN>()?;
+    let ciphertext = encrypt_with_nonce(key, &nonce, plaintext)?;
+
+    let mut output = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+    output.extend_from_slice(&nonce);
+    output.extend_from_slice(&ciphertext);
+    Ok(output)
+}
+
+/// Decrypt data produced by `encrypt`
+pub(crate) fn decrypt(key: &VaultKey, data: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    if data.len() < NONCE_LEN {
+        return Err(anyhow::anyhow!("Encrypted data is truncated"));
+    }
+
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    decrypt_with_nonce(key, nonce, ciphertext)
+}
+
+/// Encrypt with AES-256-GCM under a caller-chosen nonce, returning `ciphertext || tag`
+pub(crate) fn encrypt_with_nonce(key: &VaultKey, nonce: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    if nonce.len() != NONCE_LEN {
+        return Err(anyhow::anyhow!("Invalid nonce length"));
+    }
+
+    let cipher = Aes256Gcm::new_from_slice(key.as_bytes())
+        .map_err(|_| anyhow::anyhow!("Invalid key length"))?;
+    cipher.encrypt(Nonce::from_slice(nonce), plaintext)
+        .map_err(|_| anyhow::anyhow!("Encryption failed"))
+}
+
+/// Decrypt `ciphertext || tag` produced with the given nonce
+pub(crate) fn decrypt_with_nonce(key: &VaultKey, nonce: &[u8], ciphertext: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    if nonce.len() != NONCE_LEN {
+        return Err(anyhow::anyhow!("Invalid nonce length"));
+    }
+
+    let cipher = Aes256Gcm::new_from_slice(key.as_bytes())
+        .map_err(|_| anyhow::anyhow!("Invalid key length"))?;
+    cipher.decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map(Zeroizing::new)
+        .map_err(|_| anyhow::anyhow!("Decryption failed (wrong passphrase or corrupted data)"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shared_pages_stay_locked() {
+        let mut locked = BTreeMap::new();
+
+        // Two small regions on one page, the second spilling onto the next
+        assert_eq!(retain_pages(&mut locked, 4100, 16, 4096), vec![4096]);
+        assert_eq!(retain_pages(&mut locked, 8180, 32, 4096), vec![8192]);
+        assert_eq!(locked.get(&4096), Some(&2));
+
+        assert!(release_pages(&mut locked, 4100, 16, 4096).is_empty());
+        assert_eq!(release_pages(&mut locked, 8180, 32, 4096), vec![4096, 8192]);
+        assert!(locked.is_empty());
+
+        // Releasing a region that was never locked is harmless
+        assert!(release_pages(&mut locked, 4100, 16, 4096).is_empty());
+    }
+
+    #[test]
+    fn test_encrypt_roundtrip() {
+        let key = derive_key("correct horse", b"0123456789abcdef", 4, 8, 1).unwrap();
+        let data = encrypt(&key, b"JBSWY3DPEHPK3PXP").unwrap();
+        assert_eq!(decrypt(&key, &data).unwrap().as_slice(), b"JBSWY3DPEHPK3PXP");
+
+        let wrong = derive_key("battery staple", b"0123456789abcdef", 4, 8, 1).unwrap();
+        assert!(decrypt(&wrong, &data).is_err());
+    }
+
+    #[test]
+    fn test_pbkdf2_rfc6070() {
+        // RFC 6070 test vector (first 20 bytes of the derived key)
+        let key = derive_key_pbkdf2("password", b"salt", 4096, Pbkdf2Hash::Sha1).unwrap();
+        assert_eq!(
+            key.as_bytes()[..20],
+            [0x4b, 0x00, 0x79, 0x01, 0xb7, 0x65, 0x48, 0x9a, 0xbe, 0xad,
+             0x49, 0xd9, 0x26, 0xf7, 0x21, 0xd0, 0x65, 0xa4, 0x29, 0xc1],
+        );
+    }
+
+    #[test]
+    fn test_derive_key_is_deterministic() {
+        let a = derive_key("passphrase", b"salt", 4, 8, 1).unwrap();
+        let b = derive_key("passphrase", b"salt", 4, 8, 1).unwrap();
+        assert_eq!(a.as_bytes(), b.as_bytes());
+        assert_eq!(format!("{:?}", a), "VaultKey(<redacted>)");
+    }
+}
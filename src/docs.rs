@@ -0,0 +1,192 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use clap::{Arg, CommandFactory as _};
+use roff::{bold, roman, Roff};
+
+use crate::commands::Cli;
+
+/// Write one roff man page per command, e.g. `otpc.1` and `otpc-code.1`
+pub(crate) fn generate_man(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let mut cli = Cli::command().disable_help_subcommand(true);
+    cli.build();
+    write_man(cli, dir)
+}
+
+fn write_man(command: clap::Command, dir: &Path) -> Result<()> {
+    for sub in command.get_subcommands().filter(|sub| !sub.is_hide_set()).cloned() {
+        write_man(sub, dir)?;
+    }
+
+    let mut page = Vec::new();
+    let man = clap_mangen::Man::new(command.clone());
+    man.render(&mut page)?;
+    // clap_mangen leaves environment variables out of the options
+    page.extend_from_slice(environment_section(&command).as_bytes());
+    fs::write(dir.join(man.get_filename()), page)?;
+    Ok(())
+}
+
+fn environment_section(command: &clap::Command) -> String {
+    let variables: Vec<&Arg> = command.get_arguments()
+        .filter(|arg| !arg.is_hide_set() && arg.get_env().is_some())
+        .collect();
+    if variables.is_empty() {
+        return String::new();
+    }
+
+    let mut roff = Roff::new();
+    roff.control("SH", ["ENVIRONMENT"]);
+    for arg in variables {
+        let env = arg.get_env().unwrap_or_default().to_string_lossy().into_owned();
+        let help = arg.get_help().map(ToString::to_string).unwrap_or_default();
+        let flag = arg.get_long().map(|long| format!(" (same as --{})", long)).unwrap_or_default();
+        roff.control("TP", []);
+        roff.text([bold(env)]);
+        roff.text([roman(format!("{}{}", help, flag))]);
+    }
+    roff.to_roff()
+}
+
+/// Markdown reference of every command with its arguments, defaults and environment variables
+pub(crate) fn markdown() -> String {
+    let mut cli = Cli::command().disable_help_subcommand(true);
+    cli.build();
+
+    let mut out = String::new();
+    let _ = writeln!(out, "# Command-line reference\n");
+    let _ = writeln!(out, "<!-- Generated by `otpc gen-docs`, do not edit by hand -->\n");
+    write_command(&mut out, &mut cli, "otpc", true);
+    out
+}
+
+fn write_command(out: &mut String, command: &mut clap::Command, path: &str, root: bool) {
+    let usage = command.render_usage().to_string();
+    let usage = usage.strip_prefix("Usage: ").unwrap_or(&usage).to_string();
+
+    let _ = writeln!(out, "## `{}`\n", path);
+    if let Some(about) = command.get_long_about().or(command.get_about()) {
+        let _ = writeln!(out, "{}\n", about);
+    }
+    let _ = writeln!(out, "**Usage:** `{}`\n", usage);
+
+    let subcommands: Vec<&clap::Command> = command.get_subcommands().filter(|sub| !sub.is_hide_set()).collect();
+    if !subcommands.is_empty() {
+        let _ = writeln!(out, "**Commands:**\n");
+        for sub in &subcommands {
+            let about = sub.get_about().map(ToString::to_string).unwrap_or_default();
+            let _ = writeln!(out, "* [`{}`](#{}): {}", sub.get_name(), anchor(&format!("{} {}", path, sub.get_name())), about);
+        }
+        out.push('\n');
+    }
+
+    // Global options are listed once, on `otpc` itself
+    let arguments: Vec<&Arg> = command.get_arguments()
+        .filter(|arg| !arg.is_hide_set() && !matches!(arg.get_id().as_str(), "help" | "version"))
+        .filter(|arg| root || !arg.is_global_set())
+        .collect();
+    if !arguments.is_empty() {
+        let _ = writeln!(out, "**Arguments:**\n");
+        for arg in arguments {
+            let _ = writeln!(out, "* {}", describe(arg));
+        }
+        out.push('\n');
+    }
+
+    let names: Vec<String> = subcommands.iter().map(|sub| sub.get_name().to_string()).collect();
+    for name in names {
+        if let Some(sub) = command.find_subcommand_mut(&name) {
+            write_command(out, sub, &format!("{} {}", path, name), false);
+        }
+    }
+}
+
+/// One bullet: the flag or value, its help, then defaults, choices and environment variable
+fn describe(arg: &Arg) -> String {
+    let value_names: Vec<String> = match arg.get_value_names() {
+        Some(names) => names.iter().map(ToString::to_string).collect(),
+        None => vec![arg.get_id().as_str().to_uppercase()],
+    };
+    let values = value_names.iter().map(|name| format!("<{}>", name)).collect::<Vec<_>>().join(" ");
+
+    let mut line = if arg.is_positional() {
+        let many = if arg.get_num_args().is_some_and(|range| range.max_values() > 1) { "..." } else { "" };
+        format!("`{}{}`", values, many)
+    } else {
+        let mut flags = Vec::new();
+        if let Some(short) = arg.get_short() {
+            flags.push(format!("-{}", short));
+        }
+        if let Some(long) = arg.get_long() {
+            flags.push(format!("--{}", long));
+        }
+        match arg.get_action().takes_values() {
+            true => format!("`{} {}`", flags.join(", "), values),
+            false => format!("`{}`", flags.join(", ")),
+        }
+    };
+
+    if let Some(help) = arg.get_help() {
+        let _ = write!(line, ": {}", help);
+    }
+    if arg.is_required_set() {
+        line.push_str(" (required)");
+    }
+
+    let defaults: Vec<String> = arg.get_default_values().iter().map(|value| value.to_string_lossy().into_owned()).collect();
+    if !defaults.is_empty() && arg.get_action().takes_values() {
+        let _ = write!(line, " [default: `{}`]", defaults.join(","));
+    }
+    let choices: Vec<String> = arg.get_possible_values().iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| format!("`{}`", value.get_name()))
+        .collect();
+    if !choices.is_empty() && arg.get_action().takes_values() {
+        let _ = write!(line, " [possible values: {}]", choices.join(", "));
+    }
+    if let Some(env) = arg.get_env() {
+        let _ = write!(line, " [env: `{}`]", env.to_string_lossy());
+    }
+    line
+}
+
+/// GitHub's heading anchor for "`otpc tag add`"
+fn anchor(path: &str) -> String {
+    path.replace(' ', "-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Where the generated markdown reference is checked in, relative to the crate root
+    const REFERENCE_PATH: &str = "docs/cli.md";
+
+    #[test]
+    fn test_reference_is_current() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(REFERENCE_PATH);
+        let checked_in = fs::read_to_string(&path).unwrap_or_default().replace("\r\n", "\n");
+        assert!(
+            checked_in == markdown(),
+            "{} is out of date, regenerate it with `otpc gen-docs > {}`", REFERENCE_PATH, REFERENCE_PATH,
+        );
+    }
+
+    #[test]
+    fn test_man_pages() {
+        let dir = std::env::temp_dir().join(format!("otpc-man-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        generate_man(&dir).unwrap();
+
+        let code = fs::read_to_string(dir.join("otpc-code.1")).unwrap();
+        assert!(code.contains("\\-\\-account"));
+        assert!(fs::read_to_string(dir.join("otpc.1")).unwrap().contains("OTPC_CONFIG"));
+        assert!(dir.join("otpc.1").is_file());
+        assert!(dir.join("otpc-tag-add.1").is_file());
+        assert!(!dir.join("otpc-__complete-accounts.1").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+use std::fmt;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::config::{Account, Config};
+
+/// Secrets shorter than this many bits are below the RFC 4226 minimum
+const MIN_SECRET_BITS: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Severity {
+    /// The account cannot produce codes
+    Error,
+    /// The account works but deserves a look
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Finding {
+    fn error(message: String) -> Self {
+        Self { severity: Severity::Error, message }
+    }
+
+    fn warning(message: String) -> Self {
+        Self { severity: Severity::Warning, message }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}", label, self.message)
+    }
+}
+
+/// Check every stored account for problems
+pub(crate) fn check(config: &Config) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    for (i, account) in config.accounts.iter().enumerate() {
+        if let Err(e) = account.validate() {
+            findings.push(Finding::error(e.to_string()));
+            continue;
+        }
+        findings.extend(check_parameters(account));
+
+        let earlier = &config.accounts[..i];
+        if earlier.iter().any(|other| other.name == account.name) {
+            findings.push(Finding::warning(format!(
+                "More than one account is named '{}', commands taking a name act on the first",
+                account.name,
+            )));
+        }
+        if let Some(other) = earlier.iter().find(|other| other.validate().is_ok() && other.has_same_secret(account)) {
+            findings.push(Finding::warning(format!(
+                "'{}' has the same secret as '{}'",
+                account.name, other.name,
+            )));
+        }
+    }
+    findings
+}
+
+fn check_parameters(account: &Account) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    let length = account.secret.expose().chars().filter(|c| *c != ' ' && *c != '=').count();
+    if length * 5 < MIN_SECRET_BITS {
+        findings.push(Finding::warning(format!(
+            "Secret of '{}' is only {} bits, at least {} are recommended",
+            account.name, length * 5, MIN_SECRET_BITS,
+        )));
+    }
+    if !(6..=8).contains(&account.digits) {
+        findings.push(Finding::warning(format!(
+            "'{}' uses {} digits, most services expect 6 to 8",
+            account.name, account.digits,
+        )));
+    }
+    findings
+}
+
+/// Warn when the store can be read by other users
+#[cfg(unix)]
+pub(crate) fn check_permissions(path: &Path) -> Result<Option<Finding>> {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    let mode = std::fs::metadata(path)?.permissions().mode() & 0o777;
+    if mode & 0o077 == 0 {
+        return Ok(None);
+    }
+    Ok(Some(Finding::warning(format!(
+        "{} has mode {:o}, run `chmod 600` on it so only you can read it",
+        path.display(), mode,
+    ))))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn check_permissions(_path: &Path) -> Result<Option<Finding>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(name: &str, secret: &str) -> Account {
+        Account::new(name.to_string(), secret.into(), "Example".to_string())
+    }
+
+    #[test]
+    fn test_check() {
+        let strong = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+        let mut config = Config::new(vec![
+            account("alice", strong),
+            account("bob", "not base32!"),
+            account("alice", "MFRGGZDFMZTWQ2LKNNWG23TPOBYXE43U"),
+            account("carol", &strong.to_lowercase()),
+            account("dave", "JBSWY3DPEHPK3PXP"),
+        ]);
+        config.accounts[2].digits = 4;
+
+        let findings = check(&config);
+        let messages: Vec<String> = findings.iter().map(ToString::to_string).collect();
+        assert_eq!(messages, [
+            "error: Secret of 'bob' is not valid Base32",
+            "warning: 'alice' uses 4 digits, most services expect 6 to 8",
+            "warning: More than one account is named 'alice', commands taking a name act on the first",
+            "warning: 'carol' has the same secret as 'alice'",
+            "warning: Secret of 'dave' is only 80 bits, at least 128 are recommended",
+        ]);
+
+        assert!(check(&Config::new(vec![account("alice", strong)])).is_empty());
+    }
+}
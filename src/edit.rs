@@ -0,0 +1,222 @@
+use std::env;
+use std::fs;
+use std::process::Command;
+
+use anyhow::Result;
+use zeroize::Zeroizing;
+
+use crate::commands::EditFields;
+use crate::config::{self, Account};
+use crate::crypto;
+use crate::formats::hex_encode;
+use crate::secret::SecretString;
+
+/// Placeholder shown instead of the secret; leaving it in place keeps the stored secret
+const MASK: &str = "********";
+
+const HEADER: &str = "\
+# Edit the account below, then save and close the editor.
+# The secret is masked; leave the mask as it is to keep the stored secret.
+# Recovery codes are left out and kept as they are.
+";
+
+impl EditFields {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.issuer.is_none()
+            && self.digits.is_none()
+            && self.period.is_none()
+            && self.algorithm.is_none()
+            && self.counter.is_none()
+            && self.group.is_none()
+            && self.note.is_none()
+            && self.favorite.is_none()
+            && !self.reset_failures
+    }
+
+    pub(crate) fn apply(&self, account: &mut Account) {
+        if let Some(name) = &self.name {
+            account.name = name.clone();
+        }
+        if let Some(issuer) = &self.issuer {
+            account.issuer = issuer.clone();
+        }
+        if let Some(digits) = self.digits {
+            account.digits = digits;
+        }
+        if let Some(period) = self.period {
+            // Time steps of the old period mean nothing under the new one
+            if period != account.period {
+                account.last_step = None;
+            }
+            account.period = period;
+        }
+        if let Some(algorithm) = self.algorithm {
+            account.algorithm = algorithm;
+        }
+        if let Some(counter) = self.counter {
+            account.counter = counter;
+        }
+        if let Some(group) = &self.group {
+            account.group = Some(group.clone()).filter(|group| !group.is_empty());
+        }
+        if let Some(note) = &self.note {
+            account.note = Some(note.clone()).filter(|note| !note.is_empty());
+        }
+        if let Some(favorite) = self.favorite {
+            account.favorite = favorite;
+        }
+        if self.reset_failures {
+            account.failures = 0;
+            account.locked_until = None;
+        }
+    }
+}
+
+/// TOML view of a single account, with the secret masked and the recovery codes hidden unless `show_secret`
+pub(crate) fn to_toml(account: &Account, show_secret: bool) -> Result<Zeroizing<String>> {
+    let mut view = account.clone();
+    if !show_secret {
+        view.secret = SecretString::from(MASK);
+        view.recovery_codes.clear();
+    }
+
+    let body = Zeroizing::new(toml::to_string(&view)?);
+    let header = if show_secret { "# Edit the account below, then save and close the editor.\n" } else { HEADER };
+    Ok(Zeroizing::new(format!("{}{}", header, body.as_str())))
+}
+
+/// Parse an edited view back into an account, restoring a masked secret and hidden recovery codes
+pub(crate) fn from_toml(text: &str, original: &Account, show_secret: bool) -> Result<Account> {
+    let mut account: Account = toml::from_str(text)
+        .map_err(|e| anyhow::anyhow!("Invalid account: {}", e.message()))?;
+    if account.secret.expose() == MASK {
+        account.secret = original.secret.clone();
+    }
+    if !show_secret {
+        account.recovery_codes = original.recovery_codes.clone();
+    }
+    // The ID ties the account to its audit log entries, so it can't be edited
+    account.id = original.id.clone();
+    // Time steps of the old period mean nothing under the new one, as in `EditFields::apply`
+    if account.period != original.period {
+        account.last_step = None;
+    }
+    account.validate()?;
+    Ok(account)
+}
+
+/// The user's editor command: $VISUAL, then $EDITOR, then vi
+pub(crate) fn editor_command() -> String {
+    env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string())
+}
+
+/// Open the account in an editor; returns `None` if nothing was changed
+pub(crate) fn edit_in_editor(account: &Account, show_secret: bool, editor: &str) -> Result<Option<Account>> {
+    let view = to_toml(account, show_secret)?;
+    let suffix = hex_encode(&crypto::random_bytes::<8>()?);
+    let path = env::temp_dir().join(format!("otpc-edit-{}.toml", suffix));
+    config::write_private_file(&path, view.as_bytes())?;
+
+    // The editor may be given with arguments, e.g. "code --wait"
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().ok_or(anyhow::anyhow!("No editor configured"))?;
+    let status = Command::new(program).args(parts).arg(&path).status();
+    let edited = fs::read_to_string(&path).map(Zeroizing::new);
+
+    // Overwrite before removing, in case the secret was shown
+    if let Ok(metadata) = fs::metadata(&path) {
+        let _ = config::write_private_file(&path, &vec![0u8; metadata.len() as usize]);
+    }
+    let _ = fs::remove_file(&path);
+
+    let status = status.map_err(|e| anyhow::anyhow!("Failed to run editor '{}': {}", program, e))?;
+    if !status.success() {
+        return Err(anyhow::anyhow!("Editor exited with {}", status));
+    }
+    let edited = edited?;
+    if edited.as_str() == view.as_str() {
+        return Ok(None);
+    }
+    from_toml(&edited, account, show_secret).map(Some)
+}
+
+/// Make sure no other account already uses `name`
+pub(crate) fn check_name(accounts: &[Account], index: usize, name: &str) -> Result<()> {
+    let taken = accounts.iter().enumerate().any(|(i, other)| i != index && other.name == name);
+    if taken {
+        return Err(anyhow::anyhow!("Another account is already named '{}'", name));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RecoveryCode;
+    use crate::otp::Algorithm;
+
+    fn sample() -> Account {
+        Account::new("octocat".to_string(), "JBSWY3DPEHPK3PXP".into(), "GitHub".to_string())
+    }
+
+    #[test]
+    fn test_masked_view_keeps_secret() {
+        let mut account = sample();
+        account.recovery_codes.push(RecoveryCode { code: "k7m2p-q9x4t".into(), used: false });
+        let view = to_toml(&account, false).unwrap();
+        assert!(!view.contains("JBSWY3DPEHPK3PXP"));
+        assert!(!view.contains("k7m2p-q9x4t"));
+        assert!(to_toml(&account, true).unwrap().contains("JBSWY3DPEHPK3PXP"));
+
+        let edited = view.replace("name = \"octocat\"", "name = \"hubot\"").replace("digits = 6", "digits = 8");
+        let updated = from_toml(&edited, &account, false).unwrap();
+        assert_eq!((updated.name.as_str(), updated.digits), ("hubot", 8));
+        assert_eq!(updated.secret.expose(), "JBSWY3DPEHPK3PXP");
+        assert_eq!(updated.recovery_codes, account.recovery_codes);
+
+        let invalid = view.replace("digits = 6", "digits = 0");
+        assert!(from_toml(&invalid, &account, false).is_err());
+    }
+
+    #[test]
+    fn test_period_change_resets_last_step() {
+        let mut account = sample();
+        account.last_step = Some(58_000_000);
+        let view = to_toml(&account, false).unwrap();
+        assert_eq!(from_toml(&view, &account, false).unwrap().last_step, Some(58_000_000));
+
+        let updated = from_toml(&view.replace("period = 30", "period = 60"), &account, false).unwrap();
+        assert_eq!((updated.period, updated.last_step), (60, None));
+    }
+
+    #[test]
+    fn test_apply_fields() {
+        let mut account = sample();
+        let fields = EditFields { issuer: Some("GitHub Enterprise".to_string()), algorithm: Some(Algorithm::Sha256), ..Default::default() };
+        assert!(!fields.is_empty());
+        fields.apply(&mut account);
+        assert_eq!((account.issuer.as_str(), account.algorithm), ("GitHub Enterprise", Algorithm::Sha256));
+        assert_eq!(account.name, "octocat");
+
+        let accounts = vec![sample(), Account::new("hubot".to_string(), "GEZDGNBVGY3TQOJQ".into(), "host".to_string())];
+        assert!(check_name(&accounts, 0, "hubot").is_err());
+        assert!(check_name(&accounts, 0, "octocat").is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_edit_in_editor() {
+        let account = sample();
+        let updated = edit_in_editor(&account, false, "sed -i s/GitHub/Gitea/").unwrap().unwrap();
+        assert_eq!(updated.issuer, "Gitea");
+        assert_eq!(updated.secret.expose(), "JBSWY3DPEHPK3PXP");
+
+        assert!(edit_in_editor(&account, false, "true").unwrap().is_none());
+        assert!(edit_in_editor(&account, false, "false").is_err());
+    }
+}
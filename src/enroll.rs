@@ -0,0 +1,402 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use zeroize::Zeroizing;
+
+use crate::config::{self, Account};
+use crate::crypto::random_bytes;
+use crate::formats::{hex_decode, hex_encode};
+use crate::otp::{constant_time_eq, encode_base32};
+use crate::qrcode::{to_key_uri, url_encode};
+use crate::secret::SecretString;
+use crate::verify::{self, Outcome, Policy};
+
+/// Bytes of a generated secret, the 160 bits RFC 4226 recommends
+const SECRET_LEN: usize = 20;
+/// Characters of a recovery code, shown as two dash-separated halves
+const RECOVERY_CODE_LEN: usize = 10;
+/// Recovery code characters, leaving out 0, 1, i, l and o, which are easily mixed up
+const RECOVERY_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// Where a user's credential stands
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Enrolled, waiting for a first code to prove the authenticator app has the secret
+    Pending,
+    Active,
+    Disabled,
+}
+
+/// A user's second factor as kept in a `Storage`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Credential {
+    pub status: Status,
+    /// Secret, OTP parameters and replay and lockout state, named after the user
+    pub account: Account,
+    /// Salted SHA-256 hashes of the unused recovery codes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recovery_codes: Vec<String>,
+    pub enrolled_at: u64,
+}
+
+/// Persistence of credentials, one per user
+pub trait Storage {
+    fn load(&self, user: &str) -> Result<Option<Credential>>;
+    fn save(&mut self, user: &str, credential: &Credential) -> Result<()>;
+}
+
+/// Credentials kept in memory, for tests and short-lived services
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    credentials: HashMap<String, Credential>,
+}
+
+impl Storage for MemoryStorage {
+    fn load(&self, user: &str) -> Result<Option<Credential>> {
+        Ok(self.credentials.get(user).cloned())
+    }
+
+    fn save(&mut self, user: &str, credential: &Credential) -> Result<()> {
+        self.credentials.insert(user.to_string(), credential.clone());
+        Ok(())
+    }
+}
+
+/// One TOML file per user in a directory, readable only by the service's user
+///
+/// Files are replaced atomically, but nothing serializes two processes verifying the same user:
+/// run one service per directory, or replay protection only holds within each process.
+#[derive(Debug)]
+pub struct FileStorage {
+    dir: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, user: &str) -> Result<PathBuf> {
+        // Percent-encoding leaves no separators, only `.` and `..` still mean something
+        let name = url_encode(user);
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(anyhow::anyhow!("Invalid user name '{}'", user));
+        }
+        Ok(self.dir.join(format!("{}.toml", name)))
+    }
+}
+
+impl Storage for FileStorage {
+    fn load(&self, user: &str) -> Result<Option<Credential>> {
+        let contents = match fs::read_to_string(self.path(user)?) {
+            Ok(contents) => Zeroizing::new(contents),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Some(toml::from_str(&contents).map_err(|e| anyhow::anyhow!(e))?))
+    }
+
+    fn save(&mut self, user: &str, credential: &Credential) -> Result<()> {
+        let contents = Zeroizing::new(toml::to_string(credential)?);
+        config::write_atomically(&self.path(user)?, contents.as_bytes())
+    }
+}
+
+/// What to show a user who is enrolling: the secret, and the otpauth URI as text or as a QR code
+pub struct Enrollment {
+    pub secret: SecretString,
+    pub uri: Zeroizing<String>,
+}
+
+impl Enrollment {
+    /// The URI as an SVG QR code, to embed in a page
+    pub fn qr_svg(&self) -> Result<String> {
+        let code = ::qrcode::QrCode::new(self.uri.as_bytes())?;
+        Ok(code.render::<::qrcode::render::svg::Color>().min_dimensions(200, 200).build())
+    }
+
+    /// The URI as a PNG QR code
+    pub fn qr_png(&self) -> Result<Vec<u8>> {
+        let code = ::qrcode::QrCode::new(self.uri.as_bytes())?;
+        let image = code.render::<image::Luma<u8>>().min_dimensions(200, 200).build();
+        let mut png = Vec::new();
+        image.write_to(&mut io::Cursor::new(&mut png), image::ImageFormat::Png)?;
+        Ok(png)
+    }
+}
+
+/// Second-factor enrollment and verification for the users of a service
+pub struct Authenticator<S: Storage> {
+    storage: S,
+    issuer: String,
+    policy: Policy,
+    window: u64,
+}
+
+impl<S: Storage> Authenticator<S> {
+    /// Credentials in `storage`, shown as `issuer` in authenticator apps
+    pub fn new(storage: S, issuer: impl Into<String>) -> Self {
+        Self { storage, issuer: issuer.into(), policy: Policy::default(), window: 1 }
+    }
+
+    /// Lock credentials after repeated failures as `policy` says
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Accept `window` periods of clock drift on either side, at most `verify::MAX_WINDOW`
+    pub fn with_window(mut self, window: u64) -> Self {
+        self.window = window;
+        self
+    }
+
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    /// The user's credential, if they ever enrolled
+    pub fn credential(&self, user: &str) -> Result<Option<Credential>> {
+        self.storage.load(user)
+    }
+
+    /// Generate a new secret for `user`, replacing a pending or disabled credential
+    pub fn enroll(&mut self, user: &str, timestamp: u64) -> Result<Enrollment> {
+        if self.storage.load(user)?.is_some_and(|credential| credential.status == Status::Active) {
+            return Err(anyhow::anyhow!("'{}' is already enrolled, disable the credential first", user));
+        }
+
+        let secret = encode_base32(&random_bytes::<SECRET_LEN>()?);
+        let account = Account::new(user.to_string(), secret.clone(), self.issuer.clone());
+        let uri = to_key_uri(&account);
+        let credential = Credential { status: Status::Pending, account, recovery_codes: Vec::new(), enrolled_at: timestamp };
+        self.storage.save(user, &credential)?;
+        Ok(Enrollment { secret, uri })
+    }
+
+    /// Activate a pending credential with a first code from the user's app
+    pub fn confirm(&mut self, user: &str, code: &str, timestamp: u64) -> Result<Outcome> {
+        self.check(user, Status::Pending, |credential, policy, window| {
+            let outcome = verify::verify(&mut credential.account, code, timestamp, window, policy);
+            if outcome.is_accepted() {
+                credential.status = Status::Active;
+            }
+            outcome
+        })
+    }
+
+    /// Check a code from an active credential; a code is accepted at most once
+    pub fn verify(&mut self, user: &str, code: &str, timestamp: u64) -> Result<Outcome> {
+        self.check(user, Status::Active, |credential, policy, window| {
+            verify::verify(&mut credential.account, code, timestamp, window, policy)
+        })
+    }
+
+    /// Turn the second factor off, dropping the unused recovery codes
+    pub fn disable(&mut self, user: &str) -> Result<()> {
+        let mut credential = self.storage.load(user)?
+            .ok_or(anyhow::anyhow!("'{}' is not enrolled", user))?;
+        credential.status = Status::Disabled;
+        credential.recovery_codes.clear();
+        self.storage.save(user, &credential)
+    }
+
+    /// Replace the user's recovery codes with `count` new ones, returned once in plain text
+    pub fn issue_recovery_codes(&mut self, user: &str, count: usize) -> Result<Vec<Zeroizing<String>>> {
+        let mut credential = self.active(user)?;
+        let codes = (0..count).map(|_| recovery_code()).collect::<Result<Vec<_>>>()?;
+        credential.recovery_codes = codes.iter().map(|code| hash_recovery_code(code)).collect::<Result<_>>()?;
+        self.storage.save(user, &credential)?;
+        Ok(codes)
+    }
+
+    /// Accept an unused recovery code instead of an OTP code, and use it up
+    pub fn use_recovery_code(&mut self, user: &str, code: &str, timestamp: u64) -> Result<Outcome> {
+        self.check(user, Status::Active, |credential, policy, _| {
+            if let Some(until) = verify::locked_until(&credential.account, timestamp) {
+                return Outcome::Locked { until };
+            }
+            let index = credential.recovery_codes.iter().position(|hash| recovery_code_matches(hash, code));
+            if let Some(index) = index {
+                credential.recovery_codes.remove(index);
+            }
+            verify::record(&mut credential.account, index.is_some(), timestamp, policy);
+            match index {
+                Some(_) => Outcome::Accepted,
+                None => Outcome::Invalid,
+            }
+        })
+    }
+
+    fn active(&self, user: &str) -> Result<Credential> {
+        match self.storage.load(user)? {
+            Some(credential) if credential.status == Status::Active => Ok(credential),
+            _ => Err(anyhow::anyhow!("'{}' has no active credential", user)),
+        }
+    }
+
+    /// Run `attempt` on a credential in the `expected` state, then save what changed
+    fn check(
+        &mut self,
+        user: &str,
+        expected: Status,
+        attempt: impl FnOnce(&mut Credential, &Policy, u64) -> Outcome,
+    ) -> Result<Outcome> {
+        let mut credential = match self.storage.load(user)? {
+            Some(credential) if credential.status == expected => credential,
+            _ if expected == Status::Pending => return Err(anyhow::anyhow!("'{}' has no pending enrollment", user)),
+            _ => return Err(anyhow::anyhow!("'{}' has no active credential", user)),
+        };
+        let outcome = attempt(&mut credential, &self.policy, self.window);
+        if !matches!(outcome, Outcome::Locked { .. }) {
+            self.storage.save(user, &credential)?;
+        }
+        Ok(outcome)
+    }
+}
+
+/// A random recovery code such as `k7dwm-q3xzp`
+fn recovery_code() -> Result<Zeroizing<String>> {
+    let mut code = Zeroizing::new(String::with_capacity(RECOVERY_CODE_LEN + 1));
+    while code.len() < RECOVERY_CODE_LEN + 1 {
+        if code.len() == RECOVERY_CODE_LEN / 2 {
+            code.push('-');
+        }
+        let [byte] = random_bytes::<1>()?;
+        // Rejection sampling keeps every character equally likely
+        let limit = 256 - 256 % RECOVERY_ALPHABET.len();
+        if (byte as usize) < limit {
+            code.push(RECOVERY_ALPHABET[byte as usize % RECOVERY_ALPHABET.len()] as char);
+        }
+    }
+    Ok(code)
+}
+
+/// Lowercase without dashes or spaces, so `K7DWM Q3XZP` matches `k7dwm-q3xzp`
+fn normalize_recovery_code(code: &str) -> Zeroizing<String> {
+    Zeroizing::new(code.chars().filter(char::is_ascii_alphanumeric).map(|c| c.to_ascii_lowercase()).collect())
+}
+
+/// `<salt>$<hash>` in hex; the codes are random enough that a fast salted hash cannot be brute forced
+fn hash_recovery_code(code: &str) -> Result<String> {
+    let salt = random_bytes::<16>()?;
+    Ok(format!("{}${}", hex_encode(&salt), hex_encode(&salted_hash(&salt, code))))
+}
+
+fn recovery_code_matches(stored: &str, code: &str) -> bool {
+    let Some((salt, hash)) = stored.split_once('$') else {
+        return false;
+    };
+    match (hex_decode(salt), hex_decode(hash)) {
+        (Ok(salt), Ok(hash)) => constant_time_eq(&salted_hash(&salt, code), &hash),
+        _ => false,
+    }
+}
+
+fn salted_hash(salt: &[u8], code: &str) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(normalize_recovery_code(code).as_bytes());
+    hasher.finalize().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::otp::Otp;
+    use crate::qrcode::parse_qr_from_bytes;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn code_at(authenticator: &Authenticator<impl Storage>, user: &str, timestamp: u64) -> String {
+        let credential = authenticator.credential(user).unwrap().unwrap();
+        Otp::from_account(&credential.account).unwrap().generate_totp_at(timestamp)
+    }
+
+    #[test]
+    fn test_enroll_confirm_verify() {
+        let mut authenticator = Authenticator::new(MemoryStorage::default(), "Example");
+        let enrollment = authenticator.enroll("alice@example.com", NOW).unwrap();
+        assert!(enrollment.uri.starts_with("otpauth://totp/Example:alice%40example.com?secret="));
+        assert_eq!(enrollment.secret.expose().len(), 32);
+
+        // The QR code decodes back to the same account
+        let scanned = parse_qr_from_bytes(&enrollment.qr_png().unwrap()).unwrap();
+        assert_eq!((scanned.name.as_str(), scanned.secret.expose()), ("alice@example.com", enrollment.secret.expose()));
+        assert!(enrollment.qr_svg().unwrap().starts_with("<?xml"));
+
+        // Only a pending enrollment can be confirmed, and only an active one verified
+        let code = code_at(&authenticator, "alice@example.com", NOW);
+        assert!(authenticator.verify("alice@example.com", &code, NOW).is_err());
+        assert_eq!(authenticator.confirm("alice@example.com", "000000", NOW).unwrap(), Outcome::Invalid);
+        assert_eq!(authenticator.confirm("alice@example.com", &code, NOW).unwrap(), Outcome::Accepted);
+        assert!(authenticator.confirm("alice@example.com", &code, NOW).is_err());
+        assert!(authenticator.enroll("alice@example.com", NOW).is_err());
+
+        // The confirming code cannot log in again
+        assert_eq!(authenticator.verify("alice@example.com", &code, NOW).unwrap(), Outcome::Replayed);
+        let later = code_at(&authenticator, "alice@example.com", NOW + 30);
+        assert_eq!(authenticator.verify("alice@example.com", &later, NOW + 30).unwrap(), Outcome::Accepted);
+
+        authenticator.disable("alice@example.com").unwrap();
+        let status = authenticator.credential("alice@example.com").unwrap().unwrap().status;
+        assert_eq!(status, Status::Disabled);
+        assert!(authenticator.verify("alice@example.com", &later, NOW + 60).is_err());
+        assert!(authenticator.enroll("alice@example.com", NOW).is_ok());
+        assert!(authenticator.disable("bob").is_err());
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let policy = Policy { max_failures: 2, lockout_seconds: 60 };
+        let mut authenticator = Authenticator::new(MemoryStorage::default(), "Example").with_policy(policy);
+        authenticator.enroll("alice", NOW).unwrap();
+        assert!(authenticator.issue_recovery_codes("alice", 3).is_err());
+        let code = code_at(&authenticator, "alice", NOW);
+        authenticator.confirm("alice", &code, NOW).unwrap();
+
+        let codes = authenticator.issue_recovery_codes("alice", 3).unwrap();
+        assert_eq!(codes.len(), 3);
+        assert!(codes.iter().all(|code| code.len() == RECOVERY_CODE_LEN + 1 && code.as_bytes()[5] == b'-'));
+        // Only hashes are stored
+        let stored = authenticator.credential("alice").unwrap().unwrap().recovery_codes;
+        assert!(stored.iter().all(|hash| !codes.iter().any(|code| hash.contains(code.as_str()))));
+
+        let typed = codes[1].to_uppercase().replace('-', " ");
+        assert_eq!(authenticator.use_recovery_code("alice", &typed, NOW).unwrap(), Outcome::Accepted);
+        assert_eq!(authenticator.use_recovery_code("alice", &codes[1], NOW).unwrap(), Outcome::Invalid);
+        assert_eq!(authenticator.credential("alice").unwrap().unwrap().recovery_codes.len(), 2);
+
+        // Wrong recovery codes count towards the lockout like wrong OTP codes
+        assert_eq!(authenticator.use_recovery_code("alice", "aaaaa-aaaaa", NOW).unwrap(), Outcome::Invalid);
+        assert_eq!(authenticator.use_recovery_code("alice", &codes[0], NOW).unwrap(), Outcome::Locked { until: NOW + 60 });
+        assert_eq!(authenticator.use_recovery_code("alice", &codes[0], NOW + 60).unwrap(), Outcome::Accepted);
+    }
+
+    #[test]
+    fn test_file_storage() {
+        let dir = std::env::temp_dir().join(format!("otpc-enroll-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut authenticator = Authenticator::new(FileStorage::new(&dir).unwrap(), "Example");
+        let enrollment = authenticator.enroll("../alice", NOW).unwrap();
+        assert!(dir.join("..%2Falice.toml").is_file());
+        assert!(authenticator.enroll("..", NOW).is_err());
+
+        // A new instance sees the saved state
+        let authenticator = Authenticator::new(FileStorage::new(&dir).unwrap(), "Example");
+        let credential = authenticator.credential("../alice").unwrap().unwrap();
+        assert_eq!(credential.status, Status::Pending);
+        assert_eq!(credential.account.secret, enrollment.secret);
+        assert!(authenticator.credential("bob").unwrap().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+use std::io::{self, Write as _};
+use std::process::{Command, ExitStatus, Stdio};
+
+use anyhow::Result;
+
+/// Placeholder replaced by the code in the child's arguments
+pub(crate) const PLACEHOLDER: &str = "{code}";
+
+/// How the code reaches the child process
+pub(crate) struct Delivery<'a> {
+    /// Environment variable to set to the code
+    pub env: Option<&'a str>,
+    /// Write the code and a newline to the child's standard input
+    pub stdin: bool,
+}
+
+impl Delivery<'_> {
+    /// Refuse to run a command the code would never reach
+    pub(crate) fn check(&self, command: &[String]) -> Result<()> {
+        let in_args = command.iter().any(|arg| arg.contains(PLACEHOLDER));
+        if self.env.is_none() && !self.stdin && !in_args {
+            return Err(anyhow::anyhow!(
+                "Pass the code with --env <NAME>, --stdin or a {} placeholder in the command",
+                PLACEHOLDER,
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Seconds to wait so the code stays valid for at least `min_remaining` seconds once the child gets it
+pub(crate) fn wait_seconds(remaining: u64, min_remaining: u64) -> u64 {
+    if remaining < min_remaining {
+        remaining
+    } else {
+        0
+    }
+}
+
+/// Replace every `{code}` in the arguments
+pub(crate) fn substitute(args: &[String], code: &str) -> Vec<String> {
+    args.iter().map(|arg| arg.replace(PLACEHOLDER, code)).collect()
+}
+
+/// Run `command` with the code delivered as asked, and wait for it to exit
+pub(crate) fn run(command: &[String], code: &str, delivery: &Delivery) -> Result<ExitStatus> {
+    let (program, args) = command.split_first()
+        .ok_or(anyhow::anyhow!("No command to run"))?;
+
+    let mut child = Command::new(program);
+    child.args(substitute(args, code));
+    if let Some(name) = delivery.env {
+        child.env(name, code);
+    }
+    if delivery.stdin {
+        child.stdin(Stdio::piped());
+    }
+
+    let mut child = child.spawn()
+        .map_err(|e| anyhow::anyhow!("Cannot run '{}': {}", program, e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // Closing the pipe afterwards tells the child no more input follows; one that never reads is fine
+        match stdin.write_all(format!("{}\n", code).as_bytes()) {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(child.wait()?)
+}
+
+/// Exit code to leave with after the child exited, following the shell's 128 + signal convention
+pub(crate) fn exit_code(status: ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return 128 + signal;
+    }
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_delivery() {
+        assert_eq!(substitute(&strings(&["--otp={code}", "x"]), "123456"), ["--otp=123456", "x"]);
+        assert_eq!(wait_seconds(3, 5), 3);
+        assert_eq!(wait_seconds(5, 5), 0);
+        assert_eq!(wait_seconds(29, 0), 0);
+
+        let none = Delivery { env: None, stdin: false };
+        assert!(none.check(&strings(&["vpn", "--otp"])).is_err());
+        assert!(none.check(&strings(&["vpn", "--otp={code}"])).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run() {
+        let env = Delivery { env: Some("MFA_CODE"), stdin: false };
+        let status = run(&strings(&["sh", "-c", "test \"$MFA_CODE\" = 123456 && test \"$0\" = 123456", "{code}"]), "123456", &env).unwrap();
+        assert!(status.success());
+
+        let stdin = Delivery { env: None, stdin: true };
+        let status = run(&strings(&["sh", "-c", "read code && test \"$code\" = 123456"]), "123456", &stdin).unwrap();
+        assert!(status.success());
+
+        let status = run(&strings(&["sh", "-c", "exit 3"]), "123456", &stdin).unwrap();
+        assert_eq!(exit_code(status), 3);
+        assert!(run(&[], "123456", &stdin).is_err());
+    }
+}
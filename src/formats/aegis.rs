@@ -0,0 +1,422 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use zeroize::Zeroizing;
+
+use crate::config::Account;
+use crate::crypto::{self, VaultKey, NONCE_LEN};
+use crate::formats::{hex_decode, hex_encode, random_uuid, Imported};
+use crate::otp::{Algorithm, OtpType};
+use crate::recovery;
+use crate::secret::SecretString;
+
+/// Key slot type protected by a password (scrypt)
+const SLOT_PASSWORD: u32 = 1;
+/// Length of an AES-GCM tag in bytes
+const TAG_LEN: usize = 16;
+/// scrypt parameters Aegis uses for new password slots
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+/// Highest scrypt costs accepted from a vault, whose parameters are untrusted: N = 2^20 with r = 8
+/// already takes 1 GiB of memory
+const MAX_SCRYPT_N: u64 = 1 << 20;
+const MAX_SCRYPT_R: u32 = 8;
+const MAX_SCRYPT_P: u32 = 16;
+
+#[derive(Serialize, Deserialize)]
+struct Vault {
+    version: u32,
+    header: Header,
+    /// The database object, or its base64 ciphertext when encrypted
+    db: Value,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    slots: Option<Vec<Slot>>,
+    params: Option<KeyParams>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Slot {
+    #[serde(rename = "type")]
+    slot_type: u32,
+    uuid: String,
+    key: String,
+    key_params: KeyParams,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    n: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    r: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    p: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    salt: Option<String>,
+    #[serde(default)]
+    repaired: bool,
+    #[serde(default)]
+    is_backup: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyParams {
+    nonce: String,
+    tag: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Database {
+    version: u32,
+    entries: Vec<Entry>,
+    #[serde(default)]
+    groups: Vec<Group>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    #[serde(rename = "type")]
+    entry_type: String,
+    #[serde(default)]
+    uuid: String,
+    name: String,
+    #[serde(default)]
+    issuer: String,
+    /// Free-form note, also carrying the account's recovery codes
+    #[serde(default)]
+    note: SecretString,
+    #[serde(default)]
+    favorite: bool,
+    #[serde(default)]
+    icon: Option<String>,
+    info: Info,
+    /// Group name, used up to database version 2
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    /// Group UUIDs, used from database version 3
+    #[serde(default)]
+    groups: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Info {
+    secret: SecretString,
+    algo: String,
+    digits: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    period: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    counter: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Group {
+    uuid: String,
+    name: String,
+}
+
+/// Whether the data looks like an Aegis vault
+pub(crate) fn detect(data: &[u8]) -> bool {
+    serde_json::from_slice::<Value>(data)
+        .map(|value| value.get("header").is_some() && value.get("db").is_some())
+        .unwrap_or(false)
+}
+
+/// Read an Aegis backup, asking for the password only if the vault is encrypted
+pub(crate) fn import(data: &str, password: impl FnOnce() -> Result<Zeroizing<String>>) -> Result<Imported> {
+    let vault: Vault = serde_json::from_str(data)?;
+    if vault.version != 1 {
+        return Err(anyhow::anyhow!("Unsupported Aegis vault version {}", vault.version));
+    }
+
+    let database: Database = match &vault.db {
+        Value::String(ciphertext) => {
+            let master_key = unlock_master_key(&vault.header, &password()?)?;
+            let params = vault.header.params.as_ref()
+                .ok_or(anyhow::anyhow!("Encrypted vault is missing its parameters"))?;
+
+            let mut sealed = BASE64.decode(ciphertext)?;
+            sealed.extend_from_slice(&hex_decode(&params.tag)?);
+            let plain = crypto::decrypt_with_nonce(&master_key, &hex_decode(&params.nonce)?, &sealed)?;
+            serde_json::from_slice(&plain)?
+        }
+        db => serde_json::from_value(db.clone())?,
+    };
+
+    Ok(map_entries(database))
+}
+
+fn unlock_master_key(header: &Header, password: &str) -> Result<VaultKey> {
+    let slots = header.slots.as_deref().unwrap_or_default();
+    let mut tried = false;
+
+    for slot in slots.iter().filter(|slot| slot.slot_type == SLOT_PASSWORD) {
+        let (Some(n), Some(r), Some(p), Some(salt)) = (slot.n, slot.r, slot.p, &slot.salt) else {
+            continue;
+        };
+        check_scrypt_params(n, r, p)?;
+        tried = true;
+
+        let derived = crypto::derive_key(password, &hex_decode(salt)?, n.trailing_zeros() as u8, r, p)?;
+        let mut sealed = hex_decode(&slot.key)?;
+        sealed.extend_from_slice(&hex_decode(&slot.key_params.tag)?);
+        if let Ok(master_key) = crypto::decrypt_with_nonce(&derived, &hex_decode(&slot.key_params.nonce)?, &sealed) {
+            return VaultKey::from_bytes(&master_key);
+        }
+    }
+
+    if tried {
+        Err(anyhow::anyhow!("Incorrect password"))
+    } else {
+        Err(anyhow::anyhow!("Vault has no password slot (biometric slots are not supported)"))
+    }
+}
+
+fn check_scrypt_params(n: u64, r: u32, p: u32) -> Result<()> {
+    if !n.is_power_of_two() || !(2..=MAX_SCRYPT_N).contains(&n) {
+        return Err(anyhow::anyhow!("Invalid scrypt parameter N = {}, expected a power of two up to {}", n, MAX_SCRYPT_N));
+    }
+    if !(1..=MAX_SCRYPT_R).contains(&r) || !(1..=MAX_SCRYPT_P).contains(&p) {
+        return Err(anyhow::anyhow!(
+            "Unsupported scrypt parameters r = {}, p = {} (at most r = {}, p = {})", r, p, MAX_SCRYPT_R, MAX_SCRYPT_P
+        ));
+    }
+    Ok(())
+}
+
+fn map_entries(database: Database) -> Imported {
+    let group_names: BTreeMap<&str, &str> = database.groups.iter()
+        .map(|group| (group.uuid.as_str(), group.name.as_str()))
+        .collect();
+    let mut imported = Imported::default();
+
+    for entry in database.entries {
+        let otp_type = match entry.entry_type.as_str() {
+            "totp" => OtpType::Totp,
+            "hotp" => OtpType::Hotp,
+            other => {
+                imported.skipped.push(format!("{}: unsupported type '{}'", entry.name, other));
+                continue;
+            }
+        };
+        let Some(algorithm) = Algorithm::parse(&entry.info.algo) else {
+            imported.skipped.push(format!("{}: unsupported algorithm '{}'", entry.name, entry.info.algo));
+            continue;
+        };
+
+        let issuer = match entry.issuer.trim() {
+            "" => "host".to_string(),
+            issuer => issuer.to_string(),
+        };
+        let mut account = Account::new(entry.name, entry.info.secret, issuer);
+        account.otp_type = otp_type;
+        account.algorithm = algorithm;
+        account.digits = entry.info.digits;
+        account.period = entry.info.period.unwrap_or(account.period);
+        account.counter = entry.info.counter.unwrap_or(0);
+        account.group = entry.group.or_else(|| {
+            entry.groups.iter()
+                .find_map(|uuid| group_names.get(uuid.as_str()))
+                .map(|name| name.to_string())
+        });
+        (account.note, account.recovery_codes) = recovery::split_note(entry.note.expose());
+        account.favorite = entry.favorite;
+
+        match account.validate() {
+            Ok(()) => imported.accounts.push(account),
+            Err(e) => imported.skipped.push(e.to_string()),
+        }
+    }
+    imported
+}
+
+/// Write accounts as an Aegis backup, encrypted when a password is given
+pub(crate) fn export(accounts: &[Account], password: Option<&str>) -> Result<Zeroizing<String>> {
+    let mut groups: Vec<Group> = Vec::new();
+    let mut entries = Vec::with_capacity(accounts.len());
+
+    for account in accounts {
+        let group_uuid = match &account.group {
+            Some(name) => match groups.iter().find(|group| &group.name == name) {
+                Some(group) => Some(group.uuid.clone()),
+                None => {
+                    let uuid = random_uuid()?;
+                    groups.push(Group { uuid: uuid.clone(), name: name.clone() });
+                    Some(uuid)
+                }
+            },
+            None => None,
+        };
+
+        let (entry_type, period, counter) = match account.otp_type {
+            OtpType::Totp => ("totp", Some(account.period), None),
+            OtpType::Hotp => ("hotp", None, Some(account.counter)),
+        };
+        entries.push(Entry {
+            entry_type: entry_type.to_string(),
+            uuid: random_uuid()?,
+            name: account.name.clone(),
+            // "host" is otpc's placeholder for a missing issuer, as on import
+            issuer: if account.issuer == "host" { String::new() } else { account.issuer.clone() },
+            note: recovery::note_with_codes(account).map(|note| SecretString::from(note.as_str())).unwrap_or_default(),
+            favorite: account.favorite,
+            icon: None,
+            info: Info {
+                secret: account.secret.clone(),
+                algo: account.algorithm.as_str().to_string(),
+                digits: account.digits,
+                period,
+                counter,
+            },
+            group: None,
+            groups: group_uuid.into_iter().collect(),
+        });
+    }
+
+    let database = Database { version: 3, entries, groups };
+    let vault = match password {
+        None => Vault {
+            version: 1,
+            header: Header { slots: None, params: None },
+            db: serde_json::to_value(&database)?,
+        },
+        Some(password) => seal_database(&database, password)?,
+    };
+
+    Ok(Zeroizing::new(serde_json::to_string_pretty(&vault)?))
+}
+
+fn seal_database(database: &Database, password: &str) -> Result<Vault> {
+    let master_key = VaultKey::from_bytes(&crypto::random_bytes::<32>()?)?;
+
+    // Wrap the master key with the password-derived key
+    let salt = crypto::random_bytes::<32>()?;
+    let derived = crypto::derive_key(password, &salt, SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P)?;
+    let slot_nonce = crypto::random_bytes::<NONCE_LEN>()?;
+    let wrapped = crypto::encrypt_with_nonce(&derived, &slot_nonce, master_key.as_bytes())?;
+    let (wrapped_key, slot_tag) = wrapped.split_at(wrapped.len() - TAG_LEN);
+
+    let plain = Zeroizing::new(serde_json::to_vec(database)?);
+    let db_nonce = crypto::random_bytes::<NONCE_LEN>()?;
+    let sealed = crypto::encrypt_with_nonce(&master_key, &db_nonce, &plain)?;
+    let (ciphertext, db_tag) = sealed.split_at(sealed.len() - TAG_LEN);
+
+    Ok(Vault {
+        version: 1,
+        header: Header {
+            slots: Some(vec![Slot {
+                slot_type: SLOT_PASSWORD,
+                uuid: random_uuid()?,
+                key: hex_encode(wrapped_key),
+                key_params: KeyParams { nonce: hex_encode(&slot_nonce), tag: hex_encode(slot_tag) },
+                n: Some(1 << SCRYPT_LOG_N),
+                r: Some(SCRYPT_R),
+                p: Some(SCRYPT_P),
+                salt: Some(hex_encode(&salt)),
+                repaired: true,
+                is_backup: false,
+            }]),
+            params: Some(KeyParams { nonce: hex_encode(&db_nonce), tag: hex_encode(db_tag) }),
+        },
+        db: Value::String(BASE64.encode(ciphertext)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAIN_VAULT: &str = include_str!("../assets/aegis_plain.json");
+    const ENCRYPTED_VAULT: &str = include_str!("../assets/aegis_encrypted.json");
+
+    fn no_password() -> Result<Zeroizing<String>> {
+        panic!("a plain vault must not ask for a password")
+    }
+
+    fn assert_sample_accounts(imported: &Imported) {
+        assert_eq!(imported.accounts.len(), 3);
+        assert_eq!(imported.skipped.len(), 1);
+        assert!(imported.skipped[0].contains("steam"));
+
+        let alice = &imported.accounts[0];
+        assert_eq!((alice.name.as_str(), alice.issuer.as_str()), ("alice@example.com", "Example"));
+        assert_eq!(alice.secret.expose(), "JBSWY3DPEHPK3PXP");
+        assert_eq!(alice.group.as_deref(), Some("Work"));
+        assert_eq!(alice.note.as_deref(), Some("primary login"));
+        assert!(alice.favorite);
+
+        let bob = &imported.accounts[1];
+        assert_eq!(bob.otp_type, OtpType::Hotp);
+        assert_eq!(bob.algorithm, Algorithm::Sha256);
+        assert_eq!((bob.digits, bob.counter), (8, 5));
+        assert_eq!(bob.note, None);
+
+        let carol = &imported.accounts[2];
+        assert_eq!(carol.issuer, "host");
+        assert_eq!(carol.algorithm, Algorithm::Sha512);
+        assert_eq!(carol.period, 60);
+    }
+
+    #[test]
+    fn test_import_plain_vault() {
+        assert!(detect(PLAIN_VAULT.as_bytes()));
+        let imported = import(PLAIN_VAULT, no_password).unwrap();
+        assert_sample_accounts(&imported);
+    }
+
+    #[test]
+    fn test_import_encrypted_vault() {
+        let imported = import(ENCRYPTED_VAULT, || Ok(Zeroizing::new("test".to_string()))).unwrap();
+        assert_sample_accounts(&imported);
+
+        let wrong = import(ENCRYPTED_VAULT, || Ok(Zeroizing::new("wrong".to_string())));
+        assert!(wrong.is_err());
+
+        // Costs from the file are refused before deriving anything
+        for (from, to) in [("\"n\": 32768", "\"n\": 4294967296"), ("\"n\": 32768", "\"n\": 30000"), ("\"r\": 8", "\"r\": 4096")] {
+            let costly = ENCRYPTED_VAULT.replace(from, to);
+            let error = import(&costly, || Ok(Zeroizing::new("test".to_string()))).unwrap_err();
+            assert!(error.to_string().contains("scrypt"));
+        }
+    }
+
+    #[test]
+    fn test_export_roundtrip() {
+        let accounts = import(PLAIN_VAULT, no_password).unwrap().accounts;
+
+        let plain = export(&accounts, None).unwrap();
+        assert!(!plain.contains("\"issuer\": \"host\""));
+        let reimported = import(&plain, no_password).unwrap();
+        assert_eq!(reimported.accounts.len(), 3);
+        assert_eq!(reimported.accounts[2].issuer, "host");
+        assert_eq!(reimported.accounts[0].group.as_deref(), Some("Work"));
+        assert_eq!(reimported.accounts[1].counter, 5);
+
+        let encrypted = export(&accounts, Some("hunter2")).unwrap();
+        assert!(!encrypted.contains("JBSWY3DPEHPK3PXP"));
+        let reimported = import(&encrypted, || Ok(Zeroizing::new("hunter2".to_string()))).unwrap();
+        assert_eq!(reimported.accounts[2].algorithm, Algorithm::Sha512);
+    }
+
+    #[test]
+    fn test_export_keeps_recovery_codes() {
+        let mut accounts = import(PLAIN_VAULT, no_password).unwrap().accounts;
+        let codes = [Zeroizing::new("k7m2p-q9x4t".to_string()), Zeroizing::new("h3w8c-r6n5v".to_string())];
+        recovery::add_codes(&mut accounts[0], &codes);
+        recovery::use_code(&mut accounts[0], None).unwrap();
+        recovery::add_codes(&mut accounts[1], &codes[1..]);
+
+        let encrypted = export(&accounts, Some("hunter2")).unwrap();
+        assert!(!encrypted.contains("h3w8c-r6n5v"));
+        let reimported = import(&encrypted, || Ok(Zeroizing::new("hunter2".to_string()))).unwrap().accounts;
+        assert_eq!(reimported[0].note.as_deref(), Some("primary login"));
+        assert_eq!(reimported[0].recovery_codes, accounts[0].recovery_codes);
+        assert!(reimported[0].recovery_codes[0].used);
+        assert_eq!(reimported[1].note, None);
+        assert_eq!(reimported[1].recovery_codes, accounts[1].recovery_codes);
+        assert!(reimported[2].recovery_codes.is_empty());
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::Value;
+use zeroize::Zeroizing;
+
+use crate::config::Account;
+use crate::crypto::{self, Pbkdf2Hash, NONCE_LEN};
+use crate::formats::Imported;
+use crate::otp::{Algorithm, OtpType};
+use crate::secret::SecretString;
+
+/// Length of the PBKDF2 salt in an encrypted backup
+const SALT_LEN: usize = 12;
+/// Length of an AES-GCM tag in bytes
+const TAG_LEN: usize = 16;
+/// Upper bound on PBKDF2 rounds, to reject arbitrary binary files
+const MAX_ROUNDS: u32 = 10_000_000;
+
+#[derive(Deserialize)]
+struct Entry {
+    secret: SecretString,
+    #[serde(default)]
+    issuer: String,
+    label: String,
+    #[serde(default = "default_digits")]
+    digits: u32,
+    #[serde(rename = "type")]
+    entry_type: String,
+    #[serde(default = "default_algorithm")]
+    algorithm: String,
+    #[serde(default)]
+    period: Option<u64>,
+    #[serde(default)]
+    counter: Option<u64>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+fn default_digits() -> u32 {
+    6
+}
+
+fn default_algorithm() -> String {
+    "SHA1".to_string()
+}
+
+/// Whether the data looks like an andOTP backup, either plain JSON or a `.json.aes` file
+pub(crate) fn detect(data: &[u8]) -> bool {
+    if let Ok(value) = serde_json::from_slice::<Value>(data) {
+        return value.as_array()
+            .and_then(|entries| entries.first())
+            .is_some_and(|entry| entry.get("secret").is_some() && entry.get("type").is_some());
+    }
+
+    // iterations (u32, big endian) || salt || iv || ciphertext || tag
+    data.len() > 4 + SALT_LEN + NONCE_LEN + TAG_LEN
+        && (1..=MAX_ROUNDS).contains(&u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+}
+
+/// Read an andOTP backup, asking for the password only if the file is encrypted
+pub(crate) fn import(data: &[u8], password: impl FnOnce() -> Result<Zeroizing<String>>) -> Result<Imported> {
+    let entries: Vec<Entry> = match serde_json::from_slice(data) {
+        Ok(entries) => entries,
+        Err(_) if !data.starts_with(b"[") => serde_json::from_slice(&decrypt(data, &password()?)?)?,
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut imported = Imported::default();
+    for entry in entries {
+        let otp_type = match entry.entry_type.as_str() {
+            "TOTP" => OtpType::Totp,
+            "HOTP" => OtpType::Hotp,
+            other => {
+                imported.skipped.push(format!("{}: unsupported type '{}'", entry.label, other));
+                continue;
+            }
+        };
+        let Some(algorithm) = Algorithm::parse(&entry.algorithm) else {
+            imported.skipped.push(format!("{}: unsupported algorithm '{}'", entry.label, entry.algorithm));
+            continue;
+        };
+
+        let issuer = match entry.issuer.trim() {
+            "" => "host".to_string(),
+            issuer => issuer.to_string(),
+        };
+        let mut account = Account::new(entry.label, entry.secret, issuer);
+        account.otp_type = otp_type;
+        account.algorithm = algorithm;
+        account.digits = entry.digits;
+        account.period = entry.period.unwrap_or(account.period);
+        account.counter = entry.counter.unwrap_or(0);
+        account.tags = entry.tags;
+
+        match account.validate() {
+            Ok(()) => imported.accounts.push(account),
+            Err(e) => imported.skipped.push(e.to_string()),
+        }
+    }
+    Ok(imported)
+}
+
+fn decrypt(data: &[u8], password: &str) -> Result<Zeroizing<Vec<u8>>> {
+    if data.len() < 4 + SALT_LEN + NONCE_LEN + TAG_LEN {
+        return Err(anyhow::anyhow!("Encrypted andOTP backup is truncated"));
+    }
+
+    let (rounds, rest) = data.split_at(4);
+    let (salt, rest) = rest.split_at(SALT_LEN);
+    let (iv, ciphertext) = rest.split_at(NONCE_LEN);
+
+    let rounds = u32::from_be_bytes([rounds[0], rounds[1], rounds[2], rounds[3]]);
+    if !(1..=MAX_ROUNDS).contains(&rounds) {
+        return Err(anyhow::anyhow!("Invalid PBKDF2 iteration count {}", rounds));
+    }
+
+    let key = crypto::derive_key_pbkdf2(password, salt, rounds, Pbkdf2Hash::Sha1)?;
+    crypto::decrypt_with_nonce(&key, iv, ciphertext)
+        .map_err(|_| anyhow::anyhow!("Incorrect password"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAIN_BACKUP: &[u8] = include_bytes!("../assets/andotp_plain.json");
+    const ENCRYPTED_BACKUP: &[u8] = include_bytes!("../assets/andotp_encrypted.json.aes");
+
+    fn assert_sample_accounts(imported: &Imported) {
+        assert_eq!(imported.accounts.len(), 2);
+        assert_eq!(imported.skipped.len(), 1);
+        assert!(imported.skipped[0].contains("STEAM"));
+
+        let alice = &imported.accounts[0];
+        assert_eq!((alice.name.as_str(), alice.issuer.as_str()), ("alice", "Example"));
+        assert!(alice.tags.is_empty());
+
+        let bob = &imported.accounts[1];
+        assert_eq!(bob.issuer, "host");
+        assert_eq!(bob.otp_type, OtpType::Hotp);
+        assert_eq!(bob.algorithm, Algorithm::Sha512);
+        assert_eq!((bob.digits, bob.counter), (8, 7));
+        assert_eq!(bob.tags, ["Work", "VPN"]);
+    }
+
+    #[test]
+    fn test_import_plain_backup() {
+        assert!(detect(PLAIN_BACKUP));
+        let imported = import(PLAIN_BACKUP, || panic!("a plain backup must not ask for a password")).unwrap();
+        assert_sample_accounts(&imported);
+    }
+
+    #[test]
+    fn test_import_encrypted_backup() {
+        assert!(detect(ENCRYPTED_BACKUP));
+        let imported = import(ENCRYPTED_BACKUP, || Ok(Zeroizing::new("test".to_string()))).unwrap();
+        assert_sample_accounts(&imported);
+
+        assert!(import(ENCRYPTED_BACKUP, || Ok(Zeroizing::new("wrong".to_string()))).is_err());
+    }
+}
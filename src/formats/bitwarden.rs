@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use zeroize::Zeroizing;
+
+use crate::config::Account;
+use crate::formats::{account_from_otp_field, random_uuid, Imported};
+use crate::qrcode::to_key_uri;
+use crate::recovery;
+use crate::secret::SecretString;
+
+/// Item type of a login in Bitwarden exports
+const ITEM_LOGIN: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Export {
+    #[serde(default)]
+    encrypted: bool,
+    #[serde(default)]
+    folders: Vec<Folder>,
+    #[serde(default)]
+    items: Vec<Item>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Folder {
+    id: String,
+    name: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Item {
+    #[serde(default)]
+    id: String,
+    #[serde(default)]
+    organization_id: Option<String>,
+    #[serde(default)]
+    folder_id: Option<String>,
+    #[serde(rename = "type")]
+    item_type: u32,
+    #[serde(default)]
+    reprompt: u32,
+    name: String,
+    /// Free-form notes, also carrying the account's recovery codes
+    #[serde(default)]
+    notes: Option<SecretString>,
+    #[serde(default)]
+    favorite: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    login: Option<Login>,
+    #[serde(default)]
+    collection_ids: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Login {
+    #[serde(default)]
+    uris: Vec<Value>,
+    #[serde(default)]
+    username: Option<String>,
+    /// Never read; passwords have no place in the OTP store
+    #[serde(default, skip_deserializing)]
+    password: Option<SecretString>,
+    /// otpauth URI or bare Base32 secret
+    #[serde(default)]
+    totp: Option<SecretString>,
+}
+
+/// Whether the data looks like a Bitwarden JSON export
+pub(crate) fn detect(data: &[u8]) -> bool {
+    serde_json::from_slice::<Value>(data)
+        .map(|value| value.get("items").is_some_and(Value::is_array) && value.get("encrypted").is_some())
+        .unwrap_or(false)
+}
+
+/// Read the TOTP fields of a Bitwarden JSON export, ignoring logins without one
+pub(crate) fn import(data: &str) -> Result<Imported> {
+    let export: Export = serde_json::from_str(data)?;
+    if export.encrypted {
+        return Err(anyhow::anyhow!("Encrypted Bitwarden exports are not supported, export as unencrypted JSON"));
+    }
+
+    let folder_names: BTreeMap<&str, &str> = export.folders.iter()
+        .map(|folder| (folder.id.as_str(), folder.name.as_str()))
+        .collect();
+    let mut imported = Imported::default();
+
+    for item in &export.items {
+        let Some(login) = &item.login else { continue };
+        let Some(totp) = login.totp.as_ref().filter(|totp| !totp.expose().trim().is_empty()) else {
+            continue;
+        };
+        if totp.expose().starts_with("steam://") {
+            imported.skipped.push(format!("{}: unsupported type 'steam'", item.name));
+            continue;
+        }
+
+        let name = login.username.as_deref().filter(|name| !name.is_empty()).unwrap_or(&item.name);
+        match account_from_otp_field(totp.expose(), name, &item.name) {
+            Ok(mut account) => {
+                account.group = item.folder_id.as_deref()
+                    .and_then(|id| folder_names.get(id))
+                    .map(|name| name.to_string());
+                if let Some(notes) = &item.notes {
+                    (account.note, account.recovery_codes) = recovery::split_note(notes.expose());
+                }
+                account.favorite = item.favorite;
+                imported.accounts.push(account);
+            }
+            Err(e) => imported.skipped.push(format!("{}: {}", item.name, e)),
+        }
+    }
+    Ok(imported)
+}
+
+/// Write accounts as an unencrypted Bitwarden JSON export, one login per account
+pub(crate) fn export(accounts: &[Account]) -> Result<Zeroizing<String>> {
+    let mut folders: Vec<Folder> = Vec::new();
+    let mut items = Vec::with_capacity(accounts.len());
+
+    for account in accounts {
+        let folder_id = match &account.group {
+            Some(name) => match folders.iter().find(|folder| &folder.name == name) {
+                Some(folder) => Some(folder.id.clone()),
+                None => {
+                    let id = random_uuid()?;
+                    folders.push(Folder { id: id.clone(), name: name.clone() });
+                    Some(id)
+                }
+            },
+            None => None,
+        };
+
+        let title = if account.issuer == "host" { &account.name } else { &account.issuer };
+        items.push(Item {
+            id: random_uuid()?,
+            organization_id: None,
+            folder_id,
+            item_type: ITEM_LOGIN,
+            reprompt: 0,
+            name: title.clone(),
+            notes: recovery::note_with_codes(account).map(|note| SecretString::from(note.as_str())),
+            favorite: account.favorite,
+            login: Some(Login {
+                uris: Vec::new(),
+                username: Some(account.name.clone()),
+                password: None,
+                totp: Some(SecretString::new(to_key_uri(account).to_string())),
+            }),
+            collection_ids: None,
+        });
+    }
+
+    let export = Export { encrypted: false, folders, items };
+    Ok(Zeroizing::new(serde_json::to_string_pretty(&export)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::otp::{Algorithm, OtpType};
+
+    const EXPORT: &str = include_str!("../assets/bitwarden.json");
+
+    #[test]
+    fn test_import_export() {
+        assert!(detect(EXPORT.as_bytes()));
+
+        let imported = import(EXPORT).unwrap();
+        assert_eq!(imported.accounts.len(), 2);
+        assert_eq!(imported.skipped.len(), 1);
+
+        let github = &imported.accounts[0];
+        assert_eq!((github.name.as_str(), github.issuer.as_str()), ("octocat", "GitHub"));
+        assert_eq!(github.group.as_deref(), Some("Work"));
+
+        // A bare secret takes its name and issuer from the login
+        let mail = &imported.accounts[1];
+        assert_eq!((mail.name.as_str(), mail.issuer.as_str()), ("alice@example.com", "Mail"));
+        assert_eq!(mail.secret.expose(), "GEZDGNBVGY3TQOJQ");
+        assert_eq!(mail.note.as_deref(), Some("backup codes in the safe"));
+        assert!(mail.favorite && !github.favorite);
+
+        let mut accounts = imported.accounts.clone();
+        recovery::add_codes(&mut accounts[1], &[Zeroizing::new("k7m2p-q9x4t".to_string())]);
+        let exported = export(&accounts).unwrap();
+        assert!(!exported.contains("hunter2"));
+        let reimported = import(&exported).unwrap();
+        assert_eq!(reimported.accounts[1].note.as_deref(), Some("backup codes in the safe"));
+        assert_eq!(reimported.accounts[1].recovery_codes, accounts[1].recovery_codes);
+        assert_eq!(reimported.accounts.len(), 2);
+        assert!(reimported.accounts[1].has_same_secret(mail));
+        assert_eq!(reimported.accounts[0].group.as_deref(), Some("Work"));
+        assert!(reimported.accounts[1].favorite);
+        assert_eq!((reimported.accounts[1].otp_type, reimported.accounts[1].algorithm), (OtpType::Totp, Algorithm::Sha1));
+    }
+}
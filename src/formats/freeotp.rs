@@ -0,0 +1,147 @@
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::Value;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::config::Account;
+use crate::formats::Imported;
+use crate::otp::{encode_base32, Algorithm, OtpType};
+use crate::qrcode::parse_key_uri;
+
+#[derive(Deserialize)]
+struct Backup {
+    tokens: Vec<Token>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Token {
+    #[serde(default = "default_algorithm")]
+    algo: String,
+    #[serde(default)]
+    counter: u64,
+    #[serde(default = "default_digits")]
+    digits: u32,
+    #[serde(default)]
+    issuer_ext: String,
+    label: String,
+    #[serde(default = "default_period")]
+    period: u64,
+    /// Raw key as Java signed bytes
+    secret: Vec<i8>,
+    #[serde(rename = "type")]
+    token_type: String,
+}
+
+fn default_algorithm() -> String {
+    "SHA1".to_string()
+}
+
+fn default_digits() -> u32 {
+    6
+}
+
+fn default_period() -> u64 {
+    30
+}
+
+/// Whether the data looks like a FreeOTP+ JSON export or its list of otpauth URIs
+pub(crate) fn detect(data: &[u8]) -> bool {
+    if let Ok(value) = serde_json::from_slice::<Value>(data) {
+        return value.get("tokens").is_some_and(Value::is_array);
+    }
+
+    let Ok(text) = std::str::from_utf8(data) else {
+        return false;
+    };
+    let mut lines = uri_lines(text).peekable();
+    lines.peek().is_some() && lines.all(|line| line.starts_with("otpauth://"))
+}
+
+/// Read a FreeOTP+ export (JSON or one otpauth URI per line)
+pub(crate) fn import(data: &str) -> Result<Imported> {
+    let mut imported = Imported::default();
+
+    if !data.trim_start().starts_with('{') {
+        for line in uri_lines(data) {
+            match parse_key_uri(line) {
+                Ok(account) => imported.accounts.push(account),
+                Err(e) => imported.skipped.push(e.to_string()),
+            }
+        }
+        return Ok(imported);
+    }
+
+    let backup: Backup = serde_json::from_str(data)?;
+    for mut token in backup.tokens {
+        let otp_type = match token.token_type.as_str() {
+            "TOTP" => OtpType::Totp,
+            "HOTP" => OtpType::Hotp,
+            other => {
+                imported.skipped.push(format!("{}: unsupported type '{}'", token.label, other));
+                continue;
+            }
+        };
+        let Some(algorithm) = Algorithm::parse(&token.algo) else {
+            imported.skipped.push(format!("{}: unsupported algorithm '{}'", token.label, token.algo));
+            continue;
+        };
+
+        let key = Zeroizing::new(token.secret.iter().map(|&b| b as u8).collect::<Vec<u8>>());
+        token.secret.zeroize();
+        let issuer = match token.issuer_ext.trim() {
+            "" => "host".to_string(),
+            issuer => issuer.to_string(),
+        };
+        let mut account = Account::new(token.label, encode_base32(&key), issuer);
+        account.otp_type = otp_type;
+        account.algorithm = algorithm;
+        account.digits = token.digits;
+        account.period = token.period;
+        account.counter = token.counter;
+
+        match account.validate() {
+            Ok(()) => imported.accounts.push(account),
+            Err(e) => imported.skipped.push(e.to_string()),
+        }
+    }
+    Ok(imported)
+}
+
+fn uri_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines().map(str::trim).filter(|line| !line.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JSON_BACKUP: &str = include_str!("../assets/freeotp_plus.json");
+    const URI_BACKUP: &str = include_str!("../assets/freeotp_plus_uris.txt");
+
+    #[test]
+    fn test_import_json_and_uris_agree() {
+        assert!(detect(JSON_BACKUP.as_bytes()));
+        assert!(detect(URI_BACKUP.as_bytes()));
+
+        let from_json = import(JSON_BACKUP).unwrap();
+        let from_uris = import(URI_BACKUP).unwrap();
+        assert_eq!(from_json.accounts.len(), 2);
+        assert_eq!(from_uris.accounts.len(), 2);
+
+        let github = &from_json.accounts[0];
+        assert_eq!((github.name.as_str(), github.issuer.as_str()), ("octocat", "GitHub"));
+        assert_eq!(github.secret.expose(), "JBSWY3DPEHPK3PXP");
+
+        let router = &from_json.accounts[1];
+        assert_eq!(router.issuer, "host");
+        assert_eq!(router.secret.expose(), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!((router.otp_type, router.algorithm), (OtpType::Hotp, Algorithm::Sha256));
+        assert_eq!((router.digits, router.counter), (8, 11));
+
+        for (a, b) in from_json.accounts.iter().zip(&from_uris.accounts) {
+            assert!(a.has_same_secret(b));
+            assert_eq!((a.otp_type, a.digits, a.counter), (b.otp_type, b.digits, b.counter));
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::Result;
+use zeroize::Zeroizing;
+
+use crate::config::Account;
+use crate::formats::{account_from_otp_field, Imported};
+use crate::qrcode::to_key_uri;
+use crate::recovery;
+
+/// Columns written by KeePassXC's CSV export (2.7 and later)
+const HEADER: [&str; 7] = ["Group", "Title", "Username", "Password", "URL", "Notes", "TOTP"];
+/// Name of KeePassXC's root group, which prefixes every group path
+const ROOT_GROUP: &str = "Root";
+
+/// Position of the columns we read, looked up by header name
+struct Columns {
+    group: Option<usize>,
+    title: usize,
+    username: Option<usize>,
+    notes: Option<usize>,
+    otp: usize,
+}
+
+impl Columns {
+    fn from_header(header: &csv::StringRecord) -> Option<Self> {
+        let find = |names: &[&str]| header.iter().position(|column| {
+            names.iter().any(|name| column.trim().eq_ignore_ascii_case(name))
+        });
+
+        Some(Self {
+            group: find(&["Group"]),
+            title: find(&["Title"])?,
+            username: find(&["Username", "UserName"]),
+            notes: find(&["Notes"]),
+            // The `otp` attribute holds the same otpauth URI as the TOTP column
+            otp: find(&["TOTP", "otp"])?,
+        })
+    }
+}
+
+/// Whether the data looks like a KeePassXC CSV export with a TOTP column
+pub(crate) fn detect(data: &[u8]) -> bool {
+    let mut reader = csv::Reader::from_reader(data);
+    reader.headers().is_ok_and(|header| header.len() > 1 && Columns::from_header(header).is_some())
+}
+
+/// Read the TOTP column of a KeePassXC CSV export, ignoring entries without one
+pub(crate) fn import(data: &[u8]) -> Result<Imported> {
+    let mut reader = csv::Reader::from_reader(data);
+    let columns = Columns::from_header(reader.headers()?)
+        .ok_or(anyhow::anyhow!("CSV export has no Title and TOTP (or otp) columns"))?;
+    let mut imported = Imported::default();
+
+    for record in reader.records() {
+        let record = record?;
+        let field = |index: Option<usize>| index.and_then(|i| record.get(i)).unwrap_or_default();
+
+        let otp = Zeroizing::new(field(Some(columns.otp)).to_string());
+        if otp.trim().is_empty() {
+            continue;
+        }
+        let title = field(Some(columns.title));
+        let name = match field(columns.username) {
+            "" => title,
+            username => username,
+        };
+
+        match account_from_otp_field(&otp, name, title) {
+            Ok(mut account) => {
+                account.group = group_from_path(field(columns.group));
+                (account.note, account.recovery_codes) = recovery::split_note(field(columns.notes));
+                imported.accounts.push(account);
+            }
+            Err(e) => imported.skipped.push(format!("{}: {}", title, e)),
+        }
+    }
+    Ok(imported)
+}
+
+/// Write accounts as a KeePassXC CSV export, with the otpauth URI in the TOTP column
+pub(crate) fn export(accounts: &[Account]) -> Result<Zeroizing<String>> {
+    let mut writer = csv::WriterBuilder::new()
+        .quote_style(csv::QuoteStyle::Always)
+        .from_writer(Vec::new());
+    writer.write_record(HEADER)?;
+
+    for account in accounts {
+        let group = match &account.group {
+            Some(group) => format!("{}/{}", ROOT_GROUP, group),
+            None => ROOT_GROUP.to_string(),
+        };
+        let title = if account.issuer == "host" { &account.name } else { &account.issuer };
+        let uri = to_key_uri(account);
+        let notes = recovery::note_with_codes(account).unwrap_or_default();
+        writer.write_record([
+            group.as_str(),
+            title,
+            &account.name,
+            "",
+            "",
+            notes.as_str(),
+            uri.as_str(),
+        ])?;
+    }
+
+    let data = Zeroizing::new(writer.into_inner().map_err(|e| anyhow::anyhow!(e.to_string()))?);
+    Ok(Zeroizing::new(String::from_utf8(data.to_vec())?))
+}
+
+/// `Root/Work/VPN` becomes `Work/VPN`; entries directly under the root have no group
+fn group_from_path(path: &str) -> Option<String> {
+    let path = path.trim_matches('/');
+    let path = path.strip_prefix(ROOT_GROUP).map(|rest| rest.trim_start_matches('/')).unwrap_or(path);
+    Some(path.to_string()).filter(|group| !group.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORT: &str = include_str!("../assets/keepassxc.csv");
+
+    #[test]
+    fn test_import_export() {
+        assert!(detect(EXPORT.as_bytes()));
+
+        let imported = import(EXPORT.as_bytes()).unwrap();
+        assert_eq!(imported.accounts.len(), 2);
+        assert!(imported.skipped.is_empty());
+
+        let github = &imported.accounts[0];
+        assert_eq!((github.name.as_str(), github.issuer.as_str()), ("octocat", "GitHub"));
+        assert_eq!(github.group.as_deref(), Some("Work/Dev"));
+        assert_eq!(github.note.as_deref(), Some("line one\nline two"));
+
+        let router = &imported.accounts[1];
+        assert_eq!((router.name.as_str(), router.issuer.as_str()), ("Router", "Router"));
+        assert_eq!(router.group, None);
+
+        let mut accounts = imported.accounts.clone();
+        recovery::add_codes(&mut accounts[0], &[Zeroizing::new("k7m2p-q9x4t".to_string())]);
+        let exported = export(&accounts).unwrap();
+        assert!(!exported.contains("hunter2"));
+        let reimported = import(exported.as_bytes()).unwrap();
+        assert_eq!(reimported.accounts[0].recovery_codes, accounts[0].recovery_codes);
+        assert_eq!(reimported.accounts.len(), 2);
+        assert!(reimported.accounts[0].has_same_secret(github));
+        assert_eq!(reimported.accounts[0].group.as_deref(), Some("Work/Dev"));
+        assert_eq!(reimported.accounts[0].note.as_deref(), Some("line one\nline two"));
+    }
+
+    #[test]
+    fn test_group_from_path() {
+        assert_eq!(group_from_path("Root"), None);
+        assert_eq!(group_from_path("Root/Work"), Some("Work".to_string()));
+        assert_eq!(group_from_path("Personal"), Some("Personal".to_string()));
+    }
+}
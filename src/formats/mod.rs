@@ -0,0 +1,271 @@
+pub(crate) mod aegis;
+pub(crate) mod andotp;
+pub(crate) mod bitwarden;
+pub(crate) mod freeotp;
+pub(crate) mod keepassxc;
+pub(crate) mod pass;
+pub(crate) mod table;
+pub(crate) mod twofas;
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use zeroize::Zeroizing;
+
+use crate::config::Account;
+use crate::crypto;
+use crate::qrcode;
+use crate::secret::SecretString;
+use table::{Field, FieldMap, TableFormat};
+
+/// Formats accepted by `otpc import`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// Detect the format from the file contents
+    Auto,
+    /// QR code image containing an otpauth URI
+    Qr,
+    /// Aegis JSON backup (plain or encrypted)
+    Aegis,
+    /// 2FAS backup (plain or encrypted)
+    #[value(name = "2fas")]
+    TwoFas,
+    /// andOTP backup (plain JSON or .json.aes)
+    Andotp,
+    /// FreeOTP+ JSON export or list of otpauth URIs
+    Freeotp,
+    /// Bitwarden unencrypted JSON export (login TOTP fields)
+    Bitwarden,
+    /// KeePassXC CSV export (TOTP or otp column)
+    Keepassxc,
+    /// pass-otp: a password store directory, or one decrypted entry
+    Pass,
+    /// CSV with a header row (columns chosen with --map)
+    Csv,
+    /// JSON array of accounts, or an object with an "accounts" array
+    Json,
+    /// TOML with an [[accounts]] array
+    Toml,
+}
+
+impl ImportFormat {
+    /// Guess the format of a backup from its contents
+    pub(crate) fn detect(data: &[u8]) -> Option<Self> {
+        if image::guess_format(data).is_ok() {
+            Some(Self::Qr)
+        } else if aegis::detect(data) {
+            Some(Self::Aegis)
+        } else if twofas::detect(data) {
+            Some(Self::TwoFas)
+        } else if bitwarden::detect(data) {
+            Some(Self::Bitwarden)
+        } else if freeotp::detect(data) {
+            Some(Self::Freeotp)
+        } else if keepassxc::detect(data) {
+            Some(Self::Keepassxc)
+        } else if andotp::detect(data) {
+            Some(Self::Andotp)
+        } else {
+            table::detect(data).map(|format| match format {
+                TableFormat::Csv => Self::Csv,
+                TableFormat::Json => Self::Json,
+                TableFormat::Toml => Self::Toml,
+            })
+        }
+    }
+}
+
+/// Formats produced by `otpc export`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Aegis JSON backup (plain or encrypted)
+    Aegis,
+    /// Bitwarden unencrypted JSON export
+    Bitwarden,
+    /// KeePassXC CSV (importable through KeePassXC's CSV import)
+    Keepassxc,
+    /// pass-otp entries inserted into the password store with `pass insert`
+    Pass,
+    /// CSV spreadsheet, one row per account
+    Csv,
+    /// JSON object with an "accounts" array
+    Json,
+    /// TOML with an [[accounts]] array
+    Toml,
+}
+
+/// Accounts read from a backup, plus entries that could not be mapped
+#[derive(Debug, Default)]
+pub(crate) struct Imported {
+    pub accounts: Vec<Account>,
+    /// Human readable reason for every entry left out
+    pub skipped: Vec<String>,
+}
+
+/// Read accounts from a backup file, or from a whole password store directory for `pass`
+pub(crate) fn import_file(
+    format: ImportFormat,
+    path: &Path,
+    map: &FieldMap,
+    password: impl FnOnce() -> Result<Zeroizing<String>>,
+) -> Result<Imported> {
+    if format == ImportFormat::Pass && path.is_dir() {
+        return pass::import_store(path);
+    }
+
+    let data = Zeroizing::new(fs::read(path)?);
+    import(format, &data, map, password)
+}
+
+/// Read accounts from a backup, asking for the password only if it is encrypted
+pub(crate) fn import(
+    format: ImportFormat,
+    data: &[u8],
+    map: &FieldMap,
+    password: impl FnOnce() -> Result<Zeroizing<String>>,
+) -> Result<Imported> {
+    let format = match format {
+        ImportFormat::Auto => ImportFormat::detect(data)
+            .ok_or(anyhow::anyhow!("Unrecognized backup format, pass --format explicitly"))?,
+        format => format,
+    };
+
+    match format {
+        ImportFormat::Auto => unreachable!("format was detected above"),
+        ImportFormat::Qr => Ok(Imported {
+            accounts: vec![qrcode::parse_qr_from_bytes(data)?],
+            skipped: Vec::new(),
+        }),
+        ImportFormat::Aegis => aegis::import(as_text(data)?, password),
+        ImportFormat::TwoFas => twofas::import(as_text(data)?, password),
+        ImportFormat::Andotp => andotp::import(data, password),
+        ImportFormat::Freeotp => freeotp::import(as_text(data)?),
+        ImportFormat::Bitwarden => bitwarden::import(as_text(data)?),
+        ImportFormat::Keepassxc => keepassxc::import(data),
+        ImportFormat::Pass => Ok(Imported {
+            accounts: vec![pass::import_entry(as_text(data)?)?],
+            skipped: Vec::new(),
+        }),
+        ImportFormat::Csv => table::import(TableFormat::Csv, as_text(data)?, map),
+        ImportFormat::Json => table::import(TableFormat::Json, as_text(data)?, map),
+        ImportFormat::Toml => table::import(TableFormat::Toml, as_text(data)?, map),
+    }
+}
+
+/// Write accounts in a file format; `pass` writes into the password store instead
+///
+/// The password only applies to Aegis backups, the field selection only to CSV, JSON and TOML.
+pub(crate) fn export(
+    format: ExportFormat,
+    accounts: &[Account],
+    password: Option<&str>,
+    fields: &[Field],
+) -> Result<Zeroizing<String>> {
+    match format {
+        ExportFormat::Aegis => aegis::export(accounts, password),
+        ExportFormat::Bitwarden => bitwarden::export(accounts),
+        ExportFormat::Keepassxc => keepassxc::export(accounts),
+        ExportFormat::Pass => Err(anyhow::anyhow!("pass entries are written to the password store, not a file")),
+        ExportFormat::Csv => table::export(TableFormat::Csv, accounts, fields),
+        ExportFormat::Json => table::export(TableFormat::Json, accounts, fields),
+        ExportFormat::Toml => table::export(TableFormat::Toml, accounts, fields),
+    }
+}
+
+/// Map a password manager TOTP field, an otpauth URI or a bare Base32 secret, to an account
+///
+/// `name` and `issuer` come from the surrounding entry and fill in what the field lacks.
+pub(crate) fn account_from_otp_field(value: &str, name: &str, issuer: &str) -> Result<Account> {
+    let value = value.trim();
+    if value.starts_with("otpauth://") {
+        let mut account = qrcode::parse_key_uri(value)?;
+        if account.issuer == "host" && !issuer.trim().is_empty() {
+            account.issuer = issuer.trim().to_string();
+        }
+        return Ok(account);
+    }
+
+    let secret = SecretString::new(value.chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_ascii_uppercase())
+        .collect());
+    let issuer = match issuer.trim() {
+        "" => "host",
+        issuer => issuer,
+    };
+    let account = Account::new(name.trim().to_string(), secret, issuer.to_string());
+    account.validate()?;
+    Ok(account)
+}
+
+fn as_text(data: &[u8]) -> Result<&str> {
+    std::str::from_utf8(data).map_err(|_| anyhow::anyhow!("Backup is not valid UTF-8 text"))
+}
+
+pub(crate) fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn hex_decode(input: &str) -> Result<Vec<u8>> {
+    if !input.len().is_multiple_of(2) {
+        return Err(anyhow::anyhow!("Invalid hex string"));
+    }
+
+    (0..input.len())
+        .step_by(2)
+        .map(|i| {
+            input.get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or(anyhow::anyhow!("Invalid hex string"))
+        })
+        .collect()
+}
+
+/// Random RFC 4122 version 4 UUID
+pub(crate) fn random_uuid() -> Result<String> {
+    let mut bytes = crypto::random_bytes::<16>()?;
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex = hex_encode(&bytes);
+    Ok(format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_formats() {
+        let detect = ImportFormat::detect;
+        assert_eq!(detect(include_bytes!("../assets/example.png")), Some(ImportFormat::Qr));
+        assert_eq!(detect(include_bytes!("../assets/aegis_encrypted.json")), Some(ImportFormat::Aegis));
+        assert_eq!(detect(include_bytes!("../assets/2fas_encrypted.2fas")), Some(ImportFormat::TwoFas));
+        assert_eq!(detect(include_bytes!("../assets/andotp_plain.json")), Some(ImportFormat::Andotp));
+        assert_eq!(detect(include_bytes!("../assets/andotp_encrypted.json.aes")), Some(ImportFormat::Andotp));
+        assert_eq!(detect(include_bytes!("../assets/freeotp_plus.json")), Some(ImportFormat::Freeotp));
+        assert_eq!(detect(include_bytes!("../assets/freeotp_plus_uris.txt")), Some(ImportFormat::Freeotp));
+        assert_eq!(detect(include_bytes!("../assets/bitwarden.json")), Some(ImportFormat::Bitwarden));
+        assert_eq!(detect(include_bytes!("../assets/keepassxc.csv")), Some(ImportFormat::Keepassxc));
+        assert_eq!(detect(b"name,secret\nalice,JBSWY3DPEHPK3PXP\n"), Some(ImportFormat::Csv));
+        assert_eq!(detect(b"[[accounts]]\nname = \"alice\"\n"), Some(ImportFormat::Toml));
+        assert_eq!(detect(b"{\"hello\": \"world\"}"), None);
+    }
+
+    #[test]
+    fn test_hex_roundtrip() {
+        assert_eq!(hex_encode(&[0x00, 0xab, 0xff]), "00abff");
+        assert_eq!(hex_decode("00ABff").unwrap(), vec![0x00, 0xab, 0xff]);
+        assert!(hex_decode("abc").is_err());
+        assert!(hex_decode("zz").is_err());
+    }
+
+    #[test]
+    fn test_random_uuid_format() {
+        let uuid = random_uuid().unwrap();
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(&uuid[14..15], "4");
+    }
+}
@@ -0,0 +1,157 @@
+use std::env;
+use std::fs;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::Result;
+use zeroize::Zeroizing;
+
+use crate::config::Account;
+use crate::formats::Imported;
+use crate::qrcode::{parse_key_uri, to_key_uri};
+use crate::recovery;
+
+/// Read a single decrypted pass entry; pass-otp keeps the otpauth URI on its first such line
+///
+/// Recovery codes listed below the URI by `export_store` come back with the account.
+pub(crate) fn import_entry(text: &str) -> Result<Account> {
+    let uri = text.lines()
+        .map(str::trim)
+        .find(|line| line.starts_with("otpauth://"))
+        .ok_or(anyhow::anyhow!("Entry has no otpauth URI"))?;
+    let mut account = parse_key_uri(uri)?;
+    (_, account.recovery_codes) = recovery::split_note(text);
+    Ok(account)
+}
+
+/// Read every OTP entry below a password store directory through `pass show`
+pub(crate) fn import_store(dir: &Path) -> Result<Imported> {
+    let mut entries = Vec::new();
+    collect_entries(dir, dir, &mut entries)?;
+    entries.sort();
+
+    let mut imported = Imported::default();
+    for entry in entries {
+        let output = Command::new("pass")
+            .arg("show")
+            .arg(&entry)
+            .env("PASSWORD_STORE_DIR", dir)
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| anyhow::anyhow!("Failed to run pass: {}", e))?;
+        let text = Zeroizing::new(String::from_utf8_lossy(&output.stdout).into_owned());
+        if !output.status.success() {
+            imported.skipped.push(format!("{}: pass show failed", entry));
+            continue;
+        }
+
+        // Entries without an otpauth URI are ordinary passwords
+        if text.contains("otpauth://") {
+            match import_entry(&text) {
+                Ok(account) => imported.accounts.push(account),
+                Err(e) => imported.skipped.push(format!("{}: {}", entry, e)),
+            }
+        }
+    }
+    Ok(imported)
+}
+
+fn collect_entries(root: &Path, dir: &Path, entries: &mut Vec<String>) -> Result<()> {
+    for item in fs::read_dir(dir)? {
+        let path = item?.path();
+        let hidden = path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if hidden {
+            continue;
+        }
+
+        if path.is_dir() {
+            collect_entries(root, &path, entries)?;
+        } else if path.extension().is_some_and(|ext| ext == "gpg") {
+            let entry = path.strip_prefix(root)?.with_extension("");
+            entries.push(entry.to_string_lossy().replace('\\', "/"));
+        }
+    }
+    Ok(())
+}
+
+/// Entry path for an account below `prefix`, e.g. `otp/GitHub/octocat`
+pub(crate) fn entry_name(prefix: &str, account: &Account) -> String {
+    let sanitize = |part: &str| part.replace('/', "-");
+    let prefix = prefix.trim_matches('/');
+    let mut parts: Vec<String> = Vec::new();
+    if !prefix.is_empty() {
+        parts.push(prefix.to_string());
+    }
+    if account.issuer != "host" && !account.issuer.is_empty() {
+        parts.push(sanitize(&account.issuer));
+    }
+    parts.push(sanitize(&account.name));
+    parts.join("/")
+}
+
+/// Write every account as a pass-otp entry through `pass insert`, leaving existing entries alone
+///
+/// Returns the names of the entries that were created and of those that already existed.
+pub(crate) fn export_store(accounts: &[Account], prefix: &str) -> Result<(Vec<String>, Vec<String>)> {
+    let store = store_dir()?;
+    let (mut created, mut existing) = (Vec::new(), Vec::new());
+
+    for account in accounts {
+        let entry = entry_name(prefix, account);
+        if store.join(format!("{}.gpg", entry)).exists() {
+            existing.push(entry);
+            continue;
+        }
+
+        let mut child = Command::new("pass")
+            .args(["insert", "--multiline", &entry])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("Failed to run pass: {}", e))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(to_key_uri(account).as_bytes())?;
+            stdin.write_all(b"\n")?;
+            if let Some(note) = recovery::note_with_codes(account) {
+                stdin.write_all(note.as_bytes())?;
+                stdin.write_all(b"\n")?;
+            }
+        }
+        if !child.wait()?.success() {
+            return Err(anyhow::anyhow!("pass insert {} failed", entry));
+        }
+        created.push(entry);
+    }
+    Ok((created, existing))
+}
+
+fn store_dir() -> Result<PathBuf> {
+    if let Some(dir) = env::var_os("PASSWORD_STORE_DIR") {
+        return Ok(PathBuf::from(dir));
+    }
+    let home = env::var_os("HOME").ok_or(anyhow::anyhow!("HOME is not set"))?;
+    Ok(PathBuf::from(home).join(".password-store"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_entry() {
+        let entry = "otpauth://totp/GitHub:octocat?secret=JBSWY3DPEHPK3PXP&issuer=GitHub\nlogin: octocat\n";
+        let account = import_entry(entry).unwrap();
+        assert_eq!((account.name.as_str(), account.issuer.as_str()), ("octocat", "GitHub"));
+        assert!(import_entry("hunter2\nlogin: octocat\n").is_err());
+
+        assert_eq!(entry_name("otp/", &account), "otp/GitHub/octocat");
+        let reparsed = import_entry(&to_key_uri(&account)).unwrap();
+        assert!(reparsed.has_same_secret(&account));
+
+        let with_codes = format!("{}\nlogin: octocat\n\nRecovery codes:\nk7m2p-q9x4t (used)\nh3w8c-r6n5v\n", entry.lines().next().unwrap());
+        let account = import_entry(&with_codes).unwrap();
+        assert_eq!(account.recovery_codes.len(), 2);
+        assert!(account.recovery_codes[0].used && !account.recovery_codes[1].used);
+    }
+}
@@ -0,0 +1,475 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::ser::{SerializeMap, Serializer};
+use serde::Serialize;
+use zeroize::Zeroizing;
+
+use crate::config::{Account, RecoveryCode};
+use crate::formats::Imported;
+use crate::otp::{Algorithm, OtpType};
+use crate::recovery;
+use crate::secret::SecretString;
+
+/// Separator between tags in a single text column
+const TAG_SEPARATOR: &str = ";";
+
+/// Generic spreadsheet-style formats, one row per account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TableFormat {
+    Csv,
+    Json,
+    Toml,
+}
+
+/// Account fields that can be exported or mapped from import columns
+///
+/// The names match the keys of the account store, so a plain (unencrypted)
+/// otpc config file can be imported as TOML without a mapping.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Name,
+    Issuer,
+    Secret,
+    Type,
+    Algorithm,
+    Digits,
+    Period,
+    Counter,
+    Group,
+    Tags,
+    Favorite,
+    Note,
+    #[value(name = "recovery_codes")]
+    RecoveryCodes,
+}
+
+impl Field {
+    const ALL: [Field; 13] = [
+        Field::Name, Field::Issuer, Field::Secret, Field::Type, Field::Algorithm,
+        Field::Digits, Field::Period, Field::Counter, Field::Group, Field::Tags,
+        Field::Favorite, Field::Note, Field::RecoveryCodes,
+    ];
+
+    /// Fields only exported with `--include-secrets`
+    fn is_sensitive(self) -> bool {
+        matches!(self, Field::Secret | Field::RecoveryCodes)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Field::Name => "name",
+            Field::Issuer => "issuer",
+            Field::Secret => "secret",
+            Field::Type => "type",
+            Field::Algorithm => "algorithm",
+            Field::Digits => "digits",
+            Field::Period => "period",
+            Field::Counter => "counter",
+            Field::Group => "group",
+            Field::Tags => "tags",
+            Field::Favorite => "favorite",
+            Field::Note => "note",
+            Field::RecoveryCodes => "recovery_codes",
+        }
+    }
+
+    /// The field as text, empty when it is not set
+    fn text(self, account: &Account) -> Zeroizing<String> {
+        Zeroizing::new(match self {
+            Field::Name => account.name.clone(),
+            Field::Issuer => account.issuer.clone(),
+            Field::Secret => account.secret.expose().to_string(),
+            Field::Type => otp_type_name(account.otp_type).to_string(),
+            Field::Algorithm => account.algorithm.as_str().to_string(),
+            Field::Digits => account.digits.to_string(),
+            Field::Period => account.period.to_string(),
+            Field::Counter => account.counter.to_string(),
+            Field::Group => account.group.clone().unwrap_or_default(),
+            Field::Tags => account.tags.join(TAG_SEPARATOR),
+            Field::Favorite => account.favorite.to_string(),
+            Field::Note => account.note.clone().unwrap_or_default(),
+            Field::RecoveryCodes => join_items(account.recovery_codes.iter().map(recovery::to_text)),
+        })
+    }
+}
+
+fn otp_type_name(otp_type: OtpType) -> &'static str {
+    match otp_type {
+        OtpType::Totp => "totp",
+        OtpType::Hotp => "hotp",
+    }
+}
+
+/// Fields to export: the requested ones (all by default), with the secret and recovery codes only when explicitly allowed
+pub(crate) fn select_fields(requested: &[Field], include_secrets: bool) -> Result<Vec<Field>> {
+    let mut fields: Vec<Field> = if requested.is_empty() {
+        Field::ALL.iter().copied().filter(|field| !field.is_sensitive()).collect()
+    } else {
+        requested.to_vec()
+    };
+
+    if let Some(field) = fields.iter().find(|field| field.is_sensitive()).filter(|_| !include_secrets) {
+        return Err(anyhow::anyhow!("Exporting the {} field requires --include-secrets", field.name()));
+    }
+    if include_secrets && !fields.contains(&Field::Secret) {
+        let position = fields.iter().position(|field| *field == Field::Issuer).map_or(fields.len(), |i| i + 1);
+        fields.insert(position, Field::Secret);
+    }
+    if include_secrets && requested.is_empty() {
+        fields.push(Field::RecoveryCodes);
+    }
+    Ok(fields)
+}
+
+/// One account serialized with only the selected fields, in order
+struct Row<'a> {
+    account: &'a Account,
+    fields: &'a [Field],
+}
+
+impl Serialize for Row<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let account = self.account;
+        let mut map = serializer.serialize_map(None)?;
+        for field in self.fields {
+            let key = field.name();
+            match field {
+                Field::Name => map.serialize_entry(key, &account.name)?,
+                Field::Issuer => map.serialize_entry(key, &account.issuer)?,
+                Field::Secret => map.serialize_entry(key, &account.secret)?,
+                Field::Type => map.serialize_entry(key, &account.otp_type)?,
+                Field::Algorithm => map.serialize_entry(key, &account.algorithm)?,
+                Field::Digits => map.serialize_entry(key, &account.digits)?,
+                Field::Period => map.serialize_entry(key, &account.period)?,
+                Field::Counter => map.serialize_entry(key, &account.counter)?,
+                // TOML has no null, so unset optional fields are left out
+                Field::Group => if let Some(group) = &account.group {
+                    map.serialize_entry(key, group)?
+                },
+                Field::Tags => map.serialize_entry(key, &account.tags)?,
+                Field::Favorite => map.serialize_entry(key, &account.favorite)?,
+                Field::Note => if let Some(note) = &account.note {
+                    map.serialize_entry(key, note)?
+                },
+                Field::RecoveryCodes => if !account.recovery_codes.is_empty() {
+                    map.serialize_entry(key, &account.recovery_codes)?
+                },
+            }
+        }
+        map.end()
+    }
+}
+
+#[derive(Serialize)]
+struct Document<'a> {
+    accounts: Vec<Row<'a>>,
+}
+
+/// Write the selected fields of every account
+pub(crate) fn export(format: TableFormat, accounts: &[Account], fields: &[Field]) -> Result<Zeroizing<String>> {
+    let document = Document {
+        accounts: accounts.iter().map(|account| Row { account, fields }).collect(),
+    };
+
+    match format {
+        TableFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            writer.write_record(fields.iter().map(|field| field.name()))?;
+            for account in accounts {
+                let values: Vec<Zeroizing<String>> = fields.iter().map(|field| field.text(account)).collect();
+                writer.write_record(values.iter().map(|value| value.as_str()))?;
+            }
+            let data = Zeroizing::new(writer.into_inner().map_err(|e| anyhow::anyhow!(e.to_string()))?);
+            Ok(Zeroizing::new(String::from_utf8(data.to_vec())?))
+        }
+        TableFormat::Json => Ok(Zeroizing::new(serde_json::to_string_pretty(&document)?)),
+        TableFormat::Toml => Ok(Zeroizing::new(toml::to_string(&document)?)),
+    }
+}
+
+/// Which input column holds each account field
+#[derive(Debug)]
+pub(crate) struct FieldMap(Vec<(Field, String)>);
+
+impl FieldMap {
+    /// Parse `field=column` pairs; fields not mentioned are read from the column of the same name
+    pub(crate) fn parse(specs: &[String]) -> Result<Self> {
+        let mut columns: Vec<(Field, String)> = Field::ALL.iter()
+            .map(|field| (*field, field.name().to_string()))
+            .collect();
+
+        for spec in specs {
+            let (field, column) = spec.split_once('=')
+                .ok_or(anyhow::anyhow!("Invalid mapping '{}', expected field=column", spec))?;
+            let field = Field::from_str(field.trim(), true)
+                .map_err(|_| anyhow::anyhow!("Unknown field '{}' in mapping", field.trim()))?;
+            if let Some(entry) = columns.iter_mut().find(|(f, _)| *f == field) {
+                entry.1 = column.trim().to_string();
+            }
+        }
+        Ok(Self(columns))
+    }
+
+    fn column(&self, field: Field) -> &str {
+        self.0.iter()
+            .find(|(f, _)| *f == field)
+            .map(|(_, column)| column.as_str())
+            .unwrap_or(field.name())
+    }
+
+    /// Build and validate an account from one input row
+    fn account(&self, row: &Record) -> Result<Account> {
+        let value = |field: Field| {
+            row.get(self.column(field))
+                .map(|value| value.trim())
+                .filter(|value| !value.is_empty())
+        };
+        let number = |field: Field| -> Result<Option<u64>> {
+            value(field)
+                .map(|v| v.parse().map_err(|_| anyhow::anyhow!("Invalid {} '{}'", field.name(), v)))
+                .transpose()
+        };
+
+        let name = value(Field::Name).ok_or(anyhow::anyhow!("Missing name"))?;
+        let secret = value(Field::Secret).ok_or(anyhow::anyhow!("Missing secret for '{}'", name))?;
+        let issuer = value(Field::Issuer).unwrap_or("host");
+
+        let mut account = Account::new(name.to_string(), SecretString::from(secret), issuer.to_string());
+        if let Some(otp_type) = value(Field::Type) {
+            account.otp_type = OtpType::from_str(otp_type, true)
+                .map_err(|_| anyhow::anyhow!("Unsupported type '{}'", otp_type))?;
+        }
+        if let Some(algorithm) = value(Field::Algorithm) {
+            account.algorithm = Algorithm::parse(algorithm)
+                .ok_or(anyhow::anyhow!("Unsupported algorithm '{}'", algorithm))?;
+        }
+        if let Some(digits) = number(Field::Digits)? {
+            account.digits = u32::try_from(digits)?;
+        }
+        account.period = number(Field::Period)?.unwrap_or(account.period);
+        account.counter = number(Field::Counter)?.unwrap_or(account.counter);
+        account.group = value(Field::Group).map(str::to_string);
+        account.tags = value(Field::Tags)
+            .map(|tags| tags.split([';', ',']).map(str::trim).filter(|tag| !tag.is_empty()).map(str::to_string).collect())
+            .unwrap_or_default();
+        if let Some(favorite) = value(Field::Favorite) {
+            account.favorite = matches!(favorite.to_lowercase().as_str(), "true" | "yes" | "1");
+        }
+        account.note = value(Field::Note).map(str::to_string);
+        account.recovery_codes = value(Field::RecoveryCodes)
+            .map(|codes| codes.split(TAG_SEPARATOR).filter_map(recovery::from_text).collect())
+            .unwrap_or_default();
+
+        account.validate()?;
+        Ok(account)
+    }
+}
+
+/// Guess which table format the data is in, if it holds accounts with a name and secret
+pub(crate) fn detect(data: &[u8]) -> Option<TableFormat> {
+    let text = std::str::from_utf8(data).ok()?;
+    let has_accounts = |rows: Option<usize>| rows.is_some_and(|rows| rows > 0);
+
+    if let Ok(value) = serde_json::from_str::<serde_json::Value>(text) {
+        let rows = value.get("accounts").and_then(serde_json::Value::as_array).map(Vec::len);
+        return has_accounts(rows).then_some(TableFormat::Json);
+    }
+    if let Ok(table) = toml::from_str::<toml::Table>(text) {
+        let rows = table.get("accounts").and_then(toml::Value::as_array).map(Vec::len);
+        return has_accounts(rows).then_some(TableFormat::Toml);
+    }
+
+    let mut reader = csv::Reader::from_reader(data);
+    let header = reader.headers().ok()?;
+    let has_column = |name: &str| header.iter().any(|column| column.trim() == name);
+    (has_column("name") && has_column("secret")).then_some(TableFormat::Csv)
+}
+
+/// One input row: column name to value
+type Record = BTreeMap<String, Zeroizing<String>>;
+
+/// Read accounts from rows, mapping columns to fields
+pub(crate) fn import(format: TableFormat, data: &str, map: &FieldMap) -> Result<Imported> {
+    let rows = match format {
+        TableFormat::Csv => csv_records(data)?,
+        TableFormat::Json => {
+            let value: serde_json::Value = serde_json::from_str(data)?;
+            let rows = match &value {
+                serde_json::Value::Array(rows) => rows,
+                value => value.get("accounts").and_then(serde_json::Value::as_array)
+                    .ok_or(anyhow::anyhow!("Expected an array of accounts or an \"accounts\" array"))?,
+            };
+            rows.iter().map(json_record).collect()
+        }
+        TableFormat::Toml => {
+            let value: toml::Table = toml::from_str(data)?;
+            let rows = value.get("accounts").and_then(toml::Value::as_array)
+                .ok_or(anyhow::anyhow!("Expected an [[accounts]] array"))?;
+            rows.iter().map(toml_record).collect()
+        }
+    };
+
+    let mut imported = Imported::default();
+    for (index, row) in rows.iter().enumerate() {
+        match map.account(row) {
+            Ok(account) => imported.accounts.push(account),
+            Err(e) => imported.skipped.push(format!("row {}: {}", index + 1, e)),
+        }
+    }
+    Ok(imported)
+}
+
+fn csv_records(data: &str) -> Result<Vec<Record>> {
+    let mut reader = csv::Reader::from_reader(data.as_bytes());
+    let header: Vec<String> = reader.headers()?.iter().map(|column| column.trim().to_string()).collect();
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        rows.push(header.iter().cloned()
+            .zip(record.iter().map(|value| Zeroizing::new(value.to_string())))
+            .collect());
+    }
+    Ok(rows)
+}
+
+fn json_record(row: &serde_json::Value) -> Record {
+    let Some(object) = row.as_object() else {
+        return Record::new();
+    };
+    object.iter()
+        .filter_map(|(key, value)| {
+            let text = match value {
+                serde_json::Value::String(text) => text.clone(),
+                serde_json::Value::Number(number) => number.to_string(),
+                serde_json::Value::Bool(flag) => flag.to_string(),
+                serde_json::Value::Array(items) => join_items(items.iter().filter_map(|item| match item {
+                    serde_json::Value::String(text) => Some(Zeroizing::new(text.clone())),
+                    // Recovery codes, as the store and exports write them
+                    item => serde_json::from_value::<RecoveryCode>(item.clone()).ok().map(|code| recovery::to_text(&code)),
+                })),
+                _ => return None,
+            };
+            Some((key.clone(), Zeroizing::new(text)))
+        })
+        .collect()
+}
+
+/// List items in a single text column
+fn join_items(items: impl Iterator<Item = Zeroizing<String>>) -> String {
+    let items: Vec<Zeroizing<String>> = items.collect();
+    items.iter().map(|item| item.as_str()).collect::<Vec<_>>().join(TAG_SEPARATOR)
+}
+
+fn toml_record(row: &toml::Value) -> Record {
+    let Some(table) = row.as_table() else {
+        return Record::new();
+    };
+    table.iter()
+        .filter_map(|(key, value)| {
+            let text = match value {
+                toml::Value::String(text) => text.clone(),
+                toml::Value::Integer(number) => number.to_string(),
+                toml::Value::Boolean(flag) => flag.to_string(),
+                toml::Value::Array(items) => join_items(items.iter().filter_map(|item| match item {
+                    toml::Value::String(text) => Some(Zeroizing::new(text.clone())),
+                    item => item.clone().try_into::<RecoveryCode>().ok().map(|code| recovery::to_text(&code)),
+                })),
+                _ => return None,
+            };
+            Some((key.clone(), Zeroizing::new(text)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_accounts() -> Vec<Account> {
+        let mut bank = Account::new("customer-42".to_string(), "GEZDGNBVGY3TQOJQ".into(), "Bank".to_string());
+        bank.otp_type = OtpType::Hotp;
+        bank.algorithm = Algorithm::Sha256;
+        bank.digits = 8;
+        bank.counter = 3;
+        bank.group = Some("Finance".to_string());
+        bank.tags = vec!["customer".to_string(), "eu".to_string()];
+        bank.favorite = true;
+        bank.note = Some("card reader, \"old\" one".to_string());
+        bank.recovery_codes = vec![
+            RecoveryCode { code: "k7m2p-q9x4t".into(), used: true },
+            RecoveryCode { code: "h3w8c-r6n5v".into(), used: false },
+        ];
+        vec![
+            Account::new("octocat".to_string(), "JBSWY3DPEHPK3PXP".into(), "GitHub".to_string()),
+            bank,
+        ]
+    }
+
+    #[test]
+    fn test_select_fields() {
+        let fields = select_fields(&[], false).unwrap();
+        assert!(!fields.contains(&Field::Secret));
+        assert_eq!(select_fields(&[], true).unwrap()[2], Field::Secret);
+        assert!(select_fields(&[Field::Name, Field::Secret], false).is_err());
+        assert!(select_fields(&[Field::Name, Field::RecoveryCodes], false).is_err());
+        assert_eq!(select_fields(&[Field::Name], true).unwrap(), [Field::Name, Field::Secret]);
+        assert_eq!(select_fields(&[], true).unwrap().last(), Some(&Field::RecoveryCodes));
+    }
+
+    #[test]
+    fn test_export_without_secrets() {
+        let accounts = sample_accounts();
+        let fields = select_fields(&[], false).unwrap();
+        for format in [TableFormat::Csv, TableFormat::Json, TableFormat::Toml] {
+            let data = export(format, &accounts, &fields).unwrap();
+            assert!(!data.contains("JBSWY3DPEHPK3PXP"), "{:?} export leaked a secret", format);
+            assert!(!data.contains("h3w8c-r6n5v"), "{:?} export leaked a recovery code", format);
+            assert!(data.contains("customer-42"));
+        }
+
+        let csv = export(TableFormat::Csv, &accounts, &[Field::Issuer, Field::Name]).unwrap();
+        assert_eq!(csv.lines().next(), Some("issuer,name"));
+    }
+
+    #[test]
+    fn test_roundtrip_with_secrets() {
+        let accounts = sample_accounts();
+        let fields = select_fields(&[], true).unwrap();
+        let map = FieldMap::parse(&[]).unwrap();
+
+        for format in [TableFormat::Csv, TableFormat::Json, TableFormat::Toml] {
+            let data = export(format, &accounts, &fields).unwrap();
+            let imported = import(format, &data, &map).unwrap();
+            assert!(imported.skipped.is_empty(), "{:?}: {:?}", format, imported.skipped);
+
+            let bank = &imported.accounts[1];
+            assert!(bank.has_same_secret(&accounts[1]));
+            assert_eq!((bank.otp_type, bank.algorithm), (OtpType::Hotp, Algorithm::Sha256));
+            assert_eq!((bank.digits, bank.counter), (8, 3));
+            assert_eq!(bank.note, accounts[1].note);
+            assert_eq!(bank.tags, ["customer", "eu"]);
+            assert_eq!(bank.recovery_codes, accounts[1].recovery_codes);
+            assert!(bank.favorite && !imported.accounts[0].favorite);
+            assert_eq!(imported.accounts[0].group, None);
+        }
+    }
+
+    #[test]
+    fn test_import_csv_with_mapping() {
+        let data = "Service,Login,Seed,Digits\nGitHub,octocat,JBSWY3DPEHPK3PXP,6\nBroken,bob,not base32!,6\nGitLab,,JBSWY3DPEHPK3PXP,6\n";
+        let specs = ["issuer=Service".to_string(), "name=Login".to_string(), "secret=Seed".to_string(), "digits=Digits".to_string()];
+        let imported = import(TableFormat::Csv, data, &FieldMap::parse(&specs).unwrap()).unwrap();
+
+        assert_eq!(imported.accounts.len(), 1);
+        assert_eq!((imported.accounts[0].name.as_str(), imported.accounts[0].issuer.as_str()), ("octocat", "GitHub"));
+        assert_eq!(imported.skipped.len(), 2);
+        assert!(imported.skipped[0].starts_with("row 2"));
+        assert!(imported.skipped[1].contains("Missing name"));
+
+        assert!(FieldMap::parse(&["colour=Colour".to_string()]).is_err());
+        assert!(FieldMap::parse(&["name".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::Deserialize;
+use serde_json::Value;
+use zeroize::Zeroizing;
+
+use crate::config::Account;
+use crate::crypto::{self, Pbkdf2Hash};
+use crate::formats::Imported;
+use crate::otp::{Algorithm, OtpType};
+use crate::secret::SecretString;
+
+/// PBKDF2 rounds 2FAS uses for encrypted backups
+const PBKDF2_ROUNDS: u32 = 10_000;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Backup {
+    #[serde(default)]
+    services: Vec<Service>,
+    /// `ciphertext:salt:iv`, each base64, when the backup is encrypted
+    #[serde(default)]
+    services_encrypted: Option<String>,
+    #[serde(default)]
+    groups: Vec<Group>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Service {
+    name: String,
+    secret: SecretString,
+    otp: ServiceOtp,
+    #[serde(default)]
+    group_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ServiceOtp {
+    #[serde(default)]
+    label: Option<String>,
+    #[serde(default)]
+    account: Option<String>,
+    #[serde(default)]
+    issuer: Option<String>,
+    #[serde(default)]
+    digits: Option<u32>,
+    #[serde(default)]
+    period: Option<u64>,
+    #[serde(default)]
+    counter: Option<u64>,
+    #[serde(default)]
+    algorithm: Option<String>,
+    #[serde(default)]
+    token_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Group {
+    id: String,
+    name: String,
+}
+
+/// Whether the data looks like a 2FAS backup
+pub(crate) fn detect(data: &[u8]) -> bool {
+    serde_json::from_slice::<Value>(data)
+        .map(|value| value.get("services").is_some() && value.get("schemaVersion").is_some())
+        .unwrap_or(false)
+}
+
+/// Read a 2FAS backup, asking for the password only if the services are encrypted
+pub(crate) fn import(data: &str, password: impl FnOnce() -> Result<Zeroizing<String>>) -> Result<Imported> {
+    let backup: Backup = serde_json::from_str(data)?;
+
+    let services = match &backup.services_encrypted {
+        Some(encrypted) => decrypt_services(encrypted, &password()?)?,
+        None => backup.services,
+    };
+
+    let group_names: BTreeMap<&str, &str> = backup.groups.iter()
+        .map(|group| (group.id.as_str(), group.name.as_str()))
+        .collect();
+    let mut imported = Imported::default();
+
+    for service in services {
+        let otp_type = match service.otp.token_type.as_deref().unwrap_or("TOTP") {
+            "TOTP" => OtpType::Totp,
+            "HOTP" => OtpType::Hotp,
+            other => {
+                imported.skipped.push(format!("{}: unsupported type '{}'", service.name, other));
+                continue;
+            }
+        };
+        let algorithm = service.otp.algorithm.as_deref().unwrap_or("SHA1");
+        let Some(algorithm) = Algorithm::parse(algorithm) else {
+            imported.skipped.push(format!("{}: unsupported algorithm '{}'", service.name, algorithm));
+            continue;
+        };
+
+        let issuer = service.otp.issuer
+            .filter(|issuer| !issuer.trim().is_empty())
+            .unwrap_or_else(|| service.name.clone());
+        let name = service.otp.account
+            .or(service.otp.label)
+            .filter(|name| !name.trim().is_empty())
+            .unwrap_or_else(|| service.name.clone());
+
+        let mut account = Account::new(name, service.secret, issuer);
+        account.otp_type = otp_type;
+        account.algorithm = algorithm;
+        account.digits = service.otp.digits.unwrap_or(account.digits);
+        account.period = service.otp.period.unwrap_or(account.period);
+        account.counter = service.otp.counter.unwrap_or(0);
+        account.group = service.group_id
+            .and_then(|id| group_names.get(id.as_str()).map(|name| name.to_string()));
+
+        match account.validate() {
+            Ok(()) => imported.accounts.push(account),
+            Err(e) => imported.skipped.push(e.to_string()),
+        }
+    }
+    Ok(imported)
+}
+
+fn decrypt_services(encrypted: &str, password: &str) -> Result<Vec<Service>> {
+    let parts: Vec<&str> = encrypted.split(':').collect();
+    let [ciphertext, salt, iv] = parts[..] else {
+        return Err(anyhow::anyhow!("Malformed encrypted 2FAS backup"));
+    };
+
+    let key = crypto::derive_key_pbkdf2(password, &BASE64.decode(salt)?, PBKDF2_ROUNDS, Pbkdf2Hash::Sha256)?;
+    let plain = crypto::decrypt_with_nonce(&key, &BASE64.decode(iv)?, &BASE64.decode(ciphertext)?)
+        .map_err(|_| anyhow::anyhow!("Incorrect password"))?;
+    Ok(serde_json::from_slice(&plain)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAIN_BACKUP: &str = include_str!("../assets/2fas_plain.2fas");
+    const ENCRYPTED_BACKUP: &str = include_str!("../assets/2fas_encrypted.2fas");
+
+    fn assert_sample_accounts(imported: &Imported) {
+        assert_eq!(imported.accounts.len(), 2);
+        assert_eq!(imported.skipped.len(), 1);
+        assert!(imported.skipped[0].contains("STEAM"));
+
+        let github = &imported.accounts[0];
+        assert_eq!((github.name.as_str(), github.issuer.as_str()), ("octocat", "GitHub"));
+        assert_eq!(github.secret.expose(), "JBSWY3DPEHPK3PXP");
+        assert_eq!(github.group.as_deref(), Some("Work"));
+
+        let bank = &imported.accounts[1];
+        assert_eq!((bank.name.as_str(), bank.issuer.as_str()), ("customer-42", "Bank"));
+        assert_eq!(bank.otp_type, OtpType::Hotp);
+        assert_eq!(bank.algorithm, Algorithm::Sha256);
+        assert_eq!((bank.digits, bank.counter), (8, 3));
+    }
+
+    #[test]
+    fn test_import_plain_backup() {
+        assert!(detect(PLAIN_BACKUP.as_bytes()));
+        let imported = import(PLAIN_BACKUP, || panic!("a plain backup must not ask for a password")).unwrap();
+        assert_sample_accounts(&imported);
+    }
+
+    #[test]
+    fn test_import_encrypted_backup() {
+        let imported = import(ENCRYPTED_BACKUP, || Ok(Zeroizing::new("test".to_string()))).unwrap();
+        assert_sample_accounts(&imported);
+
+        assert!(import(ENCRYPTED_BACKUP, || Ok(Zeroizing::new("wrong".to_string()))).is_err());
+    }
+}
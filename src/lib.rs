@@ -0,0 +1,33 @@
+//! The `otpc` account store and OTP engine, shared by the command line tool and the PAM module
+
+mod agent;
+mod audit;
+pub mod cli;
+mod commands;
+mod completions;
+mod config;
+mod crypto;
+mod docs;
+mod doctor;
+mod edit;
+pub mod enroll;
+mod exec;
+mod formats;
+mod menu;
+mod migrate;
+pub mod otp;
+pub mod pam;
+mod profile;
+mod pty;
+mod qrcode;
+mod reconcile;
+mod recovery;
+mod secret;
+mod serve;
+mod tags;
+mod trash;
+pub mod verify;
+mod watch;
+
+pub use config::{Account, RecoveryCode};
+pub use secret::SecretString;
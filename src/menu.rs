@@ -0,0 +1,230 @@
+use std::env;
+use std::io::{self, Read as _, Write as _};
+use std::process::{Command, Stdio};
+
+use anyhow::Result;
+use clap::ValueEnum;
+
+use crate::config::Account;
+
+/// Program showing the accounts and printing the chosen line
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Launcher {
+    Dmenu,
+    Rofi,
+    Fzf,
+    /// The command given with `--command`
+    Custom,
+}
+
+/// Program typing the code into the focused window
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Typer {
+    Xdotool,
+    Wtype,
+    Ydotool,
+}
+
+impl Typer {
+    /// wtype on Wayland, xdotool on X11, ydotool anywhere else
+    fn detect() -> Self {
+        if env::var_os("WAYLAND_DISPLAY").is_some() {
+            Typer::Wtype
+        } else if env::var_os("DISPLAY").is_some() {
+            Typer::Xdotool
+        } else {
+            Typer::Ydotool
+        }
+    }
+
+    /// The command reading the text to type from standard input, so the code never shows up in `ps`
+    fn command(self) -> Command {
+        let (program, args): (&str, &[&str]) = match self {
+            Typer::Xdotool => ("xdotool", &["type", "--clearmodifiers", "--file", "-"]),
+            Typer::Wtype => ("wtype", &["-"]),
+            Typer::Ydotool => ("ydotool", &["type", "--file", "/dev/stdin"]),
+        };
+        let mut command = Command::new(program);
+        command.args(args);
+        command
+    }
+}
+
+/// One `issuer: name` line per account, in the order given
+pub(crate) fn entries(accounts: &[&Account]) -> Vec<String> {
+    accounts.iter()
+        .map(|account| match account.issuer.is_empty() {
+            true => account.name.clone(),
+            false => format!("{}: {}", account.issuer, account.name),
+        })
+        .map(|line| line.replace(['\n', '\r'], " "))
+        .collect()
+}
+
+fn launcher_command(launcher: Launcher, custom: Option<&str>) -> Result<Command> {
+    let command = match launcher {
+        Launcher::Dmenu => {
+            let mut command = Command::new("dmenu");
+            command.args(["-i", "-p", "otpc"]);
+            command
+        }
+        Launcher::Rofi => {
+            let mut command = Command::new("rofi");
+            command.args(["-dmenu", "-i", "-p", "otpc"]);
+            command
+        }
+        Launcher::Fzf => {
+            let mut command = Command::new("fzf");
+            command.args(["--prompt", "otpc> "]);
+            command
+        }
+        Launcher::Custom => {
+            let custom = custom.ok_or(anyhow::anyhow!("--launcher custom needs --command"))?;
+            shell_command(custom)
+        }
+    };
+    Ok(command)
+}
+
+#[cfg(unix)]
+pub(crate) fn shell_command(line: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(line);
+    command
+}
+
+#[cfg(windows)]
+pub(crate) fn shell_command(line: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(line);
+    command
+}
+
+/// Let the user choose one of `lines`; returns its index, or `None` when the launcher was dismissed
+pub(crate) fn pick(launcher: Launcher, custom: Option<&str>, lines: &[String]) -> Result<Option<usize>> {
+    let mut command = launcher_command(launcher, custom)?;
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Cannot start the launcher: {}", e))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        let input = lines.iter().map(|line| format!("{}\n", line)).collect::<String>();
+        // A launcher may exit before reading everything, e.g. a script picking the first line
+        match stdin.write_all(input.as_bytes()) {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e.into()),
+            _ => {}
+        }
+    }
+
+    let mut selection = String::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        stdout.read_to_string(&mut selection)?;
+    }
+    if !child.wait()?.success() {
+        return Ok(None);
+    }
+
+    let selection = selection.lines().next().unwrap_or("").trim_end();
+    if selection.is_empty() {
+        return Ok(None);
+    }
+    lines.iter()
+        .position(|line| line == selection)
+        .map(Some)
+        .ok_or(anyhow::anyhow!("The launcher returned '{}', which is not an account", selection))
+}
+
+/// Put the code on the clipboard with the first clipboard tool found
+pub(crate) fn copy(code: &str) -> Result<()> {
+    let mut tools: Vec<(&str, &[&str])> = Vec::new();
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        tools.push(("wl-copy", &[]));
+    }
+    tools.extend([
+        ("xclip", &["-selection", "clipboard"][..]),
+        ("xsel", &["--clipboard", "--input"][..]),
+        ("pbcopy", &[][..]),
+        ("clip", &[][..]),
+    ]);
+
+    for (program, args) in tools {
+        let mut command = Command::new(program);
+        command.args(args);
+        match feed(command, code) {
+            Err(e) if e.downcast_ref::<io::Error>().is_some_and(|e| e.kind() == io::ErrorKind::NotFound) => continue,
+            result => return result,
+        }
+    }
+    Err(anyhow::anyhow!("No clipboard tool found, install wl-copy, xclip or xsel"))
+}
+
+/// Type the code into the focused window
+pub(crate) fn type_code(code: &str, typer: Option<Typer>) -> Result<()> {
+    let command = typer.unwrap_or_else(Typer::detect).command();
+    let program = command.get_program().to_string_lossy().into_owned();
+    feed(command, code).map_err(|e| match e.downcast_ref::<io::Error>() {
+        Some(io) if io.kind() == io::ErrorKind::NotFound => anyhow::anyhow!("{} is not installed", program),
+        _ => e,
+    })
+}
+
+/// Run a command with `text` on its standard input
+fn feed(mut command: Command, text: &str) -> Result<()> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow::anyhow!("{} failed with {}", command.get_program().to_string_lossy(), status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accounts() -> Vec<Account> {
+        vec![
+            Account::new("alice".to_string(), "JBSWY3DPEHPK3PXP".into(), "Example".to_string()),
+            Account::new("bob".to_string(), "GEZDGNBVGY3TQOJQ".into(), String::new()),
+        ]
+    }
+
+    #[test]
+    fn test_entries() {
+        let accounts = accounts();
+        let accounts: Vec<&Account> = accounts.iter().collect();
+        assert_eq!(entries(&accounts), ["Example: alice", "bob"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pick_with_fake_launcher() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let accounts = accounts();
+        let lines = entries(&accounts.iter().collect::<Vec<_>>());
+
+        // A launcher script choosing the line that mentions bob
+        let script = env::temp_dir().join(format!("otpc-launcher-{}.sh", std::process::id()));
+        std::fs::write(&script, "#!/bin/sh\ngrep bob\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let picked = pick(Launcher::Custom, Some(script.to_str().unwrap()), &lines).unwrap();
+        assert_eq!(picked, Some(1));
+        std::fs::remove_file(&script).unwrap();
+
+        // Dismissing the launcher, or picking something unknown
+        assert_eq!(pick(Launcher::Custom, Some("exit 1"), &lines).unwrap(), None);
+        assert_eq!(pick(Launcher::Custom, Some("cat >/dev/null"), &lines).unwrap(), None);
+        assert!(pick(Launcher::Custom, Some("echo carol"), &lines).is_err());
+        assert!(pick(Launcher::Custom, None, &lines).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use toml::{Table, Value};
+
+use crate::config;
+use crate::formats::random_uuid;
+
+/// Upgrades from each store version to the next; the first step turns version 0 into 1
+const MIGRATIONS: &[fn(&mut Table) -> Result<()>] = &[
+    add_account_ids,
+    add_verification_state,
+    add_recovery_codes,
+];
+
+/// Store version written by this build
+pub(crate) const CURRENT_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Version of a parsed store; stores without a `version` key predate versioning
+pub(crate) fn version_of(table: &Table) -> Result<u32> {
+    match table.get("version") {
+        None => Ok(0),
+        Some(Value::Integer(version)) => u32::try_from(*version)
+            .map_err(|_| anyhow::anyhow!("Invalid store version {}", version)),
+        Some(_) => Err(anyhow::anyhow!("Invalid store version, expected a number")),
+    }
+}
+
+/// Bring a store up to the current version; returns the version it started at if anything changed
+pub(crate) fn upgrade(table: &mut Table) -> Result<Option<u32>> {
+    let version = version_of(table)?;
+    if version > CURRENT_VERSION {
+        return Err(anyhow::anyhow!(
+            "The store was written by a newer otpc (store version {}, this build reads up to {}), upgrade otpc to open it",
+            version, CURRENT_VERSION,
+        ));
+    }
+    if version == CURRENT_VERSION {
+        return Ok(None);
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(table)?;
+    }
+    table.insert("version".to_string(), Value::Integer(CURRENT_VERSION.into()));
+    Ok(Some(version))
+}
+
+/// Copy the original store aside before it is rewritten, e.g. `config.toml.v0.bak`
+/// A store that was brought up to the current version when it was loaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upgrade {
+    /// Version the store had on disk
+    pub from: u32,
+    /// Copy of the file as it was before the upgrade
+    pub backup: PathBuf,
+}
+
+impl fmt::Display for Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "Upgraded the store from version {} to {}, the old file is kept at {}",
+            self.from, CURRENT_VERSION, self.backup.display(),
+        )
+    }
+}
+
+pub(crate) fn write_backup(path: &Path, version: u32, original: &str) -> Result<PathBuf> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".v{}.bak", version));
+    let backup = path.with_file_name(name);
+    config::write_atomically(&backup, original.as_bytes())?;
+    Ok(backup)
+}
+
+/// Version 1: every account, including trashed ones, gets a stable ID for the audit log
+fn add_account_ids(table: &mut Table) -> Result<()> {
+    let accounts = table.entry("accounts").or_insert_with(|| Value::Array(Vec::new()));
+    for account in accounts.as_array_mut()
+        .ok_or(anyhow::anyhow!("'accounts' must be an array"))?
+        .iter_mut()
+        .filter_map(Value::as_table_mut)
+    {
+        insert_id(account)?;
+    }
+
+    if let Some(trash) = table.get_mut("trash").and_then(Value::as_array_mut) {
+        for account in trash.iter_mut()
+            .filter_map(|trashed| trashed.get_mut("account"))
+            .filter_map(Value::as_table_mut)
+        {
+            insert_id(account)?;
+        }
+    }
+    Ok(())
+}
+
+/// Version 2: accounts keep `last_step`, `failures` and `locked_until`
+///
+/// Nothing to convert, the fields default to empty; the bump only stops older builds, which would drop
+/// them on save and with them replay protection and lockouts.
+fn add_verification_state(_table: &mut Table) -> Result<()> {
+    Ok(())
+}
+
+/// Version 3: accounts keep their `recovery_codes`, which older builds would drop on save
+fn add_recovery_codes(_table: &mut Table) -> Result<()> {
+    Ok(())
+}
+
+fn insert_id(account: &mut Table) -> Result<()> {
+    if !account.contains_key("id") {
+        account.insert("id".to_string(), Value::String(random_uuid()?));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERSION_0: &str = r#"
+[[accounts]]
+name = "alice"
+secret = "JBSWY3DPEHPK3PXP"
+issuer = "Example"
+
+[[trash]]
+deleted_at = 1
+
+[trash.account]
+name = "bob"
+secret = "GEZDGNBVGY3TQOJQ"
+issuer = "host"
+"#;
+
+    #[test]
+    fn test_upgrade_from_version_0() {
+        let mut table: Table = toml::from_str(VERSION_0).unwrap();
+        assert_eq!(upgrade(&mut table).unwrap(), Some(0));
+        assert_eq!(version_of(&table).unwrap(), CURRENT_VERSION);
+        assert!(table["accounts"][0].get("id").is_some());
+        assert!(table["trash"][0]["account"].get("id").is_some());
+
+        // Already current: nothing to do
+        assert_eq!(upgrade(&mut table).unwrap(), None);
+
+        let mut empty = Table::new();
+        upgrade(&mut empty).unwrap();
+        assert!(empty["accounts"].as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_upgrade_from_version_1() {
+        let mut table: Table = toml::from_str(r#"
+version = 1
+
+[[accounts]]
+id = "1234"
+name = "alice"
+secret = "JBSWY3DPEHPK3PXP"
+issuer = "Example"
+"#).unwrap();
+        assert_eq!(upgrade(&mut table).unwrap(), Some(1));
+        assert_eq!(version_of(&table).unwrap(), CURRENT_VERSION);
+        assert_eq!(table["accounts"][0]["id"].as_str(), Some("1234"));
+    }
+
+    #[test]
+    fn test_newer_version_is_rejected() {
+        let mut table: Table = toml::from_str(&format!("version = {}\naccounts = []\n", CURRENT_VERSION + 1)).unwrap();
+        let error = upgrade(&mut table).unwrap_err().to_string();
+        assert!(error.contains("newer otpc"));
+    }
+}
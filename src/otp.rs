@@ -1,46 +1,119 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::fmt;
+use std::ops::RangeInclusive;
+use anyhow::Result;
 use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use sha2::digest::generic_array::GenericArray;
+use zeroize::Zeroize;
+
+use crate::config::Account;
+use crate::secret::{SecretBytes, SecretString};
 
 /// OTP Type (TOTP or HOTP)
-#[derive(ValueEnum, Debug, Clone, PartialEq, Eq)]
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
 pub enum OtpType {
     /// Time-based OTP (TOTP)
+    #[default]
     Totp,
     /// HMAC-based OTP (HOTP)
     Hotp,
 }
 
+/// HMAC hash algorithm (RFC 6238 allows SHA1, SHA256 and SHA512)
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Algorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    /// Name used in otpauth URIs and most export formats
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Algorithm::Sha1 => "SHA1",
+            Algorithm::Sha256 => "SHA256",
+            Algorithm::Sha512 => "SHA512",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_uppercase().replace('-', "").as_str() {
+            "SHA1" => Some(Algorithm::Sha1),
+            "SHA256" => Some(Algorithm::Sha256),
+            "SHA512" => Some(Algorithm::Sha512),
+            _ => None,
+        }
+    }
+}
+
+/// Number of digits a code may have
+pub const DIGITS: RangeInclusive<u32> = 1..=10;
+
 /// OTP Algorithm Implementation
 /// Reference RFC: https://datatracker.ietf.org/doc/html/rfc4226
 pub struct Otp {
     /// Secret Key
-    secret: Vec<u8>,
+    secret: SecretBytes,
+    /// HMAC state derived from the secret for `algorithm`
+    key: HmacKey,
     /// Digits (Default: 6)
     digits: u32,
     /// Time Interval (Seconds, Default: 30)
     period: u64,
     /// OTP Type
     otp_type: OtpType,
+    /// HMAC Algorithm (Default: SHA1)
+    algorithm: Algorithm,
 }
 
 impl Otp {
-    /// Create a new OTP instance
-    pub fn new(secret: &str, digits: u32, period: u64, otp_type: OtpType) -> Self {
+    /// Create a new OTP instance; fails unless `digits` is within `DIGITS` and `period` is positive
+    pub fn new(secret: &str, digits: u32, period: u64, otp_type: OtpType) -> Result<Self> {
+        if !DIGITS.contains(&digits) {
+            return Err(anyhow::anyhow!("Digits must be between {} and {}", DIGITS.start(), DIGITS.end()));
+        }
+        if period == 0 {
+            return Err(anyhow::anyhow!("Period must be greater than zero"));
+        }
+
         // Base32 decoding
         let secret = Self::decode_base32(secret);
+        let key = HmacKey::new(Algorithm::Sha1, secret.expose());
         
-        Self {
+        Ok(Self {
             secret,
+            key,
             digits,
             period,
             otp_type,
+            algorithm: Algorithm::Sha1,
+        })
+    }
+
+    /// Create an OTP instance with the parameters stored for an account
+    pub fn from_account(account: &Account) -> Result<Self> {
+        let otp = Self::new(account.secret.expose(), account.digits, account.period, account.otp_type)
+            .map_err(|e| anyhow::anyhow!("{}: {}", account.name, e))?;
+        Ok(otp.with_algorithm(account.algorithm))
+    }
+
+    /// Use a different HMAC algorithm
+    pub fn with_algorithm(mut self, algorithm: Algorithm) -> Self {
+        if algorithm != self.algorithm {
+            self.key = HmacKey::new(algorithm, self.secret.expose());
         }
+        self.algorithm = algorithm;
+        self
     }
 
     /// Create a new TOTP instance with default settings (6 digits, 30 second interval)
     #[allow(dead_code)]
-    pub fn new_totp(secret: &str) -> Self {
+    pub fn new_totp(secret: &str) -> Result<Self> {
         Self::new(secret, 6, 30, OtpType::Totp)
     }
 
@@ -54,204 +127,504 @@ impl Otp {
 
     /// Generate the current TOTP code
     fn generate_totp(&self) -> String {
-        // Convert the current time to a Unix timestamp
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time is before UNIX EPOCH");
-        
-        // Divide the current time by the period to calculate the counter value
-        let counter = now.as_secs() / self.period;
-        
+        self.generate_totp_at(unix_time())
+    }
+
+    /// Generate the TOTP code for the given Unix timestamp
+    pub fn generate_totp_at(&self, timestamp: u64) -> String {
+        // Divide the time by the period to calculate the counter value
+        let counter = timestamp / self.period;
+
         self.generate_hotp(counter)
     }
 
+    /// Time interval in seconds
+    pub fn period(&self) -> u64 {
+        self.period
+    }
+
+    /// Seconds left before the TOTP code for the given timestamp expires
+    pub fn remaining_seconds(&self, timestamp: u64) -> u64 {
+        self.period - timestamp % self.period
+    }
+
+    /// Verify a TOTP code, accepting `window` periods of clock drift on either side
+    #[allow(dead_code)]
+    pub fn verify_totp(&self, code: &str, timestamp: u64, window: u64) -> bool {
+        let current = timestamp / self.period;
+        let start = current.saturating_sub(window);
+
+        (start..=current.saturating_add(window))
+            .any(|counter| constant_time_eq(self.generate_hotp(counter).as_bytes(), code.as_bytes()))
+    }
+
     /// HOTP 코드 생성
     pub fn generate_hotp(&self, counter: u64) -> String {
         // 카운터를 빅 엔디안 바이트 배열로 변환
         let counter_bytes = counter.to_be_bytes();
         
-        // HMAC-SHA1 계산
-        let hmac = self.hmac_sha1(&counter_bytes);
+        // HMAC 계산 (미리 계산한 키 상태 사용, 힙 할당 없음)
+        let mut mac = [0u8; MAX_MAC_SIZE];
+        let len = self.key.mac(&counter_bytes, &mut mac);
+        let hmac = &mac[..len];
         
         // 동적 절단 (Dynamic Truncation)
-        let offset = (hmac[19] & 0xf) as usize;
-        let binary = ((hmac[offset] & 0x7f) as u32) << 24
-            | (hmac[offset + 1] as u32) << 16
-            | (hmac[offset + 2] as u32) << 8
-            | (hmac[offset + 3] as u32);
+        let offset = (hmac[len - 1] & 0xf) as usize;
+        let binary = ((hmac[offset] & 0x7f) as u64) << 24
+            | (hmac[offset + 1] as u64) << 16
+            | (hmac[offset + 2] as u64) << 8
+            | (hmac[offset + 3] as u64);
+        mac.zeroize();
         
         // 모듈로 연산으로 필요한 자릿수만큼 잘라내기
-        let modulo = 10u32.pow(self.digits);
+        let modulo = 10u64.pow(self.digits);
         let code = binary % modulo;
         
         // 앞에 0을 채워 자릿수 맞추기
         format!("{:0width$}", code, width = self.digits as usize)
     }
 
-    /// HMAC-SHA1 구현
-    fn hmac_sha1(&self, data: &[u8]) -> [u8; 20] {
-        const BLOCK_SIZE: usize = 64;
-        const SHA1_SIZE: usize = 20;
+    /// HOTP codes for `n` consecutive counters from `counter_start`, sharing the precomputed key state
+    ///
+    /// Stops early rather than wrap around past `u64::MAX`.
+    pub fn generate_range(&self, counter_start: u64, n: usize) -> Vec<String> {
+        (0..n as u64)
+            .map_while(|i| counter_start.checked_add(i))
+            .map(|counter| self.generate_hotp(counter))
+            .collect()
+    }
+    
+    /// Base32 디코딩 구현
+    fn decode_base32(input: &str) -> SecretBytes {
+        // 재할당으로 비밀 키 사본이 남지 않도록 미리 공간 확보
+        let mut result = Vec::with_capacity(input.len() * 5 / 8 + 1);
+        let mut buffer = 0u64;
+        let mut bits = 0;
         
-        // 키 준비
-        let mut key = self.secret.clone();
-        if key.len() > BLOCK_SIZE {
-            key = self.sha1(key.as_slice()).to_vec();
+        for c in input.chars().map(|c| c.to_ascii_uppercase()) {
+            // Base32 문자를 5비트 값으로 변환
+            let val = match c {
+                'A'..='Z' => (c as u8 - b'A') as u64,
+                '2'..='7' => (c as u8 - b'2' + 26) as u64,
+                _ => continue, // 공백 등 유효하지 않은 문자는 무시
+            };
+            
+            // 버퍼에 5비트 추가
+            buffer = (buffer << 5) | val;
+            bits += 5;
+            
+            // 8비트가 모이면 바이트로 추출
+            if bits >= 8 {
+                bits -= 8;
+                result.push(((buffer >> bits) & 0xFF) as u8);
+            }
+        }
+        buffer.zeroize();
+        
+        SecretBytes::new(result)
+    }
+}
+
+/// Largest HMAC output (SHA512)
+const MAX_MAC_SIZE: usize = 64;
+/// Largest hash block (SHA512)
+const MAX_BLOCK_SIZE: usize = 128;
+const SHA1_BLOCK_SIZE: usize = 64;
+const SHA1_SIZE: usize = 20;
+/// SHA1 초기 해시 값
+const SHA1_INITIAL_STATE: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+/// HMAC with the key already absorbed, so each code only hashes the message
+enum HmacKey {
+    Sha1(HmacSha1),
+    Sha256(HmacSha2<Sha256>),
+    Sha512(HmacSha2<Sha512>),
+}
+
+impl HmacKey {
+    fn new(algorithm: Algorithm, secret: &[u8]) -> Self {
+        match algorithm {
+            Algorithm::Sha1 => HmacKey::Sha1(HmacSha1::new(secret)),
+            Algorithm::Sha256 => HmacKey::Sha256(HmacSha2::new(secret)),
+            Algorithm::Sha512 => HmacKey::Sha512(HmacSha2::new(secret)),
+        }
+    }
+
+    /// Write the MAC of `data` to the start of `out`; returns its length
+    fn mac(&self, data: &[u8], out: &mut [u8; MAX_MAC_SIZE]) -> usize {
+        match self {
+            HmacKey::Sha1(key) => {
+                let mut mac = key.mac(data);
+                out[..SHA1_SIZE].copy_from_slice(&mac);
+                mac.zeroize();
+                SHA1_SIZE
+            }
+            HmacKey::Sha256(key) => key.mac(data, out),
+            HmacKey::Sha512(key) => key.mac(data, out),
         }
-        if key.len() < BLOCK_SIZE {
-            key.resize(BLOCK_SIZE, 0);
+    }
+}
+
+/// HMAC-SHA1 구현: 키 블록(ipad/opad)을 압축한 상태를 한 번만 계산해 둠
+struct HmacSha1 {
+    /// ipad 블록 이후의 SHA1 상태
+    inner: [u32; 5],
+    /// opad 블록 이후의 SHA1 상태
+    outer: [u32; 5],
+}
+
+impl HmacSha1 {
+    fn new(secret: &[u8]) -> Self {
+        // 키 준비 (비밀 키를 복제하지 않고 스택 블록에 채움)
+        let mut key = [0u8; SHA1_BLOCK_SIZE];
+        if secret.len() > SHA1_BLOCK_SIZE {
+            let mut hasher = Sha1::new();
+            hasher.update(secret);
+            let mut hashed = hasher.finalize();
+            key[..SHA1_SIZE].copy_from_slice(&hashed);
+            hashed.zeroize();
+        } else {
+            key[..secret.len()].copy_from_slice(secret);
         }
         
         // 내부 패딩 (ipad)
-        let mut ipad = [0x36u8; BLOCK_SIZE];
+        let mut ipad = [0x36u8; SHA1_BLOCK_SIZE];
         // 외부 패딩 (opad)
-        let mut opad = [0x5cu8; BLOCK_SIZE];
+        let mut opad = [0x5cu8; SHA1_BLOCK_SIZE];
         
         // XOR 연산
-        for i in 0..BLOCK_SIZE {
-            ipad[i] ^= key[i];
-            opad[i] ^= key[i];
+        for ((i, o), k) in ipad.iter_mut().zip(opad.iter_mut()).zip(key.iter()) {
+            *i ^= k;
+            *o ^= k;
         }
+        key.zeroize();
+        
+        // 패딩 블록을 미리 압축
+        let mut inner = SHA1_INITIAL_STATE;
+        sha1_compress(&mut inner, &ipad, true);
+        let mut outer = SHA1_INITIAL_STATE;
+        sha1_compress(&mut outer, &opad, true);
+        ipad.zeroize();
+        opad.zeroize();
         
+        Self { inner, outer }
+    }
+
+    fn mac(&self, data: &[u8]) -> [u8; SHA1_SIZE] {
         // 내부 해시 계산
-        let mut inner_data = Vec::with_capacity(BLOCK_SIZE + data.len());
-        inner_data.extend_from_slice(&ipad);
-        inner_data.extend_from_slice(data);
-        let inner_hash = self.sha1(&inner_data);
+        let mut inner = Sha1::resume(self.inner, SHA1_BLOCK_SIZE as u64);
+        inner.update(data);
+        let mut inner_hash = inner.finalize();
         
         // 외부 해시 계산
-        let mut outer_data = Vec::with_capacity(BLOCK_SIZE + SHA1_SIZE);
-        outer_data.extend_from_slice(&opad);
-        outer_data.extend_from_slice(&inner_hash);
-        
-        self.sha1(&outer_data)
+        let mut outer = Sha1::resume(self.outer, SHA1_BLOCK_SIZE as u64);
+        outer.update(&inner_hash);
+        inner_hash.zeroize();
+        outer.finalize()
     }
+}
 
-    /// SHA1 해시 함수 구현
-    fn sha1(&self, data: &[u8]) -> [u8; 20] {
-        // SHA1 초기 해시 값
-        let mut h0: u32 = 0x67452301;
-        let mut h1: u32 = 0xEFCDAB89;
-        let mut h2: u32 = 0x98BADCFE;
-        let mut h3: u32 = 0x10325476;
-        let mut h4: u32 = 0xC3D2E1F0;
-        
-        // 메시지 패딩
-        let mut padded_data = data.to_vec();
-        let original_len_bits = (data.len() as u64) * 8;
-        
-        // 1 비트 추가
-        padded_data.push(0x80);
-        
-        // 0으로 패딩 (길이가 56 모듈로 64가 될 때까지)
-        while padded_data.len() % 64 != 56 {
-            padded_data.push(0);
+impl Drop for HmacSha1 {
+    fn drop(&mut self) {
+        self.inner.zeroize();
+        self.outer.zeroize();
+    }
+}
+
+/// SHA-2 압축 함수와 상수 (`HmacSha2`에서 사용)
+trait Sha2 {
+    type Word: Copy + Zeroize;
+    const BLOCK_SIZE: usize;
+    const OUTPUT_SIZE: usize;
+    const INITIAL_STATE: [Self::Word; 8];
+
+    fn compress(state: &mut [Self::Word; 8], block: &[u8]);
+    /// 상태를 빅 엔디안 바이트로 기록
+    fn write_state(state: &[Self::Word; 8], out: &mut [u8]);
+}
+
+struct Sha256;
+
+impl Sha2 for Sha256 {
+    type Word = u32;
+    const BLOCK_SIZE: usize = 64;
+    const OUTPUT_SIZE: usize = 32;
+    const INITIAL_STATE: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+    ];
+
+    fn compress(state: &mut [u32; 8], block: &[u8]) {
+        sha2::compress256(state, std::slice::from_ref(GenericArray::from_slice(block)));
+    }
+
+    fn write_state(state: &[u32; 8], out: &mut [u8]) {
+        for (bytes, word) in out.chunks_exact_mut(4).zip(state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
         }
-        
-        // 원본 메시지 길이 추가 (비트 단위, 빅 엔디안 8바이트)
-        padded_data.extend_from_slice(&original_len_bits.to_be_bytes());
-        
-        // 512비트(64바이트) 블록 단위로 처리
-        for chunk_start in (0..padded_data.len()).step_by(64) {
-            let chunk = &padded_data[chunk_start..chunk_start + 64];
-            
-            // 메시지 스케줄 준비 (16개의 32비트 워드에서 80개로 확장)
-            let mut w = [0u32; 80];
-            
-            // 처음 16개 워드는 청크에서 직접 가져옴
-            for i in 0..16 {
-                let start = i * 4;
-                w[i] = ((chunk[start] as u32) << 24)
-                    | ((chunk[start + 1] as u32) << 16)
-                    | ((chunk[start + 2] as u32) << 8)
-                    | (chunk[start + 3] as u32);
-            }
-            
-            // 나머지 64개 워드 계산
-            for i in 16..80 {
-                w[i] = Self::left_rotate(w[i-3] ^ w[i-8] ^ w[i-14] ^ w[i-16], 1);
+    }
+}
+
+struct Sha512;
+
+impl Sha2 for Sha512 {
+    type Word = u64;
+    const BLOCK_SIZE: usize = 128;
+    const OUTPUT_SIZE: usize = 64;
+    const INITIAL_STATE: [u64; 8] = [
+        0x6a09e667f3bcc908, 0xbb67ae8584caa73b, 0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+        0x510e527fade682d1, 0x9b05688c2b3e6c1f, 0x1f83d9abfb41bd6b, 0x5be0cd19137e2179,
+    ];
+
+    fn compress(state: &mut [u64; 8], block: &[u8]) {
+        sha2::compress512(state, std::slice::from_ref(GenericArray::from_slice(block)));
+    }
+
+    fn write_state(state: &[u64; 8], out: &mut [u8]) {
+        for (bytes, word) in out.chunks_exact_mut(8).zip(state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+    }
+}
+
+/// HMAC-SHA256/SHA512 구현: `HmacSha1`처럼 키 블록을 압축한 상태를 한 번만 계산하고, 해제할 때 지움
+struct HmacSha2<H: Sha2> {
+    /// ipad 블록 이후의 상태
+    inner: [H::Word; 8],
+    /// opad 블록 이후의 상태
+    outer: [H::Word; 8],
+}
+
+impl<H: Sha2> HmacSha2<H> {
+    fn new(secret: &[u8]) -> Self {
+        // 블록보다 긴 키는 먼저 해시
+        let mut key = [0u8; MAX_BLOCK_SIZE];
+        if secret.len() > H::BLOCK_SIZE {
+            sha2_finish::<H>(H::INITIAL_STATE, 0, secret, &mut key[..H::OUTPUT_SIZE]);
+        } else {
+            key[..secret.len()].copy_from_slice(secret);
+        }
+
+        let mut pad = [0u8; MAX_BLOCK_SIZE];
+        let mut absorb = |byte: u8| {
+            let mut state = H::INITIAL_STATE;
+            for (p, k) in pad.iter_mut().zip(&key).take(H::BLOCK_SIZE) {
+                *p = k ^ byte;
             }
+            H::compress(&mut state, &pad[..H::BLOCK_SIZE]);
+            state
+        };
+        let (inner, outer) = (absorb(0x36), absorb(0x5c));
+        key.zeroize();
+        pad.zeroize();
+
+        Self { inner, outer }
+    }
+
+    /// `out` 앞부분에 MAC을 기록하고 길이를 반환
+    fn mac(&self, data: &[u8], out: &mut [u8; MAX_MAC_SIZE]) -> usize {
+        let mut inner_hash = [0u8; MAX_MAC_SIZE];
+        sha2_finish::<H>(self.inner, H::BLOCK_SIZE as u64, data, &mut inner_hash[..H::OUTPUT_SIZE]);
+        sha2_finish::<H>(self.outer, H::BLOCK_SIZE as u64, &inner_hash[..H::OUTPUT_SIZE], &mut out[..H::OUTPUT_SIZE]);
+        inner_hash.zeroize();
+        H::OUTPUT_SIZE
+    }
+}
+
+impl<H: Sha2> Drop for HmacSha2<H> {
+    fn drop(&mut self) {
+        self.inner.zeroize();
+        self.outer.zeroize();
+    }
+}
+
+/// `processed` 바이트를 압축한 `state`에서 `data`를 이어 해시해 `out`에 기록 (작업 버퍼는 지움)
+fn sha2_finish<H: Sha2>(mut state: [H::Word; 8], processed: u64, data: &[u8], out: &mut [u8]) {
+    let mut block = [0u8; MAX_BLOCK_SIZE];
+    let mut chunks = data.chunks_exact(H::BLOCK_SIZE);
+    for chunk in &mut chunks {
+        H::compress(&mut state, chunk);
+    }
+    let rest = chunks.remainder();
+    let length_bits = (processed + data.len() as u64).wrapping_mul(8);
+
+    // 1 비트 추가, 길이 필드(SHA256은 8바이트, SHA512는 16바이트)가 들어갈 자리가 없으면 블록을 하나 더 사용
+    let length_size = H::BLOCK_SIZE / 8;
+    block[..rest.len()].copy_from_slice(rest);
+    block[rest.len()] = 0x80;
+    if rest.len() + 1 > H::BLOCK_SIZE - length_size {
+        H::compress(&mut state, &block[..H::BLOCK_SIZE]);
+        block.fill(0);
+    }
+    block[H::BLOCK_SIZE - 8..H::BLOCK_SIZE].copy_from_slice(&length_bits.to_be_bytes());
+    H::compress(&mut state, &block[..H::BLOCK_SIZE]);
+
+    H::write_state(&state, out);
+    state.zeroize();
+    block.zeroize();
+}
+
+/// 스트리밍 SHA1 해시 (블록 하나만 버퍼링하며 힙 할당 없음)
+struct Sha1 {
+    state: [u32; 5],
+    /// 아직 압축하지 않은 입력
+    block: [u8; SHA1_BLOCK_SIZE],
+    /// `block`에 채워진 바이트 수
+    filled: usize,
+    /// 지금까지 입력된 전체 바이트 수
+    length: u64,
+    /// 블록마다 메시지 스케줄을 지울지 (키를 해시할 때만)
+    wipe: bool,
+}
+
+impl Sha1 {
+    /// 비밀 키를 해시하는 인스턴스
+    fn new() -> Self {
+        Self { wipe: true, ..Self::resume(SHA1_INITIAL_STATE, 0) }
+    }
+
+    /// 블록 경계에서 저장해 둔 상태부터 이어서 계산
+    ///
+    /// 코드마다 압축하는 블록에는 카운터와 내부 해시만 들어가므로 스케줄을 지우지 않음
+    fn resume(state: [u32; 5], length: u64) -> Self {
+        Self { state, block: [0; SHA1_BLOCK_SIZE], filled: 0, length, wipe: false }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let take = (SHA1_BLOCK_SIZE - self.filled).min(data.len());
+            self.block[self.filled..self.filled + take].copy_from_slice(&data[..take]);
+            self.filled += take;
+            self.length += take as u64;
+            data = &data[take..];
             
-            // 초기화
-            let mut a = h0;
-            let mut b = h1;
-            let mut c = h2;
-            let mut d = h3;
-            let mut e = h4;
-            
-            // 메인 루프
-            for i in 0..80 {
-                let (f, k) = match i {
-                    0..=19 => ((b & c) | ((!b) & d), 0x5A827999),
-                    20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
-                    40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
-                    _ => (b ^ c ^ d, 0xCA62C1D6),
-                };
-                
-                let temp = Self::left_rotate(a, 5).wrapping_add(f)
-                    .wrapping_add(e)
-                    .wrapping_add(k)
-                    .wrapping_add(w[i]);
-                
-                e = d;
-                d = c;
-                c = Self::left_rotate(b, 30);
-                b = a;
-                a = temp;
+            if self.filled == SHA1_BLOCK_SIZE {
+                sha1_compress(&mut self.state, &self.block, self.wipe);
+                self.filled = 0;
             }
-            
-            // 해시 값 업데이트
-            h0 = h0.wrapping_add(a);
-            h1 = h1.wrapping_add(b);
-            h2 = h2.wrapping_add(c);
-            h3 = h3.wrapping_add(d);
-            h4 = h4.wrapping_add(e);
         }
+    }
+
+    fn finalize(mut self) -> [u8; SHA1_SIZE] {
+        let length_bits = self.length.wrapping_mul(8);
         
-        // 최종 해시 값 반환
-        let mut result = [0u8; 20];
-        result[0..4].copy_from_slice(&h0.to_be_bytes());
-        result[4..8].copy_from_slice(&h1.to_be_bytes());
-        result[8..12].copy_from_slice(&h2.to_be_bytes());
-        result[12..16].copy_from_slice(&h3.to_be_bytes());
-        result[16..20].copy_from_slice(&h4.to_be_bytes());
+        // 1 비트 추가
+        self.block[self.filled] = 0x80;
+        self.filled += 1;
+        
+        // 길이를 넣을 자리가 없으면 블록을 하나 더 사용
+        if self.filled > SHA1_BLOCK_SIZE - 8 {
+            self.block[self.filled..].fill(0);
+            sha1_compress(&mut self.state, &self.block, self.wipe);
+            self.filled = 0;
+        }
+        
+        // 0으로 패딩한 뒤 원본 메시지 길이 추가 (비트 단위, 빅 엔디안 8바이트)
+        self.block[self.filled..SHA1_BLOCK_SIZE - 8].fill(0);
+        self.block[SHA1_BLOCK_SIZE - 8..].copy_from_slice(&length_bits.to_be_bytes());
+        sha1_compress(&mut self.state, &self.block, self.wipe);
         
+        // 최종 해시 값 반환
+        let mut result = [0u8; SHA1_SIZE];
+        for (bytes, word) in result.chunks_exact_mut(4).zip(self.state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
         result
     }
+}
+
+impl Drop for Sha1 {
+    fn drop(&mut self) {
+        // 키에서 파생된 데이터 삭제
+        self.state.zeroize();
+        self.block.zeroize();
+    }
+}
+
+/// SHA1 압축 함수: 512비트(64바이트) 블록 하나를 상태에 반영, `wipe`이면 메시지 스케줄을 지움
+fn sha1_compress(state: &mut [u32; 5], block: &[u8; SHA1_BLOCK_SIZE], wipe: bool) {
+    // 메시지 스케줄 준비 (16개의 32비트 워드에서 80개로 확장)
+    let mut w = [0u32; 80];
     
-    /// 비트 왼쪽 회전 (left rotate) 연산
-    fn left_rotate(value: u32, shift: u32) -> u32 {
-        (value << shift) | (value >> (32 - shift))
+    // 처음 16개 워드는 블록에서 직접 가져옴
+    for (word, bytes) in w.iter_mut().zip(block.chunks_exact(4)) {
+        *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
     }
     
-    /// Base32 디코딩 구현
-    fn decode_base32(input: &str) -> Vec<u8> {
-        let input = input.to_uppercase().replace(" ", "");
-        let mut result = Vec::new();
-        let mut buffer = 0u64;
-        let mut bits = 0;
-        
-        for c in input.chars() {
-            // Base32 문자를 5비트 값으로 변환
-            let val = match c {
-                'A'..='Z' => (c as u8 - b'A') as u64,
-                '2'..='7' => (c as u8 - b'2' + 26) as u64,
-                _ => continue, // 유효하지 않은 문자는 무시
-            };
-            
-            // 버퍼에 5비트 추가
-            buffer = (buffer << 5) | val;
-            bits += 5;
+    // 나머지 64개 워드 계산
+    for i in 16..80 {
+        w[i] = (w[i-3] ^ w[i-8] ^ w[i-14] ^ w[i-16]).rotate_left(1);
+    }
+    
+    // 초기화
+    let [mut a, mut b, mut c, mut d, mut e] = *state;
+    
+    // 메인 루프 (20 라운드마다 함수와 상수가 바뀜)
+    let mut rounds = |words: &[u32], f: fn(u32, u32, u32) -> u32, k: u32| {
+        for &word in words {
+            let temp = a.rotate_left(5).wrapping_add(f(b, c, d))
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
             
-            // 8비트가 모이면 바이트로 추출
-            if bits >= 8 {
-                bits -= 8;
-                result.push(((buffer >> bits) & 0xFF) as u8);
-            }
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
         }
-        
-        result
+    };
+    rounds(&w[0..20], |b, c, d| (b & c) | ((!b) & d), 0x5A827999);
+    rounds(&w[20..40], |b, c, d| b ^ c ^ d, 0x6ED9EBA1);
+    rounds(&w[40..60], |b, c, d| (b & c) | (b & d) | (c & d), 0x8F1BBCDC);
+    rounds(&w[60..80], |b, c, d| b ^ c ^ d, 0xCA62C1D6);
+    if wipe {
+        w.zeroize();
+    }
+    
+    // 해시 값 업데이트
+    for (h, value) in state.iter_mut().zip([a, b, c, d, e]) {
+        *h = h.wrapping_add(value);
+    }
+}
+
+/// Base32 encoding (RFC 4648 alphabet, without padding)
+pub fn encode_base32(data: &[u8]) -> SecretString {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+    let mut result = String::with_capacity(data.len().div_ceil(5) * 8);
+    let mut buffer = 0u64;
+    let mut bits = 0;
+
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u64;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            result.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        result.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    buffer.zeroize();
+
+    SecretString::new(result)
+}
+
+/// Current Unix timestamp in seconds
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time is before UNIX EPOCH")
+        .as_secs()
+}
+
+/// Compare two byte strings without short-circuiting on the first mismatch
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
     }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
 }
 
 impl fmt::Display for Otp {
@@ -267,21 +640,134 @@ mod tests {
     #[test]
     fn test_base32_decode() {
         let decoded = Otp::decode_base32("JBSWY3DPEHPK3PXP");
-        assert_eq!(decoded, vec![72, 101, 108, 108, 111, 33, 222, 173, 190, 239]);
+        assert_eq!(decoded.expose(), [72, 101, 108, 108, 111, 33, 222, 173, 190, 239]);
     }
     
+    #[test]
+    fn test_base32_encode() {
+        let decoded = Otp::decode_base32("JBSWY3DPEHPK3PXP");
+        assert_eq!(encode_base32(decoded.expose()).expose(), "JBSWY3DPEHPK3PXP");
+        assert_eq!(encode_base32(b"12345").expose(), "GEZDGNBV");
+        assert_eq!(encode_base32(b"1").expose(), "GE");
+    }
+
+    #[test]
+    fn test_sha1_streaming() {
+        use sha1::Digest as _;
+
+        // Lengths around the padding and block boundaries, fed in uneven pieces
+        let data: Vec<u8> = (0..300u32).map(|i| (i * 7 + 3) as u8).collect();
+        for len in [0, 1, 55, 56, 63, 64, 65, 119, 120, 128, 300] {
+            let mut hasher = Sha1::new();
+            for piece in data[..len].chunks(13) {
+                hasher.update(piece);
+            }
+            assert_eq!(hasher.finalize(), <[u8; 20]>::from(sha1::Sha1::digest(&data[..len])), "length {}", len);
+        }
+    }
+
     #[test]
     fn test_hmac_sha1() {
-        let otp = Otp::new("JBSWY3DPEHPK3PXP", 6, 30, OtpType::Totp);
-        let result = otp.hmac_sha1(&[0, 0, 0, 0, 0, 0, 0, 1]);
-        assert_eq!(result.len(), 20);
+        // RFC 2202 test cases 2 and 6 (a key longer than the block)
+        let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        let mac = HmacSha1::new(b"Jefe").mac(b"what do ya want for nothing?");
+        assert_eq!(hex(&mac), "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79");
+        let mac = HmacSha1::new(&[0xaa; 80]).mac(b"Test Using Larger Than Block-Size Key - Hash Key First");
+        assert_eq!(hex(&mac), "aa4ae5e15272d00e95705637ce8a3b55ed402112");
+    }
+
+    #[test]
+    fn test_hmac_sha2() {
+        use hmac::{Hmac, Mac};
+
+        // Against the hmac crate, around the block and length field boundaries of both hashes
+        for key_len in [0, 20, 63, 64, 65, 127, 128, 129, 200] {
+            let key: Vec<u8> = (0..key_len).map(|i| i as u8).collect();
+            let (sha256, sha512) = (HmacSha2::<Sha256>::new(&key), HmacSha2::<Sha512>::new(&key));
+            for data_len in [0, 8, 55, 56, 64, 111, 112, 128, 300] {
+                let data = vec![0x5a; data_len];
+                let mut out = [0u8; MAX_MAC_SIZE];
+
+                let len = sha256.mac(&data, &mut out);
+                let expected = Hmac::<sha2::Sha256>::new_from_slice(&key).unwrap().chain_update(&data).finalize();
+                assert_eq!(out[..len], expected.into_bytes()[..], "SHA256 key {} data {}", key_len, data_len);
+
+                let len = sha512.mac(&data, &mut out);
+                let expected = Hmac::<sha2::Sha512>::new_from_slice(&key).unwrap().chain_update(&data).finalize();
+                assert_eq!(out[..len], expected.into_bytes()[..], "SHA512 key {} data {}", key_len, data_len);
+            }
+        }
     }
     
+    #[test]
+    fn test_generate_totp_rfc6238() {
+        // RFC 6238 Appendix B, SHA1 seed "12345678901234567890"
+        let otp = Otp::new("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", 8, 30, OtpType::Totp).unwrap();
+        assert_eq!(otp.generate_totp_at(59), "94287082");
+        assert_eq!(otp.generate_totp_at(1111111109), "07081804");
+        assert_eq!(otp.generate_totp_at(2000000000), "69279037");
+    }
+
+    #[test]
+    fn test_generate_totp_rfc6238_sha2() {
+        // RFC 6238 Appendix B, SHA256 and SHA512 seeds
+        let sha256 = Otp::new("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA", 8, 30, OtpType::Totp).unwrap()
+            .with_algorithm(Algorithm::Sha256);
+        assert_eq!(sha256.generate_totp_at(59), "46119246");
+        assert_eq!(sha256.generate_totp_at(1111111109), "68084774");
+
+        let sha512 = Otp::new(
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNA",
+            8,
+            30,
+            OtpType::Totp,
+        ).unwrap().with_algorithm(Algorithm::Sha512);
+        assert_eq!(sha512.generate_totp_at(59), "90693936");
+        assert_eq!(sha512.generate_totp_at(1111111109), "25091201");
+    }
+
+    #[test]
+    fn test_verify_totp_window() {
+        let otp = Otp::new("JBSWY3DPEHPK3PXP", 6, 30, OtpType::Totp).unwrap();
+        let previous = otp.generate_totp_at(1_000_000_000 - 30);
+
+        assert!(otp.verify_totp(&previous, 1_000_000_000, 1));
+        assert!(!otp.verify_totp(&previous, 1_000_000_000, 0));
+        assert!(!otp.verify_totp("12345", 1_000_000_000, 1));
+        assert_eq!(otp.remaining_seconds(1_000_000_000), 20);
+    }
+
     #[test]
     fn test_generate_hotp() {
-        let otp = Otp::new("JBSWY3DPEHPK3PXP", 6, 30, OtpType::Totp);
+        let otp = Otp::new("JBSWY3DPEHPK3PXP", 6, 30, OtpType::Totp).unwrap();
         let code = otp.generate_hotp(2);
         println!("code: {}", code);
         assert_eq!(code.len(), 6);
     }
+
+    #[test]
+    fn test_generate_range() {
+        // RFC 4226 Appendix D
+        let otp = Otp::new("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", 6, 30, OtpType::Hotp).unwrap();
+        assert_eq!(otp.generate_range(0, 4), ["755224", "287082", "359152", "969429"]);
+        assert_eq!(otp.generate_range(8, 2), [otp.generate_hotp(8), otp.generate_hotp(9)]);
+        assert_eq!(otp.generate_range(u64::MAX, 3).len(), 1);
+        assert!(otp.generate_range(5, 0).is_empty());
+
+        let sha512 = Otp::new("GEZDGNBVGY3TQOJQ", 8, 30, OtpType::Hotp).unwrap().with_algorithm(Algorithm::Sha512);
+        assert_eq!(sha512.generate_range(3, 2), [sha512.generate_hotp(3), sha512.generate_hotp(4)]);
+    }
+
+    #[test]
+    fn test_new_rejects_invalid_parameters() {
+        assert!(Otp::new("JBSWY3DPEHPK3PXP", 10, 30, OtpType::Totp).is_ok());
+        assert!(Otp::new("JBSWY3DPEHPK3PXP", 20, 30, OtpType::Totp).is_err());
+        assert!(Otp::new("JBSWY3DPEHPK3PXP", 0, 30, OtpType::Totp).is_err());
+        assert!(Otp::new("JBSWY3DPEHPK3PXP", 6, 0, OtpType::Totp).is_err());
+
+        // A hand-edited store is not validated on load
+        let mut account = Account::new("alice".to_string(), "JBSWY3DPEHPK3PXP".into(), String::new());
+        account.period = 0;
+        assert!(Otp::from_account(&account).err().unwrap().to_string().contains("alice"));
+    }
 }
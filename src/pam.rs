@@ -0,0 +1,271 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::config::{self, Config};
+use crate::verify::{self, Policy};
+
+pub use crate::migrate::Upgrade;
+pub use crate::otp::unix_time;
+pub use crate::verify::Outcome;
+
+/// Store read when the service file gives no `store=` argument, a profile the user manages with `otpc --profile pam`
+pub const DEFAULT_STORE: &str = "~/.config/otpc/profiles/pam/config.toml";
+/// TOTP periods of clock drift accepted on either side when no `window=` argument is given
+pub const DEFAULT_WINDOW: u64 = 1;
+
+/// Arguments given after the module name in a PAM service file; the `pam_otpc` module only talks to PAM
+/// and leaves every decision to this module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Store path, where `~` is the user's home directory and `%u` their user name
+    pub store: String,
+    /// Account to verify against, required when the store holds more than one
+    pub account: Option<String>,
+    pub window: u64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self { store: DEFAULT_STORE.to_string(), account: None, window: DEFAULT_WINDOW }
+    }
+}
+
+impl Options {
+    /// Parse `store=<path>`, `account=<name>` and `window=<periods>`
+    pub fn parse<'a>(args: impl IntoIterator<Item = &'a str>) -> Result<Options> {
+        let mut options = Options::default();
+        for arg in args {
+            match arg.split_once('=') {
+                Some(("store", path)) if !path.is_empty() => options.store = path.to_string(),
+                Some(("account", name)) if !name.is_empty() => options.account = Some(name.to_string()),
+                Some(("window", window)) => {
+                    options.window = window.parse().ok().filter(|&window| window <= verify::MAX_WINDOW)
+                        .ok_or_else(|| anyhow::anyhow!(
+                            "Invalid window '{}', expected at most {} periods", window, verify::MAX_WINDOW
+                        ))?;
+                }
+                _ => return Err(anyhow::anyhow!("Unknown argument '{}'", arg)),
+            }
+        }
+        Ok(options)
+    }
+
+    /// The store of `user`, whose home directory is `home`
+    pub fn store_path(&self, user: &str, home: &Path) -> Result<PathBuf> {
+        if self.store.contains("%u") && (user.is_empty() || user.contains(['/', '\0']) || user == "." || user == "..") {
+            return Err(anyhow::anyhow!("User name '{}' cannot be used in a path", user));
+        }
+        let store = self.store.replace("%u", user);
+        let path = match store.strip_prefix("~/") {
+            Some(rest) => home.join(rest),
+            None => PathBuf::from(store),
+        };
+        if !path.is_absolute() {
+            return Err(anyhow::anyhow!("Store path {} is not absolute", path.display()));
+        }
+        Ok(path)
+    }
+}
+
+/// Refuse a store someone other than its user (or root) could have written or swapped out
+///
+/// The store must be a regular file, and it and every directory above it must be owned by the user or
+/// root and writable by no one else.
+#[cfg(unix)]
+pub fn check_permissions(path: &Path, uid: u32) -> Result<()> {
+    use std::os::unix::fs::MetadataExt as _;
+
+    let path = std::fs::canonicalize(path)?;
+    let metadata = std::fs::metadata(&path)?;
+    if !metadata.is_file() {
+        return Err(anyhow::anyhow!("{} is not a regular file", path.display()));
+    }
+    check_owner(&path, metadata.uid(), metadata.mode(), uid)?;
+    for dir in path.ancestors().skip(1) {
+        let metadata = std::fs::metadata(dir)?;
+        check_owner(dir, metadata.uid(), metadata.mode(), uid)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn check_owner(path: &Path, owner: u32, mode: u32, uid: u32) -> Result<()> {
+    if owner != uid && owner != 0 {
+        return Err(anyhow::anyhow!("{} is owned by uid {}, not by its user", path.display(), owner));
+    }
+    if mode & 0o022 != 0 {
+        return Err(anyhow::anyhow!("{} is writable by other users (mode {:o})", path.display(), mode & 0o777));
+    }
+    Ok(())
+}
+
+/// Act as the user until the returned guard is dropped, so nothing under their home is touched as root
+///
+/// Only the effective IDs change, and only when running as root; the supplementary groups are cleared
+/// meanwhile. Call it before `check_permissions` and `authenticate`.
+#[cfg(unix)]
+pub fn drop_privileges(uid: u32, gid: u32) -> Result<Privileges> {
+    unix::drop_privileges(uid, gid)
+}
+
+#[cfg(unix)]
+pub use unix::Privileges;
+
+/// Verify `code` against the store and save the account's new state before answering
+///
+/// A lock file next to the store serializes concurrent logins, so a code is never accepted twice and
+/// every failure counts towards the lockout configured in the store's settings. A store written by an
+/// older `otpc` is upgraded on the way, and the upgrade is handed back for the caller to log.
+#[cfg(unix)]
+pub fn authenticate(options: &Options, path: &Path, code: &str, timestamp: u64) -> Result<(Outcome, Option<Upgrade>)> {
+    let _lock = config::lock_store(path)?;
+    let (mut config, upgrade) = Config::load_unencrypted(path)?;
+
+    let index = match &options.account {
+        Some(name) => config.accounts.iter().position(|account| &account.name == name)
+            .ok_or(anyhow::anyhow!("No account named '{}' in {}", name, path.display()))?,
+        None if config.accounts.len() == 1 => 0,
+        None => return Err(anyhow::anyhow!(
+            "{} holds {} accounts, choose one with account=<name>", path.display(), config.accounts.len(),
+        )),
+    };
+
+    let policy = Policy::from_settings(&config.settings);
+    let outcome = verify::verify(&mut config.accounts[index], code, timestamp, options.window, &policy);
+    if !matches!(outcome, Outcome::Locked { .. }) {
+        config.save_to_file(path)?;
+    }
+    Ok((outcome, upgrade))
+}
+
+#[cfg(not(unix))]
+pub fn authenticate(_options: &Options, _path: &Path, _code: &str, _timestamp: u64) -> Result<(Outcome, Option<Upgrade>)> {
+    Err(anyhow::anyhow!("The PAM module is only supported on Unix"))
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::io;
+
+    use anyhow::Result;
+
+    /// Root's effective IDs and groups, restored when dropped
+    pub struct Privileges {
+        saved: Option<(libc::uid_t, libc::gid_t, Vec<libc::gid_t>)>,
+    }
+
+    pub(super) fn drop_privileges(uid: u32, gid: u32) -> Result<Privileges> {
+        // SAFETY: plain getters without arguments
+        let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        if euid != 0 || uid == 0 {
+            return Ok(Privileges { saved: None });
+        }
+
+        // SAFETY: a zero-sized query returns the number of groups
+        let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+        let mut groups = vec![0; count.max(0) as usize];
+        // SAFETY: `groups` has room for `count` entries
+        let count = unsafe { libc::getgroups(count, groups.as_mut_ptr()) };
+        if count < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        groups.truncate(count as usize);
+
+        // From here on, dropping the guard puts back whatever was changed
+        let privileges = Privileges { saved: Some((euid, egid, groups)) };
+        // SAFETY: one valid group ID in, then plain ID changes; the group must change while still root
+        let failed = unsafe {
+            libc::setgroups(1, &gid) != 0 || libc::setegid(gid) != 0 || libc::seteuid(uid) != 0
+        };
+        if failed {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(privileges)
+    }
+
+    impl Drop for Privileges {
+        fn drop(&mut self) {
+            if let Some((euid, egid, groups)) = &self.saved {
+                // SAFETY: regaining root first, since only root may set the groups back
+                unsafe {
+                    libc::seteuid(*euid);
+                    libc::setegid(*egid);
+                    libc::setgroups(groups.len() as _, groups.as_ptr());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Account;
+    use crate::otp::Otp;
+
+    #[test]
+    fn test_options() {
+        assert_eq!(Options::parse([]).unwrap(), Options::default());
+        let options = Options::parse(["store=/var/lib/otpc/%u.toml", "account=sudo", "window=2"]).unwrap();
+        assert_eq!(options.account.as_deref(), Some("sudo"));
+        assert_eq!(options.window, 2);
+        assert_eq!(options.store_path("alice", Path::new("/home/alice")).unwrap(), Path::new("/var/lib/otpc/alice.toml"));
+        assert!(options.store_path("../root", Path::new("/root")).is_err());
+
+        let home = Options::default().store_path("alice", Path::new("/home/alice")).unwrap();
+        assert_eq!(home, Path::new("/home/alice/.config/otpc/profiles/pam/config.toml"));
+        assert!(Options::parse(["window=soon"]).is_err());
+        assert!(Options::parse(["window=1000000"]).is_err());
+        assert!(Options::parse(["nullok"]).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_authenticate() {
+        let dir = std::env::temp_dir().join(format!("otpc-pam-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        let account = Account::new("sudo".to_string(), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ".into(), String::new());
+        let code = Otp::from_account(&account).unwrap().generate_totp_at(1_000_000);
+        Config::new(vec![account]).save_to_file(&path).unwrap();
+
+        let options = Options::default();
+        assert_eq!(authenticate(&options, &path, "000000", 1_000_000).unwrap(), (Outcome::Invalid, None));
+        assert_eq!(Config::load_from_file(&path).unwrap().accounts[0].failures, 1);
+        assert_eq!(authenticate(&options, &path, &code, 1_000_000).unwrap(), (Outcome::Accepted, None));
+        // The accepted step was saved, so the same code cannot be used again
+        assert_eq!(authenticate(&options, &path, &code, 1_000_010).unwrap(), (Outcome::Replayed, None));
+        let saved = Config::load_from_file(&path).unwrap();
+        assert!(saved.accounts[0].last_step.is_some());
+        assert_eq!(saved.accounts[0].failures, 1);
+
+        let other = Options { account: Some("root".to_string()), ..Options::default() };
+        assert!(authenticate(&other, &path, &code, 1_000_000).is_err());
+
+        // A store from an older otpc is upgraded and the upgrade reported, not printed
+        let current = format!("version = {}", crate::migrate::CURRENT_VERSION);
+        let old = std::fs::read_to_string(&path).unwrap().replace(&current, "version = 1");
+        std::fs::write(&path, &old).unwrap();
+        let (outcome, upgrade) = authenticate(&options, &path, "000000", 1_000_000).unwrap();
+        assert_eq!(outcome, Outcome::Invalid);
+        let upgrade = upgrade.unwrap();
+        assert_eq!(upgrade.from, 1);
+        assert_eq!(std::fs::read_to_string(&upgrade.backup).unwrap(), old);
+        let uid = std::os::unix::fs::MetadataExt::uid(&std::fs::metadata(&dir).unwrap());
+        assert!(check_permissions(&dir, uid).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_owner() {
+        let path = Path::new("/home/alice/.config");
+        assert!(check_owner(path, 1000, 0o40700, 1000).is_ok());
+        assert!(check_owner(path, 0, 0o40755, 1000).is_ok());
+        assert!(check_owner(path, 1001, 0o40700, 1000).is_err());
+        assert!(check_owner(path, 1000, 0o40770, 1000).is_err());
+        // A sticky /tmp is still writable by everyone
+        assert!(check_owner(path, 0, 0o41777, 1000).is_err());
+    }
+}
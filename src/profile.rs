@@ -0,0 +1,195 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, Account, Config};
+
+/// Profile backed by the base store file itself
+pub(crate) const DEFAULT_PROFILE: &str = "default";
+/// Directory next to the base store holding one directory per named profile
+const PROFILES_DIR: &str = "profiles";
+/// File next to the base store naming the profile used without `--profile`
+const PROFILES_FILE: &str = "profiles.toml";
+
+/// Profile given with `--profile` or `OTPC_PROFILE`
+static SELECTED: OnceLock<String> = OnceLock::new();
+
+#[derive(Serialize, Deserialize, Default)]
+struct Profiles {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default: Option<String>,
+}
+
+/// Use the named profile for this run
+pub(crate) fn select(name: String) {
+    let _ = SELECTED.set(name);
+}
+
+/// The profile in use: the selected one, or the configured default
+pub(crate) fn active() -> Result<String> {
+    match SELECTED.get() {
+        Some(name) => Ok(name.clone()),
+        None => default_profile(),
+    }
+}
+
+/// Path of a profile's store; named profiles live in `profiles/<name>/config.toml`
+pub(crate) fn store_path(name: &str) -> Result<PathBuf> {
+    check_name(name)?;
+    let base = config::base_path()?;
+    if name == DEFAULT_PROFILE {
+        return Ok(base);
+    }
+    let dir = base.parent().ok_or(anyhow::anyhow!("Cannot found user directory"))?;
+    Ok(profile_path(dir, name))
+}
+
+fn profile_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(PROFILES_DIR).join(name).join("config.toml")
+}
+
+fn base_dir() -> Result<PathBuf> {
+    let base = config::base_path()?;
+    base.parent()
+        .map(Path::to_path_buf)
+        .ok_or(anyhow::anyhow!("Cannot found user directory"))
+}
+
+/// Profile names are used as directory names
+fn check_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(anyhow::anyhow!("Invalid profile name '{}', use letters, digits, '-' and '_'", name));
+    }
+    Ok(())
+}
+
+/// Every profile: `default` first, then the named ones in alphabetical order
+pub(crate) fn names() -> Result<Vec<String>> {
+    list_names(&base_dir()?)
+}
+
+fn list_names(dir: &Path) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    match fs::read_dir(dir.join(PROFILES_DIR)) {
+        Ok(entries) => {
+            for entry in entries {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if entry.path().join("config.toml").is_file() && check_name(&name).is_ok() && name != DEFAULT_PROFILE {
+                    names.push(name);
+                }
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    names.sort();
+    names.insert(0, DEFAULT_PROFILE.to_string());
+    Ok(names)
+}
+
+/// Accounts of a profile without unlocking it, for listing and searching only
+///
+/// Secrets of encrypted profiles stay sealed, so none of these can produce codes.
+pub(crate) fn accounts(name: &str) -> Result<Vec<Account>> {
+    let path = store_path(name)?;
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    Ok(Config::load_metadata(&path)?.accounts)
+}
+
+/// The profile used without `--profile`
+pub(crate) fn default_profile() -> Result<String> {
+    Ok(read_profiles(&base_dir()?)?.default.unwrap_or_else(|| DEFAULT_PROFILE.to_string()))
+}
+
+pub(crate) fn set_default(name: &str) -> Result<()> {
+    let dir = base_dir()?;
+    if !list_names(&dir)?.iter().any(|profile| profile == name) {
+        return Err(anyhow::anyhow!("Profile '{}' does not exist", name));
+    }
+    let profiles = Profiles { default: Some(name.to_string()).filter(|name| name != DEFAULT_PROFILE) };
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join(PROFILES_FILE), toml::to_string(&profiles)?)?;
+    Ok(())
+}
+
+fn read_profiles(dir: &Path) -> Result<Profiles> {
+    match fs::read_to_string(dir.join(PROFILES_FILE)) {
+        Ok(text) => toml::from_str(&text).map_err(|e| anyhow::anyhow!("Invalid {}: {}", PROFILES_FILE, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Profiles::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Create an empty store for a new profile, encrypted when a passphrase is given
+pub(crate) fn create(name: &str, passphrase: Option<&str>) -> Result<PathBuf> {
+    let path = store_path(name)?;
+    if name == DEFAULT_PROFILE || path.exists() {
+        return Err(anyhow::anyhow!("Profile '{}' already exists", name));
+    }
+    let dir = path.parent().ok_or(anyhow::anyhow!("Cannot found user directory"))?;
+    fs::create_dir_all(dir)?;
+
+    let mut config = Config::new(Vec::new());
+    config::write_private_file(&path, toml::to_string(&config)?.as_bytes())?;
+    if passphrase.is_some() {
+        config.set_passphrase(passphrase)?;
+        config.save_to_file(&path)?;
+    }
+    Ok(path)
+}
+
+/// Make sure a profile exists and may be removed; returns its store path
+pub(crate) fn check_removable(name: &str) -> Result<PathBuf> {
+    if name == DEFAULT_PROFILE {
+        return Err(anyhow::anyhow!("The default profile cannot be removed"));
+    }
+    let path = store_path(name)?;
+    if !path.is_file() {
+        return Err(anyhow::anyhow!("Profile '{}' does not exist", name));
+    }
+    if default_profile()? == name {
+        return Err(anyhow::anyhow!("Profile '{}' is the default, choose another default first", name));
+    }
+    Ok(path)
+}
+
+/// Delete a named profile with its store and audit log
+pub(crate) fn remove(name: &str) -> Result<()> {
+    let path = check_removable(name)?;
+    let dir = path.parent().ok_or(anyhow::anyhow!("Cannot found user directory"))?;
+    fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names() {
+        assert!(check_name("work_2024").is_ok());
+        assert!(check_name("../etc").is_err());
+        assert!(check_name("").is_err());
+
+        let dir = std::env::temp_dir().join(format!("otpc-profiles-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for name in ["work", "personal"] {
+            let path = profile_path(&dir, name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "accounts = []\n").unwrap();
+        }
+        fs::create_dir_all(dir.join(PROFILES_DIR).join("empty")).unwrap();
+
+        assert_eq!(list_names(&dir).unwrap(), ["default", "personal", "work"]);
+        assert!(read_profiles(&dir).unwrap().default.is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
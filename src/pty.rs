@@ -0,0 +1,339 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+
+use anyhow::Result;
+use regex::Regex;
+use zeroize::Zeroizing;
+
+use crate::menu;
+
+/// Output kept for matching prompts split across reads
+const SEEN_LIMIT: usize = 4096;
+
+/// What to type into the session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Reply {
+    Password,
+    Code,
+}
+
+/// Watches the child's output for the password and code prompts, answering each once
+pub(crate) struct Script {
+    prompt: Regex,
+    password: Option<(Regex, Zeroizing<String>)>,
+    seen: String,
+    password_sent: bool,
+    code_sent: bool,
+}
+
+impl Script {
+    pub(crate) fn new(prompt: &str, password: Option<(&str, Zeroizing<String>)>) -> Result<Self> {
+        let password = match password {
+            Some((regex, password)) => Some((compile(regex)?, password)),
+            None => None,
+        };
+        Ok(Self { prompt: compile(prompt)?, password, seen: String::new(), password_sent: false, code_sent: false })
+    }
+
+    /// Take in more output; returns what to type if a prompt just appeared
+    pub(crate) fn feed(&mut self, output: &[u8]) -> Option<Reply> {
+        if self.code_sent {
+            return None;
+        }
+        self.seen.push_str(&String::from_utf8_lossy(output));
+        if self.seen.len() > SEEN_LIMIT {
+            let mut start = self.seen.len() - SEEN_LIMIT;
+            while !self.seen.is_char_boundary(start) {
+                start += 1;
+            }
+            self.seen.drain(..start);
+        }
+
+        let reply = if self.prompt.is_match(&self.seen) {
+            self.code_sent = true;
+            Reply::Code
+        } else if !self.password_sent && self.password.as_ref().is_some_and(|(regex, _)| regex.is_match(&self.seen)) {
+            self.password_sent = true;
+            Reply::Password
+        } else {
+            return None;
+        };
+        // Only output after this reply may trigger the next one
+        self.seen.clear();
+        Some(reply)
+    }
+
+    fn password(&self) -> &str {
+        self.password.as_ref().map(|(_, password)| password.as_str()).unwrap_or_default()
+    }
+}
+
+fn compile(regex: &str) -> Result<Regex> {
+    Regex::new(regex).map_err(|e| anyhow::anyhow!("Invalid prompt pattern '{}': {}", regex, e))
+}
+
+/// The password to type before the code: the first line of a file or of a command's output
+pub(crate) fn read_password(file: Option<&Path>, command: Option<&str>) -> Result<Option<Zeroizing<String>>> {
+    let text = match (file, command) {
+        (Some(file), _) => Zeroizing::new(fs::read_to_string(file)?),
+        (None, Some(command)) => {
+            let output = menu::shell_command(command).stderr(Stdio::inherit()).output()?;
+            let stdout = Zeroizing::new(output.stdout);
+            if !output.status.success() {
+                return Err(anyhow::anyhow!("The password command failed with {}", output.status));
+            }
+            Zeroizing::new(String::from_utf8(stdout.to_vec())?)
+        }
+        (None, None) => return Ok(None),
+    };
+    Ok(Some(Zeroizing::new(text.lines().next().unwrap_or_default().to_string())))
+}
+
+/// Run `command` in a pseudo-terminal, answer the prompts, then leave the session to the user
+#[cfg(unix)]
+pub(crate) fn run(
+    command: &[String],
+    script: &mut Script,
+    code: impl FnMut() -> Result<Zeroizing<String>>,
+) -> Result<ExitStatus> {
+    use std::io;
+    use std::os::fd::AsRawFd as _;
+
+    let stdin = io::stdin().as_raw_fd();
+    let size = unix::window_size(stdin);
+    // Keys go to the child as typed, so line editing and Ctrl-C are handled on its side
+    let _raw = unix::RawMode::enable(stdin)?;
+    let stdout = io::stdout();
+    unix::session(command, script, code, Some(stdin), &mut stdout.lock(), size)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn run(
+    _command: &[String],
+    _script: &mut Script,
+    _code: impl FnMut() -> Result<Zeroizing<String>>,
+) -> Result<ExitStatus> {
+    Err(anyhow::anyhow!("otpc pty is only supported on Unix"))
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::fs::File;
+    use std::io::{self, Read as _, Write};
+    use std::os::fd::{AsRawFd as _, FromRawFd as _, OwnedFd, RawFd};
+    use std::os::unix::process::CommandExt as _;
+    use std::ptr;
+
+    use super::*;
+
+    /// How long to wait for output before checking whether the terminal was resized
+    const POLL_MILLIS: libc::c_int = 250;
+
+    /// Puts a terminal into raw mode and restores it when dropped
+    pub(super) struct RawMode {
+        fd: RawFd,
+        original: libc::termios,
+    }
+
+    impl RawMode {
+        /// `None` when `fd` is not a terminal, e.g. in scripts and tests
+        pub(super) fn enable(fd: RawFd) -> io::Result<Option<Self>> {
+            // SAFETY: isatty only inspects the descriptor
+            if unsafe { libc::isatty(fd) } == 0 {
+                return Ok(None);
+            }
+            // SAFETY: termios is plain data and tcgetattr fills it in completely on success
+            let mut original: libc::termios = unsafe { std::mem::zeroed() };
+            if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut raw = original;
+            // SAFETY: raw is a valid termios
+            unsafe { libc::cfmakeraw(&mut raw) };
+            if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Some(Self { fd, original }))
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            // SAFETY: original came from tcgetattr on the same descriptor
+            unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.original) };
+        }
+    }
+
+    pub(super) fn window_size(fd: RawFd) -> Option<libc::winsize> {
+        // SAFETY: winsize is plain data filled in by the ioctl
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        match unsafe { libc::ioctl(fd, libc::TIOCGWINSZ as _, &mut size) } {
+            0 => Some(size),
+            _ => None,
+        }
+    }
+
+    fn set_window_size(fd: RawFd, size: &libc::winsize) {
+        // SAFETY: size points to a valid winsize
+        unsafe { libc::ioctl(fd, libc::TIOCSWINSZ as _, size) };
+    }
+
+    fn open_pty() -> io::Result<(File, OwnedFd)> {
+        let (mut master, mut slave) = (-1, -1);
+        // SAFETY: both out-pointers are valid, and the name, termios and size may be null
+        let result = unsafe { libc::openpty(&mut master, &mut slave, ptr::null_mut(), ptr::null_mut(), ptr::null_mut()) };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: openpty returned two new descriptors that nothing else owns
+        let (master, slave) = unsafe { (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+        for fd in [master.as_raw_fd(), slave.as_raw_fd()] {
+            // Keep both out of the child; it gets the slave as its standard streams only
+            // SAFETY: fcntl on descriptors owned above
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        }
+        Ok((master, slave))
+    }
+
+    /// Run the child on a new pseudo-terminal and relay between it and `input`/`output` until it exits
+    pub(super) fn session(
+        command: &[String],
+        script: &mut Script,
+        mut code: impl FnMut() -> Result<Zeroizing<String>>,
+        input: Option<RawFd>,
+        output: &mut impl Write,
+        size: Option<libc::winsize>,
+    ) -> Result<ExitStatus> {
+        let (program, args) = command.split_first().ok_or(anyhow::anyhow!("No command to run"))?;
+        let (mut master, slave) = open_pty()?;
+        if let Some(size) = &size {
+            set_window_size(master.as_raw_fd(), size);
+        }
+
+        let mut child = Command::new(program);
+        child.args(args)
+            .stdin(Stdio::from(slave.try_clone()?))
+            .stdout(Stdio::from(slave.try_clone()?))
+            .stderr(Stdio::from(slave));
+        // SAFETY: setsid and ioctl are async-signal-safe, as required between fork and exec
+        unsafe {
+            child.pre_exec(|| {
+                // A new session with the pseudo-terminal as its controlling terminal
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let mut process = child.spawn().map_err(|e| anyhow::anyhow!("Cannot run '{}': {}", program, e))?;
+        // Close our copies of the slave, so reading the master fails once the child is gone
+        drop(child);
+
+        let mut fds = [
+            libc::pollfd { fd: master.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+            libc::pollfd { fd: input.unwrap_or(-1), events: libc::POLLIN, revents: 0 },
+        ];
+        let mut last_size = size.map(|size| (size.ws_row, size.ws_col));
+        let mut buffer = [0u8; 4096];
+
+        loop {
+            // SAFETY: fds is a valid array of two pollfd
+            let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, POLL_MILLIS) };
+            if ready < 0 {
+                let error = io::Error::last_os_error();
+                if error.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(error.into());
+            }
+
+            if let Some(input) = input {
+                if let Some(size) = window_size(input).filter(|size| Some((size.ws_row, size.ws_col)) != last_size) {
+                    set_window_size(master.as_raw_fd(), &size);
+                    last_size = Some((size.ws_row, size.ws_col));
+                }
+            }
+
+            if fds[0].revents != 0 {
+                let read = match master.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(read) => read,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    // Linux reports EIO once the last process holding the terminal is gone
+                    Err(_) => break,
+                };
+                output.write_all(&buffer[..read])?;
+                output.flush()?;
+
+                match script.feed(&buffer[..read]) {
+                    Some(Reply::Password) => {
+                        let line = Zeroizing::new(format!("{}\r", script.password()));
+                        master.write_all(line.as_bytes())?;
+                    }
+                    Some(Reply::Code) => {
+                        let line = Zeroizing::new(format!("{}\r", code()?.as_str()));
+                        master.write_all(line.as_bytes())?;
+                    }
+                    None => {}
+                }
+            }
+
+            if fds[1].revents != 0 {
+                // SAFETY: the buffer is valid for its whole length
+                let read = unsafe { libc::read(fds[1].fd, buffer.as_mut_ptr().cast(), buffer.len()) };
+                if read <= 0 {
+                    // End of our input: keep showing the child's output
+                    fds[1].fd = -1;
+                } else {
+                    master.write_all(&buffer[..read as usize])?;
+                }
+            }
+        }
+        Ok(process.wait()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script() {
+        let password = Some(("(?i)password:", Zeroizing::new("hunter2".to_string())));
+        let mut script = Script::new("Verification code", password).unwrap();
+
+        assert_eq!(script.feed(b"Welcome\r\nPass"), None);
+        assert_eq!(script.feed(b"word: "), Some(Reply::Password));
+        assert_eq!(script.feed(b"\r\nPassword: "), None);
+        assert_eq!(script.feed(b"Verification code: "), Some(Reply::Code));
+        // Handed over to the user from here on
+        assert_eq!(script.feed(b"Verification code: "), None);
+        assert!(Script::new("(", None).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_session_with_fake_program() {
+        let program = r#"printf 'Password: '; read p; printf 'Verification code: '; read c
+            test "$p" = hunter2 && test "$c" = 123456 && echo logged in"#;
+        let command: Vec<String> = ["sh", "-c", program].iter().map(ToString::to_string).collect();
+
+        let password = Some(("Password:", Zeroizing::new("hunter2".to_string())));
+        let mut script = Script::new("Verification code", password).unwrap();
+        let mut output = Vec::new();
+        let status = unix::session(&command, &mut script, || Ok(Zeroizing::new("123456".to_string())), None, &mut output, None).unwrap();
+
+        assert!(status.success(), "{}", String::from_utf8_lossy(&output));
+        assert!(String::from_utf8_lossy(&output).contains("logged in"));
+    }
+
+    #[test]
+    fn test_read_password() {
+        let path = std::env::temp_dir().join(format!("otpc-password-{}", std::process::id()));
+        fs::write(&path, "hunter2\nignored\n").unwrap();
+        assert_eq!(read_password(Some(&path), None).unwrap().unwrap().as_str(), "hunter2");
+        fs::remove_file(&path).unwrap();
+        assert!(read_password(None, None).unwrap().is_none());
+    }
+}
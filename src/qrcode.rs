@@ -1,15 +1,17 @@
-use std::path::Path;
-
 use anyhow::Result;
 use rqrr::PreparedImage;
+use zeroize::Zeroizing;
 use crate::config::Account;
+use crate::otp::{Algorithm, OtpType};
+use crate::secret::SecretString;
 
-pub(crate) fn parse_qr_from_image(file: &Path) -> Result<Account> {
-    let img = image::open(file)?;
+pub(crate) fn parse_qr_from_bytes(data: &[u8]) -> Result<Account> {
+    let img = image::load_from_memory(data)?;
     let mut img = PreparedImage::prepare(img.to_luma8());
     let grids = img.detect_grids();
 
-    let qr_uri = grids[0].decode()
+    let grid = grids.first().ok_or(anyhow::anyhow!("No QR code found in image"))?;
+    let qr_uri = grid.decode()
         .map_err(|_| anyhow::anyhow!("Failed to decode QR code"))?;
     parse_key_uri(&qr_uri.1)
 }
@@ -23,6 +25,11 @@ pub(crate) fn parse_key_uri(uri: &str) -> Result<Account> {
     let remaining = &uri[10..];
 
     let type_end = remaining.find('/').ok_or(anyhow::anyhow!("Invalid URI: Missing type separator"))?;
+    let otp_type = match remaining[..type_end].to_lowercase().as_str() {
+        "totp" => OtpType::Totp,
+        "hotp" => OtpType::Hotp,
+        other => return Err(anyhow::anyhow!("Invalid URI: Unsupported OTP type '{}'", other)),
+    };
     let remaining = &remaining[type_end + 1..];
 
     // Splitting label and parameters
@@ -34,7 +41,7 @@ pub(crate) fn parse_key_uri(uri: &str) -> Result<Account> {
     // Parsing label (includes URL decoding)
     // Format: "Issuer:AccountName" or "AccountName"
     let decoded_label = url_decode(label_part)?;
-    let (mut issuer, name) = match decoded_label.find(':') {
+    let (issuer, name) = match decoded_label.find(':') {
         Some(idx) => {
             // Remove spaces
             let potential_issuer = decoded_label[..idx].trim();
@@ -55,6 +62,8 @@ pub(crate) fn parse_key_uri(uri: &str) -> Result<Account> {
 
     // Parsing parameters
     let mut secret = None;
+    let mut account = Account::new(name, SecretString::default(), issuer);
+    account.otp_type = otp_type;
     if let Some(params_str) = params_part {
         for param in params_str.split('&') {
             let pair: Vec<&str> = param.splitn(2, '=').collect();
@@ -63,9 +72,19 @@ pub(crate) fn parse_key_uri(uri: &str) -> Result<Account> {
                 let value = url_decode(pair[1])?;
 
                 match key.to_lowercase().as_str() {
-                    "secret" => secret = Some(value),
-                    "issuer" => issuer = value, // issuer parameter has higher priority
-                    // other parameters (algorithm, digits, period, counter) are currently ignored
+                    "secret" => secret = Some(SecretString::new(value)),
+                    "issuer" => account.issuer = value, // issuer parameter has higher priority
+                    "algorithm" => {
+                        account.algorithm = Algorithm::parse(&value)
+                            .ok_or(anyhow::anyhow!("Invalid URI: Unsupported algorithm '{}'", value))?;
+                    }
+                    "digits" => account.digits = value.parse()
+                        .map_err(|_| anyhow::anyhow!("Invalid URI: Invalid digits '{}'", value))?,
+                    "period" => account.period = value.parse()
+                        .map_err(|_| anyhow::anyhow!("Invalid URI: Invalid period '{}'", value))?,
+                    "counter" => account.counter = value.parse()
+                        .map_err(|_| anyhow::anyhow!("Invalid URI: Invalid counter '{}'", value))?,
+                    // other parameters (e.g. image) are ignored
                     _ => {},
                 }
             }
@@ -76,15 +95,54 @@ pub(crate) fn parse_key_uri(uri: &str) -> Result<Account> {
     if secret.is_empty() {
         return Err(anyhow::anyhow!("Invalid URI: Secret cannot be empty"));
     }
+    account.secret = secret;
+    account.validate()?;
+
+    Ok(account)
+}
 
-    Ok(Account {
-        name,
-        secret,
-        issuer,
-    })
+// Inverse of parse_key_uri; the issuer is left out when it is the "host" placeholder
+pub(crate) fn to_key_uri(account: &Account) -> Zeroizing<String> {
+    let otp_type = match account.otp_type {
+        OtpType::Totp => "totp",
+        OtpType::Hotp => "hotp",
+    };
+    let has_issuer = account.issuer != "host" && !account.issuer.is_empty();
+
+    let mut uri = Zeroizing::new(String::from("otpauth://"));
+    uri.push_str(otp_type);
+    uri.push('/');
+    if has_issuer {
+        uri.push_str(&url_encode(&account.issuer));
+        uri.push(':');
+    }
+    uri.push_str(&url_encode(&account.name));
+    uri.push_str("?secret=");
+    uri.push_str(&url_encode(account.secret.expose()));
+    if has_issuer {
+        uri.push_str("&issuer=");
+        uri.push_str(&url_encode(&account.issuer));
+    }
+    uri.push_str(&format!("&algorithm={}&digits={}", account.algorithm.as_str(), account.digits));
+    match account.otp_type {
+        OtpType::Totp => uri.push_str(&format!("&period={}", account.period)),
+        OtpType::Hotp => uri.push_str(&format!("&counter={}", account.counter)),
+    }
+    uri
+}
+
+pub(crate) fn url_encode(input: &str) -> String {
+    let mut result = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => result.push(byte as char),
+            _ => result.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    result
 }
 
-fn url_decode(input: &str) -> Result<String> {
+pub(crate) fn url_decode(input: &str) -> Result<String> {
     let mut result = Vec::new();
     let mut chars = input.bytes().peekable();
 
@@ -109,11 +167,11 @@ fn url_decode(input: &str) -> Result<String> {
         .map_err(|e| anyhow::anyhow!("Decoded string is not valid UTF-8: {}", e))
 }
 
-#[allow(unused)]
+#[cfg(test)]
 mod tests {
     use rqrr::PreparedImage;
 
-    use crate::qrcode::parse_key_uri;
+    use crate::qrcode::{parse_key_uri, to_key_uri};
 
     #[test]
     fn test_parse_qr_from_image() {
@@ -121,17 +179,57 @@ mod tests {
         let mut img = PreparedImage::prepare(img.to_luma8());
         let grids = img.detect_grids();
 
-        assert!(grids.len() > 0);
+        assert!(!grids.is_empty());
 
-        // decode the grids
-        let qr = grids[0].decode();
-        println!("{:?}", qr);
+        // The sample encodes a web link rather than an otpauth URI
+        let (_, content) = grids[0].decode().expect("Failed to decode QR code");
+        assert!(parse_key_uri(&content).is_err());
+
+        // The decoded URI holds the secret, so only the parsed account is checked
+        let uri = "otpauth://totp/Example:alice@google.com?secret=J5QXG4T5J5QXG4T5J5QXG4T5&issuer=Example";
+        let code = ::qrcode::QrCode::new(uri).expect("Failed to encode QR code");
+        let mut img = PreparedImage::prepare(code.render::<image::Luma<u8>>().min_dimensions(200, 200).build());
+        let grids = img.detect_grids();
+        let (_, decoded) = grids[0].decode().expect("Failed to decode QR code");
+        let account = parse_key_uri(&decoded).expect("Failed to parse key URI");
+        assert_eq!(account.name, "alice@google.com");
+        assert_eq!(account.issuer, "Example");
     }
 
     #[test]
     fn test_parse_key_uri() {
         let uri = "otpauth://totp/Example:alice@google.com?secret=J5QXG4T5J5QXG4T5J5QXG4T5";
         let account = parse_key_uri(uri).expect("Failed to parse key URI");
-        println!("{:?}", account);
+        assert_eq!(account.name, "alice@google.com");
+        assert_eq!(account.issuer, "Example");
+        assert_eq!(account.secret.expose(), "J5QXG4T5J5QXG4T5J5QXG4T5");
+
+        // The secret must never leak through Debug output
+        assert!(!format!("{:?}", account).contains("J5QXG4T5"));
+    }
+
+    #[test]
+    fn test_parse_key_uri_parameters() {
+        let uri = "otpauth://hotp/ACME%20Co:john?secret=JBSWY3DPEHPK3PXP&algorithm=SHA256&digits=8&period=60&counter=42";
+        let account = parse_key_uri(uri).expect("Failed to parse key URI");
+        assert_eq!(account.issuer, "ACME Co");
+        assert_eq!(account.otp_type, crate::otp::OtpType::Hotp);
+        assert_eq!(account.algorithm, crate::otp::Algorithm::Sha256);
+        assert_eq!((account.digits, account.period, account.counter), (8, 60, 42));
+
+        assert!(parse_key_uri("otpauth://totp/x?secret=JBSWY3DPEHPK3PXP&algorithm=MD5").is_err());
+    }
+
+    #[test]
+    fn test_key_uri_roundtrip() {
+        let uri = "otpauth://hotp/ACME%20Co:john%40example.com?secret=JBSWY3DPEHPK3PXP&issuer=ACME%20Co&algorithm=SHA256&digits=8&counter=42";
+        let account = parse_key_uri(uri).expect("Failed to parse key URI");
+        assert_eq!(to_key_uri(&account).as_str(), uri);
+
+        let account = parse_key_uri("otpauth://totp/router?secret=JBSWY3DPEHPK3PXP").unwrap();
+        assert_eq!(
+            to_key_uri(&account).as_str(),
+            "otpauth://totp/router?secret=JBSWY3DPEHPK3PXP&algorithm=SHA1&digits=6&period=30",
+        );
     }
 }
@@ -0,0 +1,308 @@
+use std::fmt;
+
+use anyhow::Result;
+use clap::ValueEnum;
+
+use crate::config::{Account, TrashedAccount};
+
+/// What to do when an incoming account collides with a stored one
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep the stored account and drop the incoming one
+    Skip,
+    /// Overwrite the stored account with the incoming one, moving the stored one to the trash
+    Replace,
+    /// Add the incoming account under a new name
+    Rename,
+    /// Ask for every conflict
+    Ask,
+}
+
+/// How a single conflict is settled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Resolution {
+    Skip,
+    Replace,
+    Rename,
+}
+
+/// How an incoming account relates to the store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Match {
+    /// Nothing similar is stored
+    New,
+    /// An account with the same secret is stored at this index
+    Duplicate(usize),
+    /// The same issuer and name hold a different secret
+    SameIssuerAndName(usize),
+    /// The same name is used by another issuer with a different secret
+    SameName(usize),
+}
+
+/// Compare an account against the store
+pub(crate) fn classify(accounts: &[Account], account: &Account) -> Match {
+    if let Some(index) = accounts.iter().position(|other| other.has_same_secret(account)) {
+        Match::Duplicate(index)
+    } else if let Some(index) = accounts.iter()
+        .position(|other| other.name == account.name && other.issuer == account.issuer)
+    {
+        Match::SameIssuerAndName(index)
+    } else if let Some(index) = accounts.iter().position(|other| other.name == account.name) {
+        Match::SameName(index)
+    } else {
+        Match::New
+    }
+}
+
+/// What happened to one incoming account
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Added,
+    /// Skipped because the same secret is already stored
+    Duplicate,
+    Replaced,
+    /// Added under the given name
+    Renamed(String),
+    /// Conflict left alone by the policy
+    Skipped,
+    /// Conflict that still needs a decision (dry runs with `ask`)
+    Unresolved,
+}
+
+#[derive(Debug)]
+pub(crate) struct Change {
+    pub outcome: Outcome,
+    pub name: String,
+    pub issuer: String,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match &self.outcome {
+            Outcome::Added => "add",
+            Outcome::Duplicate => "skip (duplicate)",
+            Outcome::Replaced => "replace",
+            Outcome::Renamed(_) => "rename",
+            Outcome::Skipped => "skip (conflict)",
+            Outcome::Unresolved => "conflict",
+        };
+        write!(f, "{:<16} {} ({})", label, self.name, self.issuer)?;
+        match &self.outcome {
+            Outcome::Renamed(name) => write!(f, " -> {}", name),
+            Outcome::Replaced => write!(f, ", previous account moved to the trash"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Carry over what only otpc knows about a replaced account: its audit log ID, recovery codes, and the
+/// group, tags, note and favorite flag unless the incoming account sets them
+fn keep_local_fields(stored: &Account, incoming: &mut Account) {
+    incoming.id = stored.id.clone();
+    if incoming.recovery_codes.is_empty() {
+        incoming.recovery_codes = stored.recovery_codes.clone();
+    }
+    if incoming.group.is_none() {
+        incoming.group = stored.group.clone();
+    }
+    if incoming.tags.is_empty() {
+        incoming.tags = stored.tags.clone();
+    }
+    if incoming.note.is_none() {
+        incoming.note = stored.note.clone();
+    }
+    incoming.favorite |= stored.favorite;
+}
+
+/// Merge incoming accounts into the store according to the conflict policy
+///
+/// `ask` is called with the stored and incoming account for every conflict
+/// under `ConflictPolicy::Ask`; returning `None` leaves the conflict unresolved.
+/// Replaced accounts go to `trash`, deleted at `now`, so their secret can be restored.
+pub(crate) fn reconcile(
+    accounts: &mut Vec<Account>,
+    trash: &mut Vec<TrashedAccount>,
+    incoming: Vec<Account>,
+    policy: ConflictPolicy,
+    now: u64,
+    mut ask: impl FnMut(&Account, &Account) -> Result<Option<Resolution>>,
+) -> Result<Vec<Change>> {
+    let mut changes = Vec::with_capacity(incoming.len());
+
+    for mut account in incoming {
+        let (name, issuer) = (account.name.clone(), account.issuer.clone());
+        let outcome = match classify(accounts, &account) {
+            Match::New => {
+                accounts.push(account);
+                Outcome::Added
+            }
+            Match::Duplicate(_) => Outcome::Duplicate,
+            Match::SameIssuerAndName(index) | Match::SameName(index) => {
+                let resolution = match policy {
+                    ConflictPolicy::Skip => Some(Resolution::Skip),
+                    ConflictPolicy::Replace => Some(Resolution::Replace),
+                    ConflictPolicy::Rename => Some(Resolution::Rename),
+                    ConflictPolicy::Ask => ask(&accounts[index], &account)?,
+                };
+
+                match resolution {
+                    None => Outcome::Unresolved,
+                    Some(Resolution::Skip) => Outcome::Skipped,
+                    Some(Resolution::Replace) => {
+                        keep_local_fields(&accounts[index], &mut account);
+                        let replaced = std::mem::replace(&mut accounts[index], account);
+                        trash.push(TrashedAccount { deleted_at: now, account: replaced });
+                        Outcome::Replaced
+                    }
+                    Some(Resolution::Rename) => {
+                        account.name = unique_name(accounts, &account);
+                        let renamed = account.name.clone();
+                        accounts.push(account);
+                        Outcome::Renamed(renamed)
+                    }
+                }
+            }
+        };
+        changes.push(Change { outcome, name, issuer });
+    }
+    Ok(changes)
+}
+
+/// One-line count of every kind of outcome, e.g. "2 added, 1 duplicate(s) skipped"
+pub(crate) fn summary(changes: &[Change]) -> String {
+    let count = |matches: fn(&Outcome) -> bool| changes.iter().filter(|change| matches(&change.outcome)).count();
+    let parts = [
+        (count(|o| *o == Outcome::Added), "added"),
+        (count(|o| *o == Outcome::Replaced), "replaced (previous accounts moved to the trash)"),
+        (count(|o| matches!(o, Outcome::Renamed(_))), "renamed"),
+        (count(|o| *o == Outcome::Duplicate), "duplicate(s) skipped"),
+        (count(|o| *o == Outcome::Skipped), "conflict(s) skipped"),
+        (count(|o| *o == Outcome::Unresolved), "conflict(s) to resolve"),
+    ];
+
+    let summary: Vec<String> = parts.iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, label)| format!("{} {}", count, label))
+        .collect();
+    if summary.is_empty() {
+        "nothing to do".to_string()
+    } else {
+        summary.join(", ")
+    }
+}
+
+/// Whether any change touches the store
+pub(crate) fn has_changes(changes: &[Change]) -> bool {
+    changes.iter().any(|change| matches!(change.outcome, Outcome::Added | Outcome::Replaced | Outcome::Renamed(_)))
+}
+
+/// `name (issuer)` if free, otherwise `name-2`, `name-3`, ...
+fn unique_name(accounts: &[Account], account: &Account) -> String {
+    let taken = |name: &str| accounts.iter().any(|other| other.name == name);
+
+    if account.issuer != "host" {
+        let candidate = format!("{} ({})", account.name, account.issuer);
+        if !taken(&candidate) {
+            return candidate;
+        }
+    }
+    (2..)
+        .map(|n| format!("{}-{}", account.name, n))
+        .find(|candidate| !taken(candidate))
+        .expect("an unused name exists")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RecoveryCode;
+
+    fn account(name: &str, secret: &str, issuer: &str) -> Account {
+        Account::new(name.to_string(), secret.into(), issuer.to_string())
+    }
+
+    fn incoming() -> Vec<Account> {
+        vec![
+            account("alice-copy", "jbsw y3dp ehpk 3pxp", "Example"),
+            account("alice", "GEZDGNBVGY3TQOJQ", "Example"),
+            account("alice", "MFRGGZDFMZTWQ2LK", "Bank"),
+            account("bob", "NBQXEYLDM5WGKZTF", "host"),
+            account("bob", "NBQXEYLDM5WGKZTF", "host"),
+        ]
+    }
+
+    #[test]
+    fn test_classify() {
+        let stored = vec![account("alice", "JBSWY3DPEHPK3PXP", "Example")];
+        let matches: Vec<Match> = incoming().iter().map(|account| classify(&stored, account)).collect();
+        assert_eq!(matches[..4], [Match::Duplicate(0), Match::SameIssuerAndName(0), Match::SameName(0), Match::New]);
+    }
+
+    #[test]
+    fn test_reconcile_policies() {
+        let stored = vec![account("alice", "JBSWY3DPEHPK3PXP", "Example")];
+        let never_ask = |_: &Account, _: &Account| -> Result<Option<Resolution>> { panic!("policy is not ask") };
+
+        let mut trash = Vec::new();
+
+        let mut accounts = stored.clone();
+        let changes = reconcile(&mut accounts, &mut trash, incoming(), ConflictPolicy::Skip, 100, never_ask).unwrap();
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(summary(&changes), "1 added, 2 duplicate(s) skipped, 2 conflict(s) skipped");
+
+        let mut accounts = stored.clone();
+        let changes = reconcile(&mut accounts, &mut trash, incoming(), ConflictPolicy::Replace, 100, never_ask).unwrap();
+        // The second conflict replaces what the first one wrote; both previous accounts are in the trash
+        assert_eq!((accounts[0].issuer.as_str(), accounts[0].secret.expose()), ("Bank", "MFRGGZDFMZTWQ2LK"));
+        let trashed: Vec<(&str, u64)> = trash.iter().map(|t| (t.account.secret.expose(), t.deleted_at)).collect();
+        assert_eq!(trashed, [("JBSWY3DPEHPK3PXP", 100), ("GEZDGNBVGY3TQOJQ", 100)]);
+        assert_eq!(changes[2].to_string(), "replace          alice (Bank), previous account moved to the trash");
+        assert_eq!(summary(&changes), "1 added, 2 replaced (previous accounts moved to the trash), 2 duplicate(s) skipped");
+
+        let mut trash = Vec::new();
+        let mut accounts = stored.clone();
+        let changes = reconcile(&mut accounts, &mut trash, incoming(), ConflictPolicy::Rename, 100, never_ask).unwrap();
+        let names: Vec<&str> = accounts.iter().map(|account| account.name.as_str()).collect();
+        assert_eq!(names, ["alice", "alice (Example)", "alice (Bank)", "bob"]);
+        assert_eq!(changes[1].to_string(), "rename           alice (Example) -> alice (Example)");
+
+        let mut accounts = stored.clone();
+        let mut asked = 0;
+        let changes = reconcile(&mut accounts, &mut trash, incoming(), ConflictPolicy::Ask, 100, |existing, incoming| {
+            asked += 1;
+            assert_eq!(existing.name, incoming.name);
+            Ok(None)
+        }).unwrap();
+        assert_eq!(asked, 2);
+        assert_eq!(accounts.len(), 2);
+        assert!(trash.is_empty());
+        assert!(changes.iter().any(|change| change.outcome == Outcome::Unresolved));
+    }
+
+    #[test]
+    fn test_replace_keeps_local_fields() {
+        let mut stored = account("alice", "JBSWY3DPEHPK3PXP", "Example");
+        stored.id = "1234".to_string();
+        stored.recovery_codes.push(RecoveryCode { code: "k7m2p-q9x4t".into(), used: false });
+        (stored.group, stored.tags, stored.note) = (Some("Work".to_string()), vec!["mail".to_string()], Some("primary".to_string()));
+
+        let mut replacement = account("alice", "GEZDGNBVGY3TQOJQ", "Example");
+        replacement.note = Some("from the phone".to_string());
+        let mut accounts = vec![stored.clone()];
+        let mut trash = Vec::new();
+        reconcile(&mut accounts, &mut trash, vec![replacement], ConflictPolicy::Replace, 100, |_, _| Ok(None)).unwrap();
+        assert_eq!(trash[0].account.secret.expose(), "JBSWY3DPEHPK3PXP");
+
+        let replaced = &accounts[0];
+        assert_eq!(replaced.secret.expose(), "GEZDGNBVGY3TQOJQ");
+        assert_eq!((replaced.id.as_str(), &replaced.recovery_codes), ("1234", &stored.recovery_codes));
+        assert_eq!((&replaced.group, &replaced.tags), (&stored.group, &stored.tags));
+        assert_eq!(replaced.note.as_deref(), Some("from the phone"));
+    }
+
+    #[test]
+    fn test_unique_name() {
+        let stored = vec![account("bob", "JBSWY3DPEHPK3PXP", "host"), account("bob-2", "GEZDGNBVGY3TQOJQ", "host")];
+        assert_eq!(unique_name(&stored, &account("bob", "NBQXEYLDM5WGKZTF", "host")), "bob-3");
+    }
+}
@@ -0,0 +1,208 @@
+use anyhow::Result;
+use zeroize::Zeroizing;
+
+use crate::config::{Account, RecoveryCode};
+use crate::secret::SecretString;
+
+/// Unused codes at or below which `otpc list` and `otpc recovery list` warn
+pub(crate) const LOW_CODES: usize = 2;
+
+/// Marks a used code in the single-column text form of exports
+const USED_SUFFIX: &str = " (used)";
+/// Line starting the codes kept in the note of formats without a field for them
+const NOTE_HEADING: &str = "Recovery codes:";
+
+/// Codes compare equal whatever their case, spaces and dashes
+fn normalize(code: &str) -> Zeroizing<String> {
+    Zeroizing::new(code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .flat_map(char::to_lowercase)
+        .collect())
+}
+
+fn find(account: &Account, code: &str) -> Option<usize> {
+    let code = normalize(code);
+    account.recovery_codes.iter().position(|recovery| normalize(recovery.code.expose()) == code)
+}
+
+/// Add codes the account doesn't have yet; returns how many were added
+pub(crate) fn add_codes(account: &mut Account, codes: &[Zeroizing<String>]) -> usize {
+    let mut added = 0;
+    for code in codes.iter().map(|code| code.trim()).filter(|code| !code.is_empty()) {
+        if find(account, code).is_none() {
+            account.recovery_codes.push(RecoveryCode { code: SecretString::from(code), used: false });
+            added += 1;
+        }
+    }
+    added
+}
+
+/// Mark `code` used, or the first unused code when none is given; returns the code
+pub(crate) fn use_code(account: &mut Account, code: Option<&str>) -> Result<SecretString> {
+    let index = match code {
+        Some(code) => {
+            let index = find(account, code)
+                .ok_or(anyhow::anyhow!("'{}' has no such recovery code", account.name))?;
+            if account.recovery_codes[index].used {
+                return Err(anyhow::anyhow!("This recovery code of '{}' was already used", account.name));
+            }
+            index
+        }
+        None => account.recovery_codes.iter().position(|recovery| !recovery.used)
+            .ok_or(anyhow::anyhow!("'{}' has no unused recovery codes left", account.name))?,
+    };
+    let recovery = &mut account.recovery_codes[index];
+    recovery.used = true;
+    Ok(recovery.code.clone())
+}
+
+pub(crate) fn unused(account: &Account) -> usize {
+    account.recovery_codes.iter().filter(|recovery| !recovery.used).count()
+}
+
+/// Warning for an account running out of codes, if it has any at all
+pub(crate) fn low_codes_warning(account: &Account) -> Option<String> {
+    if account.recovery_codes.is_empty() {
+        return None;
+    }
+    match unused(account) {
+        0 => Some(format!("Every recovery code of '{}' is used, get new ones from {}", account.name, account.issuer)),
+        left if left <= LOW_CODES => Some(format!(
+            "Only {} unused recovery code{} left for '{}', get new ones from {} soon",
+            left, if left == 1 { "" } else { "s" }, account.name, account.issuer,
+        )),
+        _ => None,
+    }
+}
+
+/// Warnings for every listed account running out of codes, in listing order
+pub(crate) fn low_codes_warnings<'a>(accounts: impl IntoIterator<Item = &'a Account>) -> Vec<String> {
+    accounts.into_iter().filter_map(low_codes_warning).collect()
+}
+
+/// One code as text, marked when used
+pub(crate) fn to_text(recovery: &RecoveryCode) -> Zeroizing<String> {
+    let suffix = if recovery.used { USED_SUFFIX } else { "" };
+    Zeroizing::new(format!("{}{}", recovery.code.expose(), suffix))
+}
+
+/// Read a code written by `to_text`
+pub(crate) fn from_text(text: &str) -> Option<RecoveryCode> {
+    let text = text.trim();
+    let (code, used) = match text.strip_suffix(USED_SUFFIX) {
+        Some(code) => (code.trim(), true),
+        None => (text, false),
+    };
+    (!code.is_empty()).then(|| RecoveryCode { code: SecretString::from(code), used })
+}
+
+/// The note with the recovery codes listed below it, for formats whose only free-form field is a note
+pub(crate) fn note_with_codes(account: &Account) -> Option<Zeroizing<String>> {
+    if account.recovery_codes.is_empty() {
+        return account.note.clone().map(Zeroizing::new);
+    }
+    let mut text = Zeroizing::new(String::new());
+    if let Some(note) = account.note.as_deref().filter(|note| !note.is_empty()) {
+        text.push_str(note);
+        text.push_str("\n\n");
+    }
+    text.push_str(NOTE_HEADING);
+    for recovery in &account.recovery_codes {
+        text.push('\n');
+        text.push_str(&to_text(recovery));
+    }
+    Some(text)
+}
+
+/// Split a note written by `note_with_codes` back into the note and its recovery codes
+pub(crate) fn split_note(text: &str) -> (Option<String>, Vec<RecoveryCode>) {
+    let heading = text.match_indices(NOTE_HEADING)
+        .map(|(index, _)| index)
+        .filter(|&index| index == 0 || text[..index].ends_with('\n'))
+        .last();
+    let Some(index) = heading else {
+        return (Some(text.to_string()).filter(|note| !note.is_empty()), Vec::new());
+    };
+    let codes = text[index + NOTE_HEADING.len()..].lines().filter_map(from_text).collect();
+    let note = text[..index].trim_end();
+    (Some(note.to_string()).filter(|note| !note.is_empty()), codes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(codes: &[&str]) -> Account {
+        let mut account = Account::new("octocat".to_string(), "JBSWY3DPEHPK3PXP".into(), "GitHub".to_string());
+        let codes: Vec<Zeroizing<String>> = codes.iter().map(|code| Zeroizing::new(code.to_string())).collect();
+        add_codes(&mut account, &codes);
+        account
+    }
+
+    #[test]
+    fn test_add_and_use() {
+        let mut account = account(&["k7m2p-q9x4t", "h3w8c-r6n5v", " ", "K7M2P Q9X4T"]);
+        assert_eq!(account.recovery_codes.len(), 2);
+
+        assert_eq!(use_code(&mut account, Some("H3W8CR6N5V")).unwrap().expose(), "h3w8c-r6n5v");
+        assert!(use_code(&mut account, Some("h3w8c-r6n5v")).is_err());
+        assert!(use_code(&mut account, Some("00000-00000")).is_err());
+        assert_eq!(use_code(&mut account, None).unwrap().expose(), "k7m2p-q9x4t");
+        assert!(use_code(&mut account, None).is_err());
+        assert_eq!(unused(&account), 0);
+    }
+
+    #[test]
+    fn test_low_codes_warning() {
+        assert_eq!(low_codes_warning(&account(&[])), None);
+        let mut account = account(&["a", "b", "c"]);
+        assert_eq!(low_codes_warning(&account), None);
+        use_code(&mut account, None).unwrap();
+        assert!(low_codes_warning(&account).unwrap().starts_with("Only 2 unused recovery codes"));
+        use_code(&mut account, None).unwrap();
+        use_code(&mut account, None).unwrap();
+        assert!(low_codes_warning(&account).unwrap().starts_with("Every recovery code"));
+    }
+
+    #[test]
+    fn test_low_codes_warnings() {
+        let mut low = account(&["a", "b"]);
+        low.name = "low".to_string();
+        let mut spent = account(&["c"]);
+        spent.name = "spent".to_string();
+        use_code(&mut spent, None).unwrap();
+        let accounts = [account(&[]), low, account(&["d", "e", "f"]), spent];
+
+        let warnings = low_codes_warnings(&accounts);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("'low'"));
+        assert!(warnings[1].starts_with("Every recovery code of 'spent'"));
+    }
+
+    #[test]
+    fn test_text_roundtrip() {
+        let used = RecoveryCode { code: "1234 5678".into(), used: true };
+        assert_eq!(to_text(&used).as_str(), "1234 5678 (used)");
+        assert_eq!(from_text(&to_text(&used)), Some(used));
+        assert_eq!(from_text(" abcd ").unwrap().code.expose(), "abcd");
+        assert_eq!(from_text(" "), None);
+    }
+
+    #[test]
+    fn test_note_roundtrip() {
+        let mut account = account(&["k7m2p-q9x4t", "h3w8c-r6n5v"]);
+        use_code(&mut account, None).unwrap();
+        let text = note_with_codes(&account).unwrap();
+        assert_eq!(text.as_str(), "Recovery codes:\nk7m2p-q9x4t (used)\nh3w8c-r6n5v");
+        assert_eq!(split_note(&text), (None, account.recovery_codes.clone()));
+
+        account.note = Some("primary login".to_string());
+        let text = note_with_codes(&account).unwrap();
+        assert_eq!(split_note(&text), (account.note.clone(), account.recovery_codes.clone()));
+
+        assert_eq!(split_note("no codes here"), (Some("no codes here".to_string()), Vec::new()));
+        assert_eq!(split_note(""), (None, Vec::new()));
+        account.recovery_codes.clear();
+        assert_eq!(note_with_codes(&account).as_deref().map(String::as_str), Some("primary login"));
+    }
+}
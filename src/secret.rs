@@ -0,0 +1,138 @@
+use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::Zeroize;
+
+use crate::crypto::{lock_memory, unlock_memory};
+use crate::otp::constant_time_eq;
+
+const REDACTED: &str = "<redacted>";
+
+/// Sensitive text such as a Base32 OTP secret
+///
+/// The buffer is locked from swap where possible, wiped on drop and never
+/// shown by `Debug` or `Display`. Use `expose` to read the value.
+#[derive(Default)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(value: String) -> Self {
+        lock_memory(value.as_ptr(), value.capacity());
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        self.0.zeroize();
+        unlock_memory(self.0.as_ptr(), self.0.capacity());
+    }
+}
+
+impl Clone for SecretString {
+    fn clone(&self) -> Self {
+        Self::new(self.0.clone())
+    }
+}
+
+impl PartialEq for SecretString {
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq(self.0.as_bytes(), other.0.as_bytes())
+    }
+}
+
+impl Eq for SecretString {}
+
+impl From<String> for SecretString {
+    fn from(value: String) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(value: &str) -> Self {
+        Self::new(value.to_string())
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretString({})", REDACTED)
+    }
+}
+
+impl fmt::Display for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl Serialize for SecretString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::new)
+    }
+}
+
+/// Sensitive binary data such as a decoded OTP key, with the same guarantees as `SecretString`
+pub struct SecretBytes(Vec<u8>);
+
+impl SecretBytes {
+    pub fn new(value: Vec<u8>) -> Self {
+        lock_memory(value.as_ptr(), value.capacity());
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        self.0.zeroize();
+        unlock_memory(self.0.as_ptr(), self.0.capacity());
+    }
+}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretBytes({})", REDACTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redacted_formatting() {
+        let secret = SecretString::from("JBSWY3DPEHPK3PXP");
+        assert_eq!(format!("{:?}", secret), "SecretString(<redacted>)");
+        assert_eq!(secret.to_string(), "<redacted>");
+        assert_eq!(secret.expose(), "JBSWY3DPEHPK3PXP");
+
+        let bytes = SecretBytes::new(vec![1, 2, 3]);
+        assert_eq!(format!("{:?}", bytes), "SecretBytes(<redacted>)");
+    }
+
+    #[test]
+    fn test_serde_is_transparent() {
+        let secret: SecretString = serde_json::from_str("\"JBSWY3DPEHPK3PXP\"").unwrap();
+        assert_eq!(serde_json::to_string(&secret).unwrap(), "\"JBSWY3DPEHPK3PXP\"");
+        assert_eq!(secret, SecretString::from("JBSWY3DPEHPK3PXP"));
+    }
+}
@@ -0,0 +1,677 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use crate::audit::AuditLog;
+use crate::config::{self, Account, Config};
+use crate::otp::{self, Otp, OtpType};
+use crate::qrcode::url_decode;
+use crate::reconcile::{self, Match};
+use crate::secret::SecretString;
+use crate::trash;
+use crate::verify::{self, Outcome, Policy};
+
+/// Maximum accepted size of a request head or body
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+/// Connections served at once; further ones are closed right away
+const MAX_CONNECTIONS: usize = 64;
+
+/// How long a client may stall a read or a write before its connection is dropped
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub(crate) struct ServeOptions {
+    pub socket: Option<PathBuf>,
+    pub listen: Option<SocketAddr>,
+    pub token: Option<String>,
+    pub allow_write: bool,
+}
+
+/// How a client reached the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transport {
+    Tcp,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    Unix,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+struct Response {
+    status: u16,
+    body: Value,
+}
+
+impl Response {
+    fn ok(body: Value) -> Self {
+        Self { status: 200, body }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self { status, body: json!({ "error": message }) }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            201 => "Created",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            413 => "Payload Too Large",
+            429 => "Too Many Requests",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+/// The account store backing the server, reloaded whenever the file changes
+struct Store {
+    path: PathBuf,
+    config: Config,
+    modified: Option<SystemTime>,
+}
+
+impl Store {
+    fn open(path: PathBuf) -> Result<Self> {
+        let config = Config::load_from_file(&path)?;
+        let modified = Self::modified_time(&path);
+        Ok(Self { path, config, modified })
+    }
+
+    fn modified_time(path: &PathBuf) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|meta| meta.modified()).ok()
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        let modified = Self::modified_time(&self.path);
+        if modified != self.modified {
+            self.config = self.config.reload_from_file(&self.path)?;
+            self.modified = modified;
+        }
+        Ok(())
+    }
+
+    fn save(&mut self) -> Result<()> {
+        self.config.save_to_file(&self.path)?;
+        self.modified = Self::modified_time(&self.path);
+        Ok(())
+    }
+
+    fn audit_log(&self) -> AuditLog {
+        AuditLog::beside(&self.path, &self.config.settings)
+    }
+}
+
+struct Server {
+    store: Mutex<Store>,
+    token: Option<String>,
+    allow_write: bool,
+    /// Connections being served, see `MAX_CONNECTIONS`
+    connections: AtomicUsize,
+}
+
+/// A reserved connection slot, released when dropped
+struct Slot(Arc<Server>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Server {
+    /// Reserve a connection slot, or `None` when `MAX_CONNECTIONS` are already being served
+    fn reserve(self: &Arc<Self>) -> Option<Slot> {
+        let slot = Slot(Arc::clone(self));
+        (self.connections.fetch_add(1, Ordering::SeqCst) < MAX_CONNECTIONS).then_some(slot)
+    }
+
+    fn handle(&self, request: &Request, transport: Transport) -> Response {
+        // Unix sockets are protected by file permissions, TCP needs a bearer token
+        if transport == Transport::Tcp && !self.authorized(request) {
+            return Response::error(401, "Missing or invalid bearer token");
+        }
+
+        let mut store = match self.store.lock() {
+            Ok(store) => store,
+            Err(_) => return Response::error(500, "Account store is unavailable"),
+        };
+        // Other processes writing the store take the same lock, so nothing lands between refresh and save
+        let _lock = match config::lock_store(&store.path) {
+            Ok(lock) => lock,
+            Err(e) => return Response::error(500, &format!("Failed to lock the store: {}", e)),
+        };
+        if let Err(e) = store.refresh() {
+            return Response::error(500, &format!("Failed to reload config: {}", e));
+        }
+
+        let path = request.path.split('?').next().unwrap_or_default();
+        let segments = match path.trim_matches('/')
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(url_decode)
+            .collect::<Result<Vec<_>>>()
+        {
+            Ok(segments) => segments,
+            Err(_) => return Response::error(400, "Invalid percent encoding in path"),
+        };
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["accounts"]) => Self::list_accounts(&store.config),
+            ("GET", ["accounts", name, "code"]) => Self::account_code(&store, name),
+            ("POST", ["accounts", name, "verify"]) => Self::verify_code(&mut store, name, &request.body),
+            ("POST", ["accounts"]) | ("DELETE", ["accounts", _]) if !self.allow_write => {
+                Response::error(403, "Server is read-only, restart with --allow-write")
+            }
+            ("POST", ["accounts"]) => Self::add_account(&mut store, &request.body),
+            ("DELETE", ["accounts", name]) => Self::delete_account(&mut store, name),
+            (_, ["accounts"]) | (_, ["accounts", _]) | (_, ["accounts", _, "code" | "verify"]) => {
+                Response::error(405, "Method not allowed")
+            }
+            _ => Response::error(404, "Not found"),
+        }
+    }
+
+    fn authorized(&self, request: &Request) -> bool {
+        let Some(token) = &self.token else {
+            return false;
+        };
+        request.header("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|given| otp::constant_time_eq(given.trim().as_bytes(), token.as_bytes()))
+    }
+
+    fn list_accounts(config: &Config) -> Response {
+        let accounts: Vec<Value> = config.accounts.iter()
+            .map(|acc| json!({ "name": acc.name, "issuer": acc.issuer }))
+            .collect();
+        Response::ok(json!({ "accounts": accounts }))
+    }
+
+    /// Index of the named account, which must be a TOTP one
+    fn find_totp_account(config: &Config, name: &str) -> Result<usize, Response> {
+        let index = config.accounts.iter().position(|acc| acc.name == name)
+            .ok_or_else(|| Response::error(404, "Account not found"))?;
+        if config.accounts[index].otp_type != OtpType::Totp {
+            return Err(Response::error(400, "Only TOTP accounts are served"));
+        }
+        Ok(index)
+    }
+
+    fn account_code(store: &Store, name: &str) -> Response {
+        let account = match Self::find_totp_account(&store.config, name) {
+            Ok(index) => &store.config.accounts[index],
+            Err(response) => return response,
+        };
+        if let Err(e) = store.audit_log().record_code("serve code", account, "generated") {
+            return Response::error(500, &format!("Failed to write audit log: {}", e));
+        }
+
+        let otp = match Otp::from_account(account) {
+            Ok(otp) => otp,
+            Err(e) => return Response::error(500, &e.to_string()),
+        };
+        let now = otp::unix_time();
+        let expires_in = otp.remaining_seconds(now);
+        Response::ok(json!({
+            "name": account.name,
+            "issuer": account.issuer,
+            "code": otp.generate_totp_at(now),
+            "period": otp.period(),
+            "expires_in": expires_in,
+            "expires_at": now + expires_in,
+        }))
+    }
+
+    fn verify_code(store: &mut Store, name: &str, body: &[u8]) -> Response {
+        let index = match Self::find_totp_account(&store.config, name) {
+            Ok(index) => index,
+            Err(response) => return response,
+        };
+        let body: Value = match serde_json::from_slice(body) {
+            Ok(body) => body,
+            Err(_) => return Response::error(400, "Request body must be JSON"),
+        };
+        let Some(code) = body.get("code").and_then(Value::as_str) else {
+            return Response::error(400, "Missing 'code' field");
+        };
+        // Clients may narrow the window but never widen it past what the store allows
+        let max_window = store.config.settings.serve_max_window;
+        let window = body.get("window").and_then(Value::as_u64).unwrap_or(max_window).min(max_window);
+
+        // Verification state is saved even on a read-only server, or codes could be replayed and guessed forever
+        let policy = Policy::from_settings(&store.config.settings);
+        let outcome = verify::verify(&mut store.config.accounts[index], code, otp::unix_time(), window, &policy);
+        if !matches!(outcome, Outcome::Locked { .. }) {
+            if let Err(e) = store.save() {
+                return Response::error(500, &format!("Failed to save config: {}", e));
+            }
+        }
+        let account = &store.config.accounts[index];
+        if let Err(e) = store.audit_log().record_code("serve verify", account, &outcome.to_string()) {
+            return Response::error(500, &format!("Failed to write audit log: {}", e));
+        }
+
+        match outcome {
+            Outcome::Locked { until } => Response {
+                status: 429,
+                body: json!({ "error": "Too many failed attempts", "locked_until": until }),
+            },
+            _ => Response::ok(json!({ "name": account.name, "valid": outcome.is_accepted(), "result": outcome.to_string() })),
+        }
+    }
+
+    fn add_account(store: &mut Store, body: &[u8]) -> Response {
+        let body: Value = match serde_json::from_slice(body) {
+            Ok(body) => body,
+            Err(_) => return Response::error(400, "Request body must be JSON"),
+        };
+        let (Some(name), Some(secret)) = (
+            body.get("name").and_then(Value::as_str),
+            body.get("secret").and_then(Value::as_str),
+        ) else {
+            return Response::error(400, "Missing 'name' or 'secret' field");
+        };
+
+        let account = Account::new(
+            name.to_string(),
+            SecretString::from(secret),
+            body.get("issuer").and_then(Value::as_str).unwrap_or("host").to_string(),
+        );
+        if let Err(e) = account.validate() {
+            return Response::error(400, &e.to_string());
+        }
+        match reconcile::classify(&store.config.accounts, &account) {
+            Match::New => {}
+            Match::Duplicate(_) => return Response::error(409, "An account with the same secret already exists"),
+            Match::SameIssuerAndName(_) | Match::SameName(_) => return Response::error(409, "Account already exists"),
+        }
+        store.config.accounts.push(account);
+        if let Err(e) = store.save() {
+            return Response::error(500, &format!("Failed to save config: {}", e));
+        }
+        if let Err(e) = store.audit_log().record("serve add", store.config.accounts.last(), "added") {
+            return Response::error(500, &format!("Failed to write audit log: {}", e));
+        }
+        Response { status: 201, body: json!({ "name": name }) }
+    }
+
+    fn delete_account(store: &mut Store, name: &str) -> Response {
+        let deleted_accounts: Vec<Account> = store.config.accounts.iter().filter(|acc| acc.name == name).cloned().collect();
+        let deleted = trash::delete(&mut store.config, name, otp::unix_time());
+        if deleted == 0 {
+            return Response::error(404, "Account not found");
+        }
+        if let Err(e) = store.save() {
+            return Response::error(500, &format!("Failed to save config: {}", e));
+        }
+        let log = store.audit_log();
+        for account in &deleted_accounts {
+            if let Err(e) = log.record("serve delete", Some(account), "trashed") {
+                return Response::error(500, &format!("Failed to write audit log: {}", e));
+            }
+        }
+        Response::ok(json!({ "deleted": deleted }))
+    }
+}
+
+/// Read one line of the request head, failing once the head would exceed `MAX_REQUEST_SIZE`
+fn read_head_line(reader: &mut impl BufRead, head_size: &mut usize) -> Result<String> {
+    let mut line = String::new();
+    let limit = (MAX_REQUEST_SIZE - *head_size) as u64;
+    reader.take(limit).read_line(&mut line)?;
+    *head_size += line.len();
+    if *head_size >= MAX_REQUEST_SIZE && !line.ends_with('\n') {
+        return Err(anyhow::anyhow!("Request head too large"));
+    }
+    Ok(line)
+}
+
+fn read_request(stream: &mut impl Read) -> Result<Request> {
+    let mut reader = BufReader::new(stream);
+
+    let mut head_size = 0;
+    let request_line = read_head_line(&mut reader, &mut head_size)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(anyhow::anyhow!("Malformed request line"));
+    };
+
+    let mut headers = Vec::new();
+    loop {
+        let line = read_head_line(&mut reader, &mut head_size)?;
+        if line.is_empty() {
+            break;
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            headers.push((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let mut request = Request {
+        method: method.to_uppercase(),
+        path: path.to_string(),
+        headers,
+        body: Vec::new(),
+    };
+
+    let length = request.header("Content-Length")
+        .map(|value| value.parse::<usize>())
+        .transpose()
+        .map_err(|_| anyhow::anyhow!("Invalid Content-Length"))?
+        .unwrap_or(0);
+    if length > MAX_REQUEST_SIZE {
+        return Err(anyhow::anyhow!("Request body too large"));
+    }
+    request.body.resize(length, 0);
+    reader.read_exact(&mut request.body)?;
+
+    Ok(request)
+}
+
+fn write_response(stream: &mut impl Write, response: &Response) -> Result<()> {
+    let body = response.body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.reason(),
+        body.len(),
+        body,
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Serve a connection on its own thread, unless too many are open already
+///
+/// The caller sets `CONNECTION_TIMEOUT` on the stream, so an idle client cannot hold its slot for long.
+fn spawn_connection<S: Read + Write + Send + 'static>(server: &Arc<Server>, stream: S, transport: Transport) {
+    // Dropping the stream without an answer closes it
+    let Some(slot) = server.reserve() else {
+        return;
+    };
+    let _ = thread::Builder::new().spawn(move || serve_connection(&slot.0, stream, transport));
+}
+
+fn serve_connection<S: Read + Write>(server: &Server, mut stream: S, transport: Transport) {
+    let response = match read_request(&mut stream) {
+        Ok(request) => server.handle(&request, transport),
+        Err(e) => Response::error(400, &e.to_string()),
+    };
+    // The client may already be gone, there is nobody left to report to
+    let _ = write_response(&mut stream, &response);
+}
+
+pub(crate) fn run(options: ServeOptions) -> Result<()> {
+    if options.socket.is_none() && options.listen.is_none() {
+        return Err(anyhow::anyhow!("Specify --socket <path> and/or --listen <addr>"));
+    }
+    if options.listen.is_some() && options.token.is_none() {
+        return Err(anyhow::anyhow!("--listen requires a bearer token (--token or OTPC_SERVE_TOKEN)"));
+    }
+
+    let server = Arc::new(Server {
+        store: Mutex::new(Store::open(config::prepare_path()?)?),
+        token: options.token,
+        allow_write: options.allow_write,
+        connections: AtomicUsize::new(0),
+    });
+    let mut handles = Vec::new();
+
+    if let Some(addr) = options.listen {
+        if !addr.ip().is_loopback() {
+            eprintln!("Warning: listening on non-loopback address {}", addr);
+        }
+        let listener = TcpListener::bind(addr)?;
+        println!("Listening on http://{}", listener.local_addr()?);
+
+        let server = Arc::clone(&server);
+        handles.push(thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if stream.set_read_timeout(Some(CONNECTION_TIMEOUT)).is_ok()
+                    && stream.set_write_timeout(Some(CONNECTION_TIMEOUT)).is_ok()
+                {
+                    spawn_connection(&server, stream, Transport::Tcp);
+                }
+            }
+        }));
+    }
+
+    if let Some(path) = options.socket {
+        handles.push(bind_unix_socket(Arc::clone(&server), path)?);
+    }
+
+    for handle in handles {
+        let _ = handle.join();
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn bind_unix_socket(server: Arc<Server>, path: PathBuf) -> Result<thread::JoinHandle<()>> {
+    use std::os::unix::fs::FileTypeExt as _;
+    use std::os::unix::net::UnixListener;
+
+    // Remove a stale socket left behind by a previous run, but never a regular file
+    if let Ok(meta) = fs::symlink_metadata(&path) {
+        if !meta.file_type().is_socket() {
+            return Err(anyhow::anyhow!("{} exists and is not a socket", path.display()));
+        }
+        fs::remove_file(&path)?;
+    }
+
+    // Created with the right mode rather than chmod'ed afterwards, so no client can connect in between
+    // SAFETY: umask only swaps the process file mode mask, restored right after the bind
+    let umask = unsafe { libc::umask(0o177) };
+    let bound = UnixListener::bind(&path);
+    // SAFETY: as above
+    unsafe { libc::umask(umask) };
+    let listener = bound?;
+    println!("Listening on unix:{}", path.display());
+
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if stream.set_read_timeout(Some(CONNECTION_TIMEOUT)).is_ok()
+                && stream.set_write_timeout(Some(CONNECTION_TIMEOUT)).is_ok()
+            {
+                spawn_connection(&server, stream, Transport::Unix);
+            }
+        }
+    }))
+}
+
+#[cfg(not(unix))]
+fn bind_unix_socket(_server: Arc<Server>, _path: PathBuf) -> Result<thread::JoinHandle<()>> {
+    Err(anyhow::anyhow!("Unix sockets are not supported on this platform"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_server(name: &str, allow_write: bool) -> Server {
+        // A directory per server keeps the audit logs of parallel tests apart
+        let dir = std::env::temp_dir().join(format!("otpc-serve-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let _ = fs::remove_file(dir.join("audit.log"));
+        let path = dir.join("config.toml");
+        let mut config = Config::new(vec![Account::new(
+            "alice".to_string(),
+            SecretString::from("JBSWY3DPEHPK3PXP"),
+            "Example".to_string(),
+        )]);
+        config.save_to_file(&path).unwrap();
+
+        Server {
+            store: Mutex::new(Store::open(path).unwrap()),
+            token: Some("s3cret".to_string()),
+            allow_write,
+            connections: AtomicUsize::new(0),
+        }
+    }
+
+    /// Remove the directory `test_server` created for the store and audit log
+    fn remove_server_dir(server: &Server) {
+        let dir = server.store.lock().unwrap().path.parent().unwrap().to_path_buf();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn request(method: &str, path: &str, token: Option<&str>, body: &str) -> Request {
+        let mut headers = Vec::new();
+        if let Some(token) = token {
+            headers.push(("Authorization".to_string(), format!("Bearer {}", token)));
+        }
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers,
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_read_request() {
+        let raw = b"POST /accounts/alice/verify HTTP/1.1\r\nContent-Length: 17\r\n\r\n{\"code\":\"123456\"}";
+        let request = read_request(&mut &raw[..]).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/accounts/alice/verify");
+        assert_eq!(request.body, b"{\"code\":\"123456\"}");
+
+        let endless = vec![b'a'; MAX_REQUEST_SIZE + 1];
+        assert!(read_request(&mut &endless[..]).is_err());
+        let mut long_header = b"GET /accounts HTTP/1.1\r\nX-Padding: ".to_vec();
+        long_header.resize(MAX_REQUEST_SIZE * 2, b'a');
+        assert!(read_request(&mut &long_header[..]).is_err());
+    }
+
+    #[test]
+    fn test_tcp_requires_token() {
+        let server = test_server("token", false);
+        let denied = server.handle(&request("GET", "/accounts", Some("wrong"), ""), Transport::Tcp);
+        assert_eq!(denied.status, 401);
+
+        let allowed = server.handle(&request("GET", "/accounts", Some("s3cret"), ""), Transport::Tcp);
+        assert_eq!(allowed.status, 200);
+        assert_eq!(allowed.body["accounts"][0]["name"], "alice");
+
+        let unix = server.handle(&request("GET", "/accounts", None, ""), Transport::Unix);
+        assert_eq!(unix.status, 200);
+        remove_server_dir(&server);
+    }
+
+    #[test]
+    fn test_code_and_verify() {
+        let server = test_server("code", false);
+        let response = server.handle(&request("GET", "/accounts/alice/code", None, ""), Transport::Unix);
+        assert_eq!(response.status, 200);
+        let code = response.body["code"].as_str().unwrap().to_string();
+        assert_eq!(code.len(), 6);
+        assert!(response.body["expires_in"].as_u64().unwrap() <= 30);
+
+        let body = format!("{{\"code\":\"{}\"}}", code);
+        let response = server.handle(&request("POST", "/accounts/alice/verify", None, &body), Transport::Unix);
+        assert_eq!(response.body["valid"], true);
+        let response = server.handle(&request("POST", "/accounts/alice/verify", None, &body), Transport::Unix);
+        assert_eq!((&response.body["valid"], &response.body["result"]), (&json!(false), &json!("replayed")));
+
+        // The replay was the first failure, the default policy locks the account at the fifth
+        for _ in 0..4 {
+            let response = server.handle(&request("POST", "/accounts/alice/verify", None, r#"{"code":"abc"}"#), Transport::Unix);
+            assert_eq!(response.status, 200);
+        }
+        let response = server.handle(&request("POST", "/accounts/alice/verify", None, &body), Transport::Unix);
+        assert_eq!(response.status, 429);
+        assert!(response.body["locked_until"].as_u64().is_some());
+
+        let response = server.handle(&request("GET", "/accounts/bob/code", None, ""), Transport::Unix);
+        assert_eq!(response.status, 404);
+        remove_server_dir(&server);
+    }
+
+    #[test]
+    fn test_verify_window_is_clamped() {
+        let server = test_server("window", false);
+        let old_code = {
+            let store = server.store.lock().unwrap();
+            Otp::from_account(&store.config.accounts[0]).unwrap().generate_totp_at(otp::unix_time() - 300)
+        };
+        // Ten periods back is outside the default server maximum of one, whatever the client asks for
+        let body = format!("{{\"code\":\"{}\",\"window\":1000000}}", old_code);
+        let response = server.handle(&request("POST", "/accounts/alice/verify", None, &body), Transport::Unix);
+        assert_eq!(response.body["valid"], false);
+        remove_server_dir(&server);
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        let server = Arc::new(test_server("limit", false));
+        let slots: Vec<Slot> = (0..MAX_CONNECTIONS).map(|_| server.reserve().unwrap()).collect();
+        assert!(server.reserve().is_none());
+        drop(slots);
+        assert_eq!(server.connections.load(Ordering::SeqCst), 0);
+        assert!(server.reserve().is_some());
+        remove_server_dir(&server);
+    }
+
+    #[test]
+    fn test_read_only_by_default() {
+        let server = test_server("readonly", false);
+        let response = server.handle(&request("DELETE", "/accounts/alice", None, ""), Transport::Unix);
+        assert_eq!(response.status, 403);
+        remove_server_dir(&server);
+
+        let server = test_server("readwrite", true);
+        let body = r#"{"name":"bob","secret":"GEZDGNBVGY3TQOJQ"}"#;
+        let response = server.handle(&request("POST", "/accounts", None, body), Transport::Unix);
+        assert_eq!(response.status, 201);
+        let body = r#"{"name":"carol","secret":"JBSWY3DPEHPK3PXP"}"#;
+        let response = server.handle(&request("POST", "/accounts", None, body), Transport::Unix);
+        assert_eq!(response.status, 409);
+        let response = server.handle(&request("DELETE", "/accounts/alice", None, ""), Transport::Unix);
+        assert_eq!(response.status, 200);
+
+        let store = server.store.lock().unwrap();
+        let saved = Config::load_from_file(&store.path).unwrap();
+        assert_eq!(saved.accounts.len(), 1);
+        assert_eq!(saved.accounts[0].name, "bob");
+        assert_eq!(saved.trash[0].account.name, "alice");
+
+        let commands: Vec<String> = store.audit_log().entries().unwrap().into_iter().map(|entry| entry.command).collect();
+        assert_eq!(commands, ["serve add", "serve delete"]);
+        assert_eq!(store.audit_log().verify().unwrap(), 2);
+        drop(store);
+        remove_server_dir(&server);
+    }
+}
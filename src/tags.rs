@@ -0,0 +1,113 @@
+use crate::commands::AccountFilter;
+use crate::config::Account;
+
+impl AccountFilter {
+    /// Whether the account passes every given criterion
+    pub(crate) fn matches(&self, account: &Account) -> bool {
+        let has_tags = self.tags.iter().all(|tag| account.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)));
+        let in_group = self.group.as_ref().is_none_or(|group| account.group.as_ref() == Some(group));
+        has_tags && in_group && (!self.favorites || account.favorite)
+    }
+
+    /// Matching accounts, favorites first and otherwise in stored order
+    pub(crate) fn select<'a>(&self, accounts: &'a [Account]) -> Vec<&'a Account> {
+        self.select_indices(accounts).into_iter().map(|index| &accounts[index]).collect()
+    }
+
+    /// Positions in `accounts` of the matching accounts, in the order of `select`
+    pub(crate) fn select_indices(&self, accounts: &[Account]) -> Vec<usize> {
+        let mut selected: Vec<usize> = (0..accounts.len()).filter(|&index| self.matches(&accounts[index])).collect();
+        selected.sort_by_key(|&index| !accounts[index].favorite);
+        selected
+    }
+}
+
+/// Whether the name, issuer, group or a tag contains `query`, ignoring case
+pub(crate) fn matches_query(account: &Account, query: &str) -> bool {
+    let query = query.to_lowercase();
+    let contains = |text: &str| text.to_lowercase().contains(&query);
+    contains(&account.name)
+        || contains(&account.issuer)
+        || account.group.as_deref().is_some_and(contains)
+        || account.tags.iter().any(|tag| contains(tag))
+}
+
+/// Add tags the account doesn't have yet; returns how many were added
+pub(crate) fn add_tags(account: &mut Account, tags: &[String]) -> usize {
+    let mut added = 0;
+    for tag in tags.iter().map(|tag| tag.trim()).filter(|tag| !tag.is_empty()) {
+        if !account.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+            account.tags.push(tag.to_string());
+            added += 1;
+        }
+    }
+    added
+}
+
+/// Remove tags (ignoring case); returns how many were removed
+pub(crate) fn remove_tags(account: &mut Account, tags: &[String]) -> usize {
+    let before = account.tags.len();
+    account.tags.retain(|t| !tags.iter().any(|tag| t.eq_ignore_ascii_case(tag.trim())));
+    before - account.tags.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(name: &str, tags: &[&str], group: Option<&str>, favorite: bool) -> Account {
+        let mut account = Account::new(name.to_string(), "JBSWY3DPEHPK3PXP".into(), "host".to_string());
+        account.tags = tags.iter().map(|tag| tag.to_string()).collect();
+        account.group = group.map(str::to_string);
+        account.favorite = favorite;
+        account
+    }
+
+    #[test]
+    fn test_filter_and_order() {
+        let accounts = vec![
+            account("a", &["work"], Some("Acme"), false),
+            account("b", &["work", "vpn"], None, true),
+            account("c", &[], Some("Acme"), true),
+        ];
+        let names = |filter: AccountFilter| -> Vec<String> {
+            filter.select(&accounts).iter().map(|account| account.name.clone()).collect()
+        };
+
+        assert_eq!(names(AccountFilter::default()), ["b", "c", "a"]);
+        assert_eq!(names(AccountFilter { tags: vec!["WORK".to_string()], ..Default::default() }), ["b", "a"]);
+        assert_eq!(names(AccountFilter { tags: vec!["work".to_string(), "vpn".to_string()], ..Default::default() }), ["b"]);
+        assert_eq!(names(AccountFilter { group: Some("Acme".to_string()), ..Default::default() }), ["c", "a"]);
+        assert_eq!(names(AccountFilter { favorites: true, ..Default::default() }), ["b", "c"]);
+    }
+
+    #[test]
+    fn test_select_indices() {
+        // Accounts without an ID, as in a hand-edited store, are still told apart
+        let accounts = vec![
+            account("a", &["work"], None, false),
+            account("b", &[], None, false),
+            account("c", &["work"], None, true),
+        ];
+        assert_eq!(AccountFilter::default().select_indices(&accounts), [2, 0, 1]);
+        assert_eq!(AccountFilter { tags: vec!["work".to_string()], ..Default::default() }.select_indices(&accounts), [2, 0]);
+    }
+
+    #[test]
+    fn test_matches_query() {
+        let acc = account("octocat", &["Work"], Some("GitHub"), false);
+        assert!(matches_query(&acc, "OCTO"));
+        assert!(matches_query(&acc, "hub"));
+        assert!(matches_query(&acc, "work"));
+        assert!(!matches_query(&acc, "gitlab"));
+    }
+
+    #[test]
+    fn test_add_remove_tags() {
+        let mut acc = account("a", &["work"], None, false);
+        assert_eq!(add_tags(&mut acc, &["Work".to_string(), "vpn".to_string(), " ".to_string()]), 1);
+        assert_eq!(acc.tags, ["work", "vpn"]);
+        assert_eq!(remove_tags(&mut acc, &["WORK".to_string(), "missing".to_string()]), 1);
+        assert_eq!(acc.tags, ["vpn"]);
+    }
+}
@@ -0,0 +1,116 @@
+use anyhow::Result;
+
+use crate::config::{Account, Config, TrashedAccount};
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// Move every account named `name` to the trash; returns how many were moved
+pub(crate) fn delete(config: &mut Config, name: &str, now: u64) -> usize {
+    let (deleted, kept): (Vec<Account>, Vec<Account>) = config.accounts.drain(..)
+        .partition(|account| account.name == name);
+    config.accounts = kept;
+
+    let count = deleted.len();
+    config.trash.extend(deleted.into_iter().map(|account| TrashedAccount { deleted_at: now, account }));
+    count
+}
+
+/// Put the most recently deleted account named `name` back into the store
+pub(crate) fn restore(config: &mut Config, name: &str) -> Result<Account> {
+    let index = config.trash.iter()
+        .enumerate()
+        .filter(|(_, trashed)| trashed.account.name == name)
+        .max_by_key(|(_, trashed)| trashed.deleted_at)
+        .map(|(index, _)| index)
+        .ok_or(anyhow::anyhow!("No account named '{}' in the trash", name))?;
+
+    if config.accounts.iter().any(|account| account.name == name) {
+        return Err(anyhow::anyhow!("Another account is already named '{}', rename it first", name));
+    }
+
+    let account = config.trash.remove(index).account;
+    config.accounts.push(account.clone());
+    Ok(account)
+}
+
+/// Remove trashed accounts for good, all of them or only those named `name`
+pub(crate) fn purge(config: &mut Config, name: Option<&str>) -> Vec<Account> {
+    take_trash(config, |trashed| name.is_none_or(|name| trashed.account.name == name))
+}
+
+/// Purge accounts that have been in the trash longer than the retention period
+pub(crate) fn expire(config: &mut Config, now: u64) -> Vec<Account> {
+    let retention = config.settings.trash_retention_days;
+    if retention == 0 {
+        return Vec::new();
+    }
+    take_trash(config, |trashed| now.saturating_sub(trashed.deleted_at) >= retention.saturating_mul(DAY))
+}
+
+fn take_trash(config: &mut Config, purged: impl Fn(&TrashedAccount) -> bool) -> Vec<Account> {
+    let (removed, kept): (Vec<TrashedAccount>, Vec<TrashedAccount>) = config.trash.drain(..).partition(purged);
+    config.trash = kept;
+    removed.into_iter().map(|trashed| trashed.account).collect()
+}
+
+/// Rough age of a deletion, e.g. "3 day(s) ago"
+pub(crate) fn age(deleted_at: u64, now: u64) -> String {
+    let elapsed = now.saturating_sub(deleted_at);
+    match elapsed {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{} minute(s) ago", elapsed / 60),
+        3600..DAY => format!("{} hour(s) ago", elapsed / 3600),
+        _ => format!("{} day(s) ago", elapsed / DAY),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_config() -> Config {
+        Config::new(vec![
+            Account::new("alice".to_string(), "JBSWY3DPEHPK3PXP".into(), "Example".to_string()),
+            Account::new("bob".to_string(), "GEZDGNBVGY3TQOJQ".into(), "host".to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_delete_and_restore() {
+        let mut config = sample_config();
+        assert_eq!(delete(&mut config, "alice", 100), 1);
+        assert_eq!(delete(&mut config, "carol", 100), 0);
+        assert_eq!((config.accounts.len(), config.trash.len()), (1, 1));
+
+        let restored = restore(&mut config, "alice").unwrap();
+        assert_eq!(restored.secret.expose(), "JBSWY3DPEHPK3PXP");
+        assert!(config.trash.is_empty());
+        assert!(restore(&mut config, "alice").is_err());
+
+        // Restoring over a live account with the same name is refused
+        delete(&mut config, "bob", 100);
+        config.accounts.push(Account::new("bob".to_string(), "MFRGGZDFMZTWQ2LK".into(), "host".to_string()));
+        assert!(restore(&mut config, "bob").is_err());
+        assert_eq!(config.trash.len(), 1);
+    }
+
+    #[test]
+    fn test_purge_and_expire() {
+        let mut config = sample_config();
+        delete(&mut config, "alice", 0);
+        delete(&mut config, "bob", 29 * DAY);
+
+        assert_eq!(expire(&mut config, 30 * DAY).len(), 1);
+        assert_eq!(config.trash[0].account.name, "bob");
+
+        config.settings.trash_retention_days = 0;
+        assert!(expire(&mut config, 1000 * DAY).is_empty());
+        // Effectively forever, rather than an overflow
+        config.settings.trash_retention_days = u64::MAX;
+        assert!(expire(&mut config, u64::MAX).is_empty());
+
+        assert!(purge(&mut config, Some("alice")).is_empty());
+        assert_eq!(purge(&mut config, None).len(), 1);
+        assert_eq!(age(0, 2 * DAY + 5), "2 day(s) ago");
+    }
+}
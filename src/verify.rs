@@ -0,0 +1,243 @@
+use std::fmt;
+
+use crate::audit::format_time;
+use crate::config::{Account, Settings};
+use crate::otp::{constant_time_eq, Otp, OtpType};
+
+/// Longest lockout, however many failures follow
+pub const MAX_LOCKOUT_SECONDS: u64 = 24 * 60 * 60;
+
+/// Widest window `verify` checks; larger ones are clamped to it, since each step costs an HMAC
+pub const MAX_WINDOW: u64 = 10;
+
+/// How an account reacts to repeated wrong codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Policy {
+    /// Consecutive failures allowed before the account is locked, 0 to never lock it
+    pub max_failures: u32,
+    /// First lockout; every further failure doubles it, up to `MAX_LOCKOUT_SECONDS`
+    pub lockout_seconds: u64,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::from_settings(&Settings::default())
+    }
+}
+
+impl Policy {
+    /// The policy configured in the store's `[settings]` table
+    pub(crate) fn from_settings(settings: &Settings) -> Self {
+        Self { max_failures: settings.verify_max_failures, lockout_seconds: settings.verify_lockout_seconds }
+    }
+
+    /// Lockout after the `failures`-th consecutive failure, or `None` while below the limit
+    fn lockout(&self, failures: u32) -> Option<u64> {
+        if self.max_failures == 0 || failures < self.max_failures {
+            return None;
+        }
+        let doublings = failures - self.max_failures;
+        let factor = 2u64.saturating_pow(doublings);
+        Some(self.lockout_seconds.saturating_mul(factor).min(MAX_LOCKOUT_SECONDS))
+    }
+}
+
+/// Result of checking a code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Accepted,
+    Invalid,
+    /// The code was right, but for a time step that was already used
+    Replayed,
+    /// Too many failures; the code was not even checked
+    Locked { until: u64 },
+}
+
+impl Outcome {
+    pub fn is_accepted(&self) -> bool {
+        *self == Outcome::Accepted
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Accepted => write!(f, "valid"),
+            Outcome::Invalid => write!(f, "invalid"),
+            Outcome::Replayed => write!(f, "replayed"),
+            Outcome::Locked { until } => write!(f, "locked until {}", format_time(*until)),
+        }
+    }
+}
+
+/// Check a code against an account, moving its state forward; save the account afterwards whatever the outcome
+///
+/// TOTP accepts `window` periods of clock drift on either side, but never a time step at or before
+/// `last_step`, so a code works once. HOTP looks ahead from the stored counter over the same number of
+/// candidates (`2 * window + 1`) and resynchronizes the counter past the matching value. Wrong and
+/// replayed codes count as failures; once `policy` locks the account, codes are refused unchecked.
+/// `window` is clamped to `MAX_WINDOW`.
+pub fn verify(account: &mut Account, code: &str, timestamp: u64, window: u64, policy: &Policy) -> Outcome {
+    if let Some(until) = locked_until(account, timestamp) {
+        return Outcome::Locked { until };
+    }
+
+    let outcome = check(account, code, timestamp, window.min(MAX_WINDOW));
+    record(account, outcome.is_accepted(), timestamp, policy);
+    outcome
+}
+
+/// End of the lockout `account` is under at `timestamp`, if any
+pub(crate) fn locked_until(account: &Account, timestamp: u64) -> Option<u64> {
+    account.locked_until.filter(|&until| timestamp < until)
+}
+
+/// Reset the failures after a success, or count one more and lock the account once `policy` says so
+pub(crate) fn record(account: &mut Account, accepted: bool, timestamp: u64, policy: &Policy) {
+    if accepted {
+        account.failures = 0;
+        account.locked_until = None;
+    } else {
+        account.failures = account.failures.saturating_add(1);
+        account.locked_until = policy.lockout(account.failures).map(|seconds| timestamp.saturating_add(seconds));
+    }
+}
+
+fn check(account: &mut Account, code: &str, timestamp: u64, window: u64) -> Outcome {
+    let code = code.trim();
+    if code.len() != account.digits as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return Outcome::Invalid;
+    }
+
+    // An account with impossible parameters accepts nothing
+    let Ok(otp) = Otp::from_account(account) else {
+        return Outcome::Invalid;
+    };
+    let matches = |counter: u64| constant_time_eq(otp.generate_hotp(counter).as_bytes(), code.as_bytes());
+    match account.otp_type {
+        OtpType::Totp => {
+            let current = timestamp / otp.period();
+            let step = (current.saturating_sub(window)..=current.saturating_add(window)).find(|&step| matches(step));
+            match (step, account.last_step) {
+                (None, _) => Outcome::Invalid,
+                (Some(step), Some(last)) if step <= last => Outcome::Replayed,
+                (Some(step), _) => {
+                    account.last_step = Some(step);
+                    Outcome::Accepted
+                }
+            }
+        }
+        OtpType::Hotp => {
+            let last = account.counter.saturating_add(window * 2);
+            // The last counter has no successor to move to, so its code can never be accepted
+            match (account.counter..=last).find(|&counter| matches(counter)).and_then(|counter| counter.checked_add(1)) {
+                Some(next) => {
+                    account.counter = next;
+                    Outcome::Accepted
+                }
+                None => Outcome::Invalid,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NO_LOCKOUT: Policy = Policy { max_failures: 0, lockout_seconds: 0 };
+
+    fn account(otp_type: OtpType) -> Account {
+        let mut account = Account::new("alice".to_string(), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ".into(), String::new());
+        account.otp_type = otp_type;
+        account
+    }
+
+    #[test]
+    fn test_totp_refuses_replays() {
+        let mut account = account(OtpType::Totp);
+        let otp = Otp::from_account(&account).unwrap();
+        let now = 59;
+
+        assert_eq!(verify(&mut account, &otp.generate_totp_at(now), now, 1, &NO_LOCKOUT), Outcome::Accepted);
+        assert_eq!(account.last_step, Some(1));
+        assert_eq!(verify(&mut account, &otp.generate_totp_at(now), now, 1, &NO_LOCKOUT), Outcome::Replayed);
+        // The previous step is inside the window, but older than the accepted one
+        assert_eq!(verify(&mut account, &otp.generate_totp_at(now - 30), now, 1, &NO_LOCKOUT), Outcome::Replayed);
+        assert_eq!(verify(&mut account, &otp.generate_totp_at(now + 30), now, 1, &NO_LOCKOUT), Outcome::Accepted);
+        assert_eq!(verify(&mut account, &otp.generate_totp_at(now + 90), now, 1, &NO_LOCKOUT), Outcome::Invalid);
+        assert_eq!(verify(&mut account, "12345", now, 1, &NO_LOCKOUT), Outcome::Invalid);
+    }
+
+    #[test]
+    fn test_hotp_resynchronizes() {
+        let mut account = account(OtpType::Hotp);
+        let otp = Otp::from_account(&account).unwrap();
+
+        assert!(verify(&mut account, &otp.generate_hotp(0), 0, 1, &NO_LOCKOUT).is_accepted());
+        assert_eq!(account.counter, 1);
+        assert!(!verify(&mut account, &otp.generate_hotp(0), 0, 1, &NO_LOCKOUT).is_accepted());
+        // A few button presses that never reached the server
+        assert!(verify(&mut account, &format!(" {} ", otp.generate_hotp(3)), 0, 1, &NO_LOCKOUT).is_accepted());
+        assert_eq!(account.counter, 4);
+        assert!(!verify(&mut account, &otp.generate_hotp(7), 0, 1, &NO_LOCKOUT).is_accepted());
+        assert_eq!(account.counter, 4);
+
+        account.counter = u64::MAX;
+        assert_eq!(verify(&mut account, &otp.generate_hotp(u64::MAX), 0, 1, &NO_LOCKOUT), Outcome::Invalid);
+        assert_eq!(account.counter, u64::MAX);
+    }
+
+    #[test]
+    fn test_huge_window_is_clamped() {
+        let (mut totp, mut hotp) = (account(OtpType::Totp), account(OtpType::Hotp));
+        let otp = Otp::from_account(&totp).unwrap();
+        let now = 1_000_000;
+
+        // Would scan every step since 1970 without the clamp
+        let old = otp.generate_totp_at(now - (MAX_WINDOW + 1) * 30);
+        assert_eq!(verify(&mut totp, &old, now, u64::MAX, &NO_LOCKOUT), Outcome::Invalid);
+        let edge = otp.generate_totp_at(now - MAX_WINDOW * 30);
+        assert_eq!(verify(&mut totp, &edge, now, u64::MAX, &NO_LOCKOUT), Outcome::Accepted);
+
+        let far = otp.generate_hotp(MAX_WINDOW * 2 + 1);
+        assert_eq!(verify(&mut hotp, &far, 0, u64::MAX, &NO_LOCKOUT), Outcome::Invalid);
+    }
+
+    #[test]
+    fn test_invalid_account_accepts_nothing() {
+        let mut account = account(OtpType::Totp);
+        account.period = 0;
+        assert_eq!(verify(&mut account, "123456", 1_000_000, 1, &NO_LOCKOUT), Outcome::Invalid);
+    }
+
+    #[test]
+    fn test_lockout_backs_off() {
+        let policy = Policy { max_failures: 3, lockout_seconds: 30 };
+        let mut account = account(OtpType::Totp);
+        let otp = Otp::from_account(&account).unwrap();
+        let now = 1_000_000;
+
+        for _ in 0..2 {
+            assert_eq!(verify(&mut account, "000000", now, 1, &policy), Outcome::Invalid);
+        }
+        assert_eq!(account.locked_until, None);
+        assert_eq!(verify(&mut account, "000000", now, 1, &policy), Outcome::Invalid);
+        assert_eq!(account.locked_until, Some(now + 30));
+
+        // Even the right code is refused while locked, without counting as a failure
+        let code = otp.generate_totp_at(now + 10);
+        assert_eq!(verify(&mut account, &code, now + 10, 1, &policy), Outcome::Locked { until: now + 30 });
+        assert_eq!(account.failures, 3);
+
+        // Each failure after the limit doubles the lockout
+        assert_eq!(verify(&mut account, "000000", now + 30, 1, &policy), Outcome::Invalid);
+        assert_eq!(account.locked_until, Some(now + 30 + 60));
+        let later = now + 90;
+        assert_eq!(verify(&mut account, &otp.generate_totp_at(later), later, 1, &policy), Outcome::Accepted);
+        assert_eq!((account.failures, account.locked_until), (0, None));
+
+        assert_eq!(policy.lockout(40), Some(MAX_LOCKOUT_SECONDS));
+        assert_eq!(NO_LOCKOUT.lockout(1000), None);
+    }
+}
@@ -0,0 +1,76 @@
+use std::fmt::Write as _;
+use std::io::{self, Write as _};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use zeroize::Zeroizing;
+
+use crate::config::Account;
+use crate::otp::{self, Otp, OtpType};
+
+/// Move the cursor home and clear the screen
+const CLEAR: &str = "\x1b[H\x1b[2J";
+
+/// Accounts paired with their generators, built once so redraws skip the key setup
+fn generators<'a>(accounts: &[&'a Account]) -> Result<Vec<(&'a Account, Otp)>> {
+    accounts.iter().map(|account| Ok((*account, Otp::from_account(account)?))).collect()
+}
+
+/// One line per account with the current code and the seconds it stays valid
+pub(crate) fn render(accounts: &[(&Account, Otp)], timestamp: u64) -> Zeroizing<String> {
+    let width = accounts.iter().map(|(account, _)| account.name.len()).max().unwrap_or(0);
+    let mut screen = Zeroizing::new(String::new());
+
+    for (account, otp) in accounts {
+        let star = if account.favorite { "*" } else { " " };
+        let _ = match account.otp_type {
+            OtpType::Totp => {
+                writeln!(
+                    screen, "{} {:<width$}  {}  {:>2}s  {}",
+                    star, account.name, otp.generate_totp_at(timestamp), otp.remaining_seconds(timestamp), account.issuer,
+                )
+            }
+            // Generating a HOTP code would move the counter, so leave it to `otpc code`
+            OtpType::Hotp => writeln!(screen, "{} {:<width$}  (HOTP, use `otpc code`)  {}", star, account.name, account.issuer),
+        };
+    }
+    screen
+}
+
+/// Redraw the codes every second until interrupted
+pub(crate) fn run(accounts: &[&Account]) -> Result<()> {
+    if accounts.is_empty() {
+        println!("No accounts found");
+        return Ok(());
+    }
+
+    let accounts = generators(accounts)?;
+    let mut stdout = io::stdout();
+    loop {
+        let screen = render(&accounts, otp::unix_time());
+        write!(stdout, "{}{}", CLEAR, screen.as_str())?;
+        stdout.flush()?;
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        // RFC 6238 SHA1 secret "12345678901234567890"
+        let mut totp = Account::new("alice".to_string(), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ".into(), "Example".to_string());
+        totp.digits = 8;
+        totp.favorite = true;
+        let mut hotp = Account::new("bob".to_string(), "JBSWY3DPEHPK3PXP".into(), "host".to_string());
+        hotp.otp_type = OtpType::Hotp;
+
+        let screen = render(&generators(&[&totp, &hotp]).unwrap(), 59);
+        let lines: Vec<&str> = screen.lines().collect();
+        assert_eq!(lines[0], "* alice  94287082   1s  Example");
+        assert!(lines[1].starts_with("  bob    (HOTP"));
+    }
+}